@@ -0,0 +1,546 @@
+/// Zip and tar.gz archives: packing a directory for download, listing what
+/// an archive holds and pulling single entries out of it
+use crate::fs::{allowed_path, format_permissions};
+use crate::sandbox::{self, Op};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+/// Entries returned by one `fs_archive_list`
+pub const MAX_LIST_ENTRIES: usize = 10_000;
+
+fn default_max_size() -> u64 { 2 * 1024 * 1024 * 1024 }
+fn default_max_entries() -> usize { 100_000 }
+
+/// `[archives]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    /// Most file data packed into one archive, and largest entry extracted
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+    /// Most files and directories packed into one archive
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self { max_size: default_max_size(), max_entries: default_max_entries() }
+    }
+}
+
+static CONFIG: std::sync::OnceLock<ArchiveConfig> = std::sync::OnceLock::new();
+
+fn config() -> &'static ArchiveConfig {
+    CONFIG.get_or_init(ArchiveConfig::default)
+}
+
+pub fn init(cfg: ArchiveConfig) {
+    let _ = CONFIG.set(cfg);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Zip,
+    TarGz,
+    Tar,
+}
+
+impl Format {
+    /// Formats `fs_archive` can write
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "zip" => Ok(Format::Zip),
+            "tar.gz" | "tgz" => Ok(Format::TarGz),
+            _ => Err(format!("unsupported archive format: {} (zip or tar.gz)", s)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+            Format::Tar => "tar",
+        }
+    }
+
+    /// Tell an existing archive's format from its first bytes
+    fn detect(path: &Path) -> Result<Self, String> {
+        let mut head = Vec::with_capacity(512);
+        File::open(path)
+            .and_then(|f| f.take(512).read_to_end(&mut head))
+            .map_err(|e| format!("read error: {}", e))?;
+        match crate::mime::by_magic(&head) {
+            Some("application/zip") => Ok(Format::Zip),
+            Some("application/gzip") => Ok(Format::TarGz),
+            Some("application/x-tar") => Ok(Format::Tar),
+            _ => Err(format!("not a zip, tar or tar.gz archive: {}", path.display())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, `/`-separated; directories end in `/` in zips
+    pub name: String,
+    #[serde(rename = "type")]
+    pub file_type: &'static str, // "file", "dir", "symlink"
+    pub size: u64,
+    /// Zip only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    pub modified: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Listing {
+    pub format: &'static str,
+    pub entries: Vec<ArchiveEntry>,
+    /// Stopped at MAX_LIST_ENTRIES
+    pub truncated: bool,
+}
+
+/// An archive written to a scratch file
+pub struct Packed {
+    pub source: PathBuf,
+    pub file: PathBuf,
+    /// File name to offer the client, e.g. `src.zip`
+    pub name: String,
+    pub entries: usize,
+    /// File data packed, before compression
+    pub size: u64,
+}
+
+/// What goes in an archive of `root`: every entry under it the policy
+/// allows, without following links, checked against the limits up front
+fn collect(root: &Path) -> Result<(Vec<(PathBuf, fs::Metadata)>, u64), String> {
+    let cfg = config();
+    let mut entries = Vec::new();
+    let mut total = 0u64;
+    let walker = ignore::WalkBuilder::new(root)
+        .standard_filters(false)
+        .filter_entry(|e| sandbox::readable_resolved(e.path()))
+        .build();
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("cannot read {}: {}", root.display(), e))?;
+        let meta = fs::symlink_metadata(entry.path()).map_err(|e| format!("cannot read {}: {}", entry.path().display(), e))?;
+        if meta.is_file() {
+            total += meta.len();
+        } else if !meta.is_dir() && !meta.file_type().is_symlink() {
+            continue; // sockets, fifos, devices
+        }
+        if total > cfg.max_size {
+            return Err(format!("{} is too large to archive (max {} bytes)", root.display(), cfg.max_size));
+        }
+        if entries.len() >= cfg.max_entries {
+            return Err(format!("{} has too many entries to archive (max {})", root.display(), cfg.max_entries));
+        }
+        entries.push((entry.into_path(), meta));
+    }
+    Ok((entries, total))
+}
+
+fn zip_time(secs: i64) -> zip::DateTime {
+    use chrono::{Datelike, Timelike};
+    chrono::DateTime::from_timestamp(secs, 0)
+        .and_then(|t| zip::DateTime::from_date_and_time(
+            t.year() as u16, t.month() as u8, t.day() as u8, t.hour() as u8, t.minute() as u8, t.second() as u8,
+        ).ok())
+        .unwrap_or_default()
+}
+
+fn write_zip(out: File, base: &Path, entries: &[(PathBuf, fs::Metadata)]) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(BufWriter::new(out));
+    for (path, meta) in entries {
+        let name = path.strip_prefix(base).unwrap_or(path).to_string_lossy().to_string();
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(zip_time(meta.mtime()))
+            .unix_permissions(meta.mode() & 0o7777)
+            .large_file(meta.len() >= u32::MAX as u64);
+        if meta.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if meta.file_type().is_symlink() {
+            zip.add_symlink(name, fs::read_link(path)?.to_string_lossy(), options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.flush()
+}
+
+fn write_tar_gz(out: File, base: &Path, entries: &[(PathBuf, fs::Metadata)]) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(BufWriter::new(out), flate2::Compression::default()));
+    tar.follow_symlinks(false);
+    for (path, _) in entries {
+        let name = path.strip_prefix(base).unwrap_or(path);
+        tar.append_path_with_name(path, name)?;
+    }
+    tar.into_inner()?.finish()?.flush()
+}
+
+/// Pack `path` (a directory, or a single file) into a scratch file for
+/// download. Entries are named from the directory itself down, so the
+/// archive unpacks into one folder.
+pub fn pack(path: &str, format: Format) -> Result<Packed, String> {
+    let root = allowed_path(path, Op::Read)?;
+    let (entries, size) = collect(&root)?;
+    let base = root.parent().unwrap_or(Path::new("/"));
+    let stem = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "root".into());
+    let (file, out) = crate::transfer::scratch_file()?;
+    let written = match format {
+        Format::Zip => write_zip(out, base, &entries).map_err(|e| e.to_string()),
+        _ => write_tar_gz(out, base, &entries).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = written {
+        let _ = fs::remove_file(&file);
+        return Err(format!("cannot archive {}: {}", root.display(), e));
+    }
+    Ok(Packed {
+        source: root,
+        file,
+        name: format!("{}.{}", stem, format.name()),
+        entries: entries.len(),
+        size,
+    })
+}
+
+fn open_archive(path: &str) -> Result<(PathBuf, Format), String> {
+    let p = allowed_path(path, Op::Read)?;
+    if !p.is_file() {
+        return Err(format!("not a regular file: {}", p.display()));
+    }
+    let format = Format::detect(&p)?;
+    Ok((p, format))
+}
+
+fn open_zip(p: &Path) -> Result<zip::ZipArchive<BufReader<File>>, String> {
+    let f = File::open(p).map_err(|e| format!("read error: {}", e))?;
+    zip::ZipArchive::new(BufReader::new(f)).map_err(|e| format!("cannot read {}: {}", p.display(), e))
+}
+
+fn open_tar(p: &Path, format: Format) -> Result<tar::Archive<Box<dyn Read>>, String> {
+    let f = BufReader::new(File::open(p).map_err(|e| format!("read error: {}", e))?);
+    let reader: Box<dyn Read> = match format {
+        Format::TarGz => Box::new(GzDecoder::new(f)),
+        _ => Box::new(f),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+fn zip_entry(f: &zip::read::ZipFile<'_>) -> ArchiveEntry {
+    use chrono::NaiveDate;
+    let modified = f.last_modified()
+        .and_then(|t| {
+            NaiveDate::from_ymd_opt(t.year() as i32, t.month() as u32, t.day() as u32)?
+                .and_hms_opt(t.hour() as u32, t.minute() as u32, t.second() as u32)
+        })
+        .map(|t| t.and_utc().timestamp().max(0) as u64)
+        .unwrap_or(0);
+    ArchiveEntry {
+        name: f.name().to_string(),
+        file_type: if f.is_dir() { "dir" } else if f.is_symlink() { "symlink" } else { "file" },
+        size: f.size(),
+        compressed_size: Some(f.compressed_size()),
+        modified,
+        permissions: f.unix_mode().map(format_permissions),
+        // The target is the entry's content; reading it here would mean decompressing
+        link_target: None,
+    }
+}
+
+fn tar_entry<R: Read>(e: &tar::Entry<'_, R>) -> Option<ArchiveEntry> {
+    let header = e.header();
+    let file_type = match header.entry_type() {
+        tar::EntryType::Directory => "dir",
+        tar::EntryType::Symlink | tar::EntryType::Link => "symlink",
+        t if t.is_file() => "file",
+        _ => return None, // pax/GNU headers, devices
+    };
+    Some(ArchiveEntry {
+        name: e.path().ok()?.to_string_lossy().to_string(),
+        file_type,
+        size: header.size().unwrap_or(0),
+        compressed_size: None,
+        modified: header.mtime().unwrap_or(0),
+        permissions: header.mode().ok().map(format_permissions),
+        link_target: e.link_name().ok().flatten().map(|l| l.to_string_lossy().to_string()),
+    })
+}
+
+/// Entries of a zip, tar or tar.gz file, without extracting anything
+pub fn list(path: &str) -> Result<Listing, String> {
+    let (p, format) = open_archive(path)?;
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    if format == Format::Zip {
+        let mut zip = open_zip(&p)?;
+        truncated = zip.len() > MAX_LIST_ENTRIES;
+        for i in 0..zip.len().min(MAX_LIST_ENTRIES) {
+            let f = zip.by_index_raw(i).map_err(|e| format!("cannot read {}: {}", p.display(), e))?;
+            entries.push(zip_entry(&f));
+        }
+    } else {
+        let mut tar = open_tar(&p, format)?;
+        let iter = tar.entries().map_err(|e| format!("cannot read {}: {}", p.display(), e))?;
+        for e in iter {
+            let e = e.map_err(|e| format!("cannot read {}: {}", p.display(), e))?;
+            if entries.len() >= MAX_LIST_ENTRIES {
+                truncated = true;
+                break;
+            }
+            entries.extend(tar_entry(&e));
+        }
+    }
+    Ok(Listing { format: format.name(), entries, truncated })
+}
+
+/// Copy one entry's data to `out`, refusing more than the configured limit
+fn copy_limited(mut from: impl Read, out: &mut File, name: &str, max: u64) -> Result<u64, String> {
+    let n = io::copy(&mut (&mut from).take(max + 1), out).map_err(|e| format!("cannot extract {}: {}", name, e))?;
+    if n > max {
+        return Err(format!("{} is too large to extract (max {} bytes)", name, max));
+    }
+    Ok(n)
+}
+
+/// Find `entry` in the archive and write its data to `out`
+fn extract_to(p: &Path, format: Format, entry: &str, out: &mut File) -> Result<u64, String> {
+    let missing = || format!("no entry {} in {}", entry, p.display());
+    if format == Format::Zip {
+        let mut zip = open_zip(p)?;
+        let f = zip.by_name(entry).map_err(|_| missing())?;
+        if !f.is_file() {
+            return Err(format!("{} is not a file", entry));
+        }
+        return copy_limited(f, out, entry, config().max_size);
+    }
+    let mut tar = open_tar(p, format)?;
+    let iter = tar.entries().map_err(|e| format!("cannot read {}: {}", p.display(), e))?;
+    for e in iter {
+        let e = e.map_err(|e| format!("cannot read {}: {}", p.display(), e))?;
+        if e.path().ok().is_none_or(|n| n.to_string_lossy() != entry) {
+            continue;
+        }
+        if !e.header().entry_type().is_file() {
+            return Err(format!("{} is not a file", entry));
+        }
+        return copy_limited(e, out, entry, config().max_size);
+    }
+    Err(missing())
+}
+
+fn entry_name(entry: &str) -> String {
+    entry.trim_end_matches('/').rsplit('/').next().unwrap_or(entry).to_string()
+}
+
+/// Pull one file out of an archive into a scratch file for download;
+/// returns the archive path, the scratch file and the entry's file name
+pub fn extract_for_download(path: &str, entry: &str) -> Result<(PathBuf, PathBuf, String), String> {
+    let (p, format) = open_archive(path)?;
+    let (file, mut out) = crate::transfer::scratch_file()?;
+    if let Err(e) = extract_to(&p, format, entry, &mut out) {
+        let _ = fs::remove_file(&file);
+        return Err(e);
+    }
+    Ok((p, file, entry_name(entry)))
+}
+
+/// Pull one file out of an archive into `dest` on the server. Written next
+/// to `dest` first and renamed into place, so a failure leaves nothing behind.
+pub fn extract_to_path(path: &str, entry: &str, dest: &str, overwrite: bool) -> Result<(String, u64), String> {
+    let (p, format) = open_archive(path)?;
+    let name = entry_name(entry);
+    if matches!(name.as_str(), "" | "." | "..") {
+        return Err(format!("invalid entry name: {}", entry));
+    }
+    let dest = allowed_path(dest, Op::Write)?;
+    let dest = if dest.is_dir() { sandbox::check(&dest.join(&name), Op::Write)? } else { dest };
+    if !overwrite && fs::symlink_metadata(&dest).is_ok() {
+        return Err(format!("{} already exists (pass overwrite: true to replace it)", dest.display()));
+    }
+    let dir = dest.parent().ok_or_else(|| format!("invalid destination: {}", dest.display()))?;
+    let tmp = dir.join(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4()));
+    let mut out = File::create(&tmp).map_err(|e| format!("cannot create {}: {}", tmp.display(), e))?;
+    let written = extract_to(&p, format, entry, &mut out).and_then(|n| {
+        fs::rename(&tmp, &dest).map_err(|e| format!("cannot write {}: {}", dest.display(), e))?;
+        Ok(n)
+    });
+    match written {
+        Ok(n) => Ok((dest.to_string_lossy().to_string(), n)),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// `proj/` with a file, a subdirectory, a link and a denied `.env`
+    fn project(dir: &Path) -> PathBuf {
+        let root = dir.join("proj");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "alpha").unwrap();
+        fs::write(root.join("sub/b.txt"), "beta").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        symlink("../a.txt", root.join("sub/link")).unwrap();
+        root
+    }
+
+    fn s(p: &Path) -> &str {
+        p.to_str().unwrap()
+    }
+
+    #[test]
+    fn pack_list_extract_round_trip() {
+        for (format, dir_suffix) in [(Format::Zip, "/"), (Format::TarGz, "")] {
+            let dir = scratch();
+            let root = project(&dir);
+            let packed = pack(s(&root), format).unwrap();
+            assert_eq!(packed.name, format!("proj.{}", format.name()));
+            assert_eq!(packed.entries, 5, "{:?}", format);
+            assert_eq!(packed.size, 9, "{:?}: only regular file data counts", format);
+
+            let listing = list(s(&packed.file)).unwrap();
+            assert_eq!(listing.format, format.name());
+            assert!(!listing.truncated);
+            let mut names: Vec<_> = listing.entries.iter().map(|e| (e.name.trim_end_matches('/').to_string(), e.file_type)).collect();
+            names.sort();
+            assert_eq!(names, vec![
+                ("proj".to_string(), "dir"),
+                ("proj/a.txt".to_string(), "file"),
+                ("proj/sub".to_string(), "dir"),
+                ("proj/sub/b.txt".to_string(), "file"),
+                ("proj/sub/link".to_string(), "symlink"),
+            ], "{:?}", format);
+            let sub = listing.entries.iter().find(|e| e.name.starts_with("proj/sub") && e.file_type == "dir").unwrap();
+            assert_eq!(sub.name, format!("proj/sub{}", dir_suffix));
+
+            let out = dir.join("out");
+            fs::create_dir(&out).unwrap();
+            let (written, n) = extract_to_path(s(&packed.file), "proj/sub/b.txt", s(&out), false).unwrap();
+            assert_eq!((written.as_str(), n), (s(&out.join("b.txt")), 4));
+            assert_eq!(fs::read_to_string(out.join("b.txt")).unwrap(), "beta");
+            let err = extract_to_path(s(&packed.file), "proj/sub/b.txt", s(&out), false).unwrap_err();
+            assert!(err.contains("already exists"), "{}", err);
+            extract_to_path(s(&packed.file), "proj/a.txt", s(&out.join("b.txt")), true).unwrap();
+            assert_eq!(fs::read_to_string(out.join("b.txt")).unwrap(), "alpha");
+
+            let err = extract_to_path(s(&packed.file), &format!("proj/sub{}", dir_suffix), s(&out), true).unwrap_err();
+            assert!(err.contains("not a file"), "{}", err);
+            let err = extract_to_path(s(&packed.file), "proj/c.txt", s(&out), true).unwrap_err();
+            assert!(err.contains("no entry"), "{}", err);
+
+            let (_, file, name) = extract_for_download(s(&packed.file), "proj/a.txt").unwrap();
+            assert_eq!(name, "a.txt");
+            assert_eq!(fs::read_to_string(&file).unwrap(), "alpha");
+            let entries: Vec<_> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name()).collect();
+            assert_eq!(entries, vec!["b.txt"], "no temp files left behind");
+
+            fs::remove_file(file).unwrap();
+            fs::remove_file(packed.file).unwrap();
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn collect_skips_denied_and_keeps_links() {
+        let dir = scratch();
+        let root = project(&dir);
+        fs::create_dir(root.join(".ssh")).unwrap();
+        fs::write(root.join(".ssh/id_ed25519"), "key").unwrap();
+        // A link out to a directory with more files must not be walked into
+        fs::create_dir(dir.join("outside")).unwrap();
+        fs::write(dir.join("outside/big"), "x".repeat(100)).unwrap();
+        symlink(dir.join("outside"), root.join("out")).unwrap();
+
+        let (entries, total) = collect(&root).unwrap();
+        let mut names: Vec<_> = entries.iter()
+            .map(|(p, m)| (p.strip_prefix(&dir).unwrap().to_string_lossy().to_string(), m.file_type().is_symlink()))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![
+            ("proj".to_string(), false),
+            ("proj/a.txt".to_string(), false),
+            ("proj/out".to_string(), true),
+            ("proj/sub".to_string(), false),
+            ("proj/sub/b.txt".to_string(), false),
+            ("proj/sub/link".to_string(), true),
+        ]);
+        assert_eq!(total, 9);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_limited_refuses_oversized_entries() {
+        let dir = scratch();
+        let mut out = File::create(dir.join("out")).unwrap();
+        assert_eq!(copy_limited(&b"0123456789"[..], &mut out, "e", 10).unwrap(), 10);
+        let err = copy_limited(&b"0123456789"[..], &mut out, "e", 9).unwrap_err();
+        assert!(err.contains("too large"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_stays_inside_dest() {
+        let names = ["../x", "a/../../y", "../../../../tmp/z"];
+        let dir = scratch();
+
+        let zip_path = dir.join("evil.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in names.iter().chain(&[".."]) {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        // tar::Builder refuses `..` in paths, so write the raw header names
+        let tar_path = dir.join("evil.tar.gz");
+        let mut tar = tar::Builder::new(GzEncoder::new(File::create(&tar_path).unwrap(), flate2::Compression::default()));
+        for name in names.iter().chain(&[".."]) {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(name.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, name.as_bytes()).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        for archive in [&zip_path, &tar_path] {
+            let dest = dir.join("a/b");
+            fs::create_dir_all(&dest).unwrap();
+            for name in names {
+                let (written, _) = extract_to_path(s(archive), name, s(&dest), false).unwrap();
+                assert_eq!(Path::new(&written).parent(), Some(dest.as_path()), "{} from {}", name, archive.display());
+                assert_eq!(fs::read_to_string(&written).unwrap(), name);
+            }
+            let err = extract_to_path(s(archive), "..", s(&dest), true).unwrap_err();
+            assert!(err.contains("invalid entry name"), "{}", err);
+            assert!(!dir.join("a/x").exists() && !dir.join("x").exists() && !dir.join("y").exists());
+            fs::remove_dir_all(dir.join("a")).unwrap();
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
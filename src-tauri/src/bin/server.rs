@@ -2,8 +2,14 @@ use tmux_mobile::{config::Config, server};
 
 #[tokio::main]
 async fn main() {
-    let cfg = Config::load();
-    if let Err(e) = server::start_with_socket(&cfg.host, cfg.port, &cfg.token, cfg.tmux_socket).await {
+    let cfg = match Config::load() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("❌ Config error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = server::start_with_config(cfg).await {
         eprintln!("❌ Server error: {}", e);
         std::process::exit(1);
     }
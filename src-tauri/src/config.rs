@@ -1,3 +1,12 @@
+use crate::archive::ArchiveConfig;
+use crate::exec::ExecConfig;
+use crate::logs::LogConfig;
+use crate::sandbox::FsPolicy;
+use crate::trash::TrashConfig;
+use crate::thumbnail::ThumbnailConfig;
+use crate::transfer::TransferConfig;
+use crate::triggers::TriggerSpec;
+use crate::watchers::WatchSpec;
 use serde::Deserialize;
 use serde_json;
 use std::path::PathBuf;
@@ -8,6 +17,25 @@ struct FileConfig {
     port: Option<u16>,
     token: Option<String>,
     tmux_socket: Option<String>,
+    #[serde(default)]
+    triggers: Vec<TriggerSpec>,
+    #[serde(default)]
+    watchers: Vec<WatchSpec>,
+    recordings_dir: Option<String>,
+    #[serde(default)]
+    logs: LogConfig,
+    #[serde(default)]
+    exec: ExecConfig,
+    #[serde(default)]
+    fs: FsPolicy,
+    #[serde(default)]
+    trash: TrashConfig,
+    #[serde(default)]
+    transfers: TransferConfig,
+    #[serde(default)]
+    thumbnails: ThumbnailConfig,
+    #[serde(default)]
+    archives: ArchiveConfig,
 }
 
 pub struct Config {
@@ -15,13 +43,24 @@ pub struct Config {
     pub port: u16,
     pub token: String,
     pub tmux_socket: Option<String>,
+    pub triggers: Vec<TriggerSpec>,
+    pub watchers: Vec<WatchSpec>,
+    pub recordings_dir: PathBuf,
+    pub logs: LogConfig,
+    pub exec: ExecConfig,
+    pub fs: FsPolicy,
+    pub trash: TrashConfig,
+    pub transfers: TransferConfig,
+    pub thumbnails: ThumbnailConfig,
+    pub archives: ArchiveConfig,
 }
 
 fn config_path() -> PathBuf {
-    dirs_next().join("config.toml")
+    config_dir().join("config.toml")
 }
 
-fn dirs_next() -> PathBuf {
+/// `~/.config/tmux-mobile`, also the default home for server-managed data
+pub fn config_dir() -> PathBuf {
     if let Some(home) = std::env::var_os("HOME") {
         PathBuf::from(home).join(".config").join("tmux-mobile")
     } else {
@@ -31,11 +70,16 @@ fn dirs_next() -> PathBuf {
 
 impl Config {
     /// Load config: file < env vars. Auto-generates token if missing everywhere.
-    pub fn load() -> Self {
-        let file_cfg = std::fs::read_to_string(config_path())
-            .ok()
-            .and_then(|s| toml::from_str::<FileConfig>(&s).ok())
-            .unwrap_or_default();
+    /// A config file that exists but can't be read or parsed is an error
+    /// rather than falling back to defaults, which would widen the fs policy.
+    pub fn load() -> Result<Self, String> {
+        let path = config_path();
+        let file_cfg = match std::fs::read_to_string(&path) {
+            Ok(s) => toml::from_str::<FileConfig>(&s)
+                .map_err(|e| format!("invalid {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileConfig::default(),
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
 
         let token = std::env::var("TOKEN")
             .ok()
@@ -47,17 +91,36 @@ impl Config {
                 t
             });
 
-        Config {
+        Ok(Config {
             host: std::env::var("HOST").ok().or(file_cfg.host).unwrap_or("0.0.0.0".into()),
             port: std::env::var("PORT").ok().and_then(|p| p.parse().ok()).or(file_cfg.port).unwrap_or(9899),
             token,
             tmux_socket: std::env::var("TMUX_SOCKET").ok().or(file_cfg.tmux_socket),
-        }
+            triggers: file_cfg.triggers,
+            watchers: file_cfg.watchers,
+            recordings_dir: file_cfg.recordings_dir.map(|d| expand_home(&d))
+                .unwrap_or_else(|| config_dir().join("recordings")),
+            logs: file_cfg.logs,
+            exec: file_cfg.exec,
+            fs: file_cfg.fs,
+            trash: file_cfg.trash,
+            transfers: file_cfg.transfers,
+            thumbnails: file_cfg.thumbnails,
+            archives: file_cfg.archives,
+        })
+    }
+}
+
+/// Expand a leading `~` in paths from the config file
+pub fn expand_home(p: &str) -> PathBuf {
+    match (p.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(p),
     }
 }
 
 fn save_token(token: &str) -> std::io::Result<()> {
-    let dir = dirs_next();
+    let dir = config_dir();
     std::fs::create_dir_all(&dir)?;
     let path = dir.join("config.toml");
     // Read existing or start fresh
@@ -73,12 +136,12 @@ fn save_token(token: &str) -> std::io::Result<()> {
 }
 
 /// Tauri command: return config for frontend auto-fill
-pub fn get_config_json() -> serde_json::Value {
-    let cfg = Config::load();
-    serde_json::json!({
+pub fn get_config_json() -> Result<serde_json::Value, String> {
+    let cfg = Config::load()?;
+    Ok(serde_json::json!({
         "host": cfg.host,
         "port": cfg.port,
         "token": cfg.token,
         "tmux_socket": cfg.tmux_socket,
-    })
+    }))
 }
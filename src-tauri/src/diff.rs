@@ -0,0 +1,478 @@
+/// Structured unified diffs, shared by the git methods and file comparison
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+const MAX_INTRALINE_CHARS: usize = 1000;
+
+type Ranges = Vec<(usize, usize)>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    /// `context`, `add` or `del`
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_line: Option<usize>,
+    pub text: String,
+    /// Changed `[start, end)` char ranges within `text`, for a `del`/`add`
+    /// line paired with its counterpart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Ranges>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    /// Text after the closing `@@` (usually the enclosing function)
+    pub header: String,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// `added`, `deleted`, `modified`, `renamed` or `copied`
+    pub status: &'static str,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileDiff {
+    fn new(path: String) -> Self {
+        Self { path, old_path: None, status: "modified", binary: false, additions: 0, deletions: 0, hunks: Vec::new() }
+    }
+}
+
+/// Undo git's C-style quoting of unusual paths (`"a/caf\303\251"`)
+fn unquote(s: &str) -> String {
+    let Some(inner) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+        return s.to_string();
+    };
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut it = inner.bytes().peekable();
+    while let Some(b) = it.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match it.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(d @ b'0'..=b'7') => {
+                let mut v = (d - b'0') as u32;
+                for _ in 0..2 {
+                    match it.peek() {
+                        Some(&o @ b'0'..=b'7') => {
+                            v = v * 8 + (o - b'0') as u32;
+                            it.next();
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(v as u8);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Path from a `---`/`+++` line; None for /dev/null
+fn header_path(s: &str) -> Option<String> {
+    let p = unquote(s.split('\t').next().unwrap_or(s));
+    if p == "/dev/null" {
+        return None;
+    }
+    Some(p.strip_prefix("a/").or_else(|| p.strip_prefix("b/")).unwrap_or(&p).to_string())
+}
+
+/// Path from `diff --git a/X b/X` when there are no `---`/`+++` lines
+/// (binary or mode-only changes); both sides are equal in that case
+fn git_header_path(rest: &str) -> String {
+    if rest.starts_with('"') {
+        return header_path(rest.split("\" ").next().map(|s| format!("{}\"", s)).as_deref().unwrap_or(rest)).unwrap_or_default();
+    }
+    let n = rest.len().saturating_sub(5) / 2;
+    rest.get(2..2 + n).unwrap_or(rest).to_string()
+}
+
+/// Parse `@@ -a,b +c,d @@ header`
+fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, header) = rest.split_once(" @@").unwrap_or((rest, ""));
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |r: &str| -> Option<(usize, usize)> {
+        match r.split_once(',') {
+            Some((s, n)) => Some((s.parse().ok()?, n.parse().ok()?)),
+            None => Some((r.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_lines) = range(old)?;
+    let (new_start, new_lines) = range(new)?;
+    Some(Hunk { old_start, old_lines, new_start, new_lines, header: header.trim().to_string(), lines: Vec::new() })
+}
+
+/// Parse `git diff`/`git show` output into per-file diffs
+pub fn parse_git(text: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    // Lines still expected in the current hunk (old side, new side)
+    let (mut old_left, mut new_left) = (0usize, 0usize);
+    let (mut old_no, mut new_no) = (0usize, 0usize);
+
+    for line in text.lines() {
+        if old_left > 0 || new_left > 0 {
+            let Some(file) = files.last_mut() else { continue };
+            let Some(hunk) = file.hunks.last_mut() else { continue };
+            // An empty line is a context line whose leading space was stripped
+            let kind = line.chars().next().unwrap_or(' ');
+            let text = line.get(1..).unwrap_or("").to_string();
+            match kind {
+                '+' => {
+                    hunk.lines.push(DiffLine { kind: "add", old_line: None, new_line: Some(new_no), text, changes: None });
+                    file.additions += 1;
+                    new_no += 1;
+                    new_left = new_left.saturating_sub(1);
+                }
+                '-' => {
+                    hunk.lines.push(DiffLine { kind: "del", old_line: Some(old_no), new_line: None, text, changes: None });
+                    file.deletions += 1;
+                    old_no += 1;
+                    old_left = old_left.saturating_sub(1);
+                }
+                '\\' => {} // "\ No newline at end of file"
+                _ => {
+                    hunk.lines.push(DiffLine { kind: "context", old_line: Some(old_no), new_line: Some(new_no), text, changes: None });
+                    old_no += 1;
+                    new_no += 1;
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                }
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            files.push(FileDiff::new(git_header_path(rest)));
+            continue;
+        }
+        let Some(file) = files.last_mut() else { continue };
+        if line.starts_with("new file mode") {
+            file.status = "added";
+        } else if line.starts_with("deleted file mode") {
+            file.status = "deleted";
+        } else if let Some(p) = line.strip_prefix("rename from ").or_else(|| line.strip_prefix("copy from ")) {
+            file.old_path = Some(unquote(p));
+        } else if let Some(p) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            file.status = if line.starts_with("rename") { "renamed" } else { "copied" };
+            file.path = unquote(p);
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            file.binary = true;
+        } else if let Some(p) = line.strip_prefix("+++ ") {
+            if let Some(p) = header_path(p) {
+                file.path = p;
+            }
+        } else if let Some(p) = line.strip_prefix("--- ") {
+            if let Some(p) = header_path(p) {
+                if file.status == "modified" && p != file.path {
+                    file.old_path = Some(p);
+                }
+            }
+        } else if let Some(hunk) = parse_hunk_header(line) {
+            (old_left, new_left) = (hunk.old_lines, hunk.new_lines);
+            (old_no, new_no) = (hunk.old_start, hunk.new_start);
+            file.hunks.push(hunk);
+        }
+    }
+    for hunk in files.iter_mut().flat_map(|f| f.hunks.iter_mut()) {
+        mark_intraline(&mut hunk.lines);
+    }
+    files
+}
+
+/// Char ranges that differ between two versions of a line, or None when
+/// they have too little in common for highlighting to help
+fn intraline(old: &str, new: &str) -> Option<(Ranges, Ranges)> {
+    if old.chars().count() > MAX_INTRALINE_CHARS || new.chars().count() > MAX_INTRALINE_CHARS {
+        return None;
+    }
+    let diff = TextDiff::from_chars(old, new);
+    if diff.ratio() < 0.5 {
+        return None;
+    }
+    let (mut del, mut add) = (Vec::new(), Vec::new());
+    let (mut o, mut n) = (0, 0);
+    let push = |ranges: &mut Ranges, at: usize| match ranges.last_mut() {
+        Some(last) if last.1 == at => last.1 += 1,
+        _ => ranges.push((at, at + 1)),
+    };
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                o += 1;
+                n += 1;
+            }
+            ChangeTag::Delete => {
+                push(&mut del, o);
+                o += 1;
+            }
+            ChangeTag::Insert => {
+                push(&mut add, n);
+                n += 1;
+            }
+        }
+    }
+    Some((del, add))
+}
+
+/// Pair each run of deleted lines with the added lines right after it and
+/// fill in their intraline changes
+fn mark_intraline(lines: &mut [DiffLine]) {
+    let mut i = 0;
+    while i < lines.len() {
+        let dels = lines[i..].iter().take_while(|l| l.kind == "del").count();
+        let adds = lines[i + dels..].iter().take_while(|l| l.kind == "add").count();
+        for k in 0..dels.min(adds) {
+            let (old, new) = (i + k, i + dels + k);
+            if let Some((del, add)) = intraline(&lines[old].text, &lines[new].text) {
+                lines[old].changes = Some(del);
+                lines[new].changes = Some(add);
+            }
+        }
+        i += (dels + adds).max(1);
+    }
+}
+
+/// Line diff of two texts as unified-diff hunks with `context` lines around changes
+pub fn diff_text(old: &str, new: &str, context: usize) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut hunks = Vec::new();
+    for group in diff.grouped_ops(context) {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else { continue };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        // Like diff(1), an empty side points at the line before it
+        let start = |r: &std::ops::Range<usize>| if r.is_empty() { r.start } else { r.start + 1 };
+        let mut lines = Vec::new();
+        for op in &group {
+            for change in diff.iter_changes(op) {
+                let text = change.value().strip_suffix('\n').unwrap_or(change.value());
+                let text = text.strip_suffix('\r').unwrap_or(text).to_string();
+                let (old_line, new_line) = (change.old_index().map(|i| i + 1), change.new_index().map(|i| i + 1));
+                let kind = match change.tag() {
+                    ChangeTag::Equal => "context",
+                    ChangeTag::Delete => "del",
+                    ChangeTag::Insert => "add",
+                };
+                lines.push(DiffLine { kind, old_line, new_line, text, changes: None });
+            }
+        }
+        mark_intraline(&mut lines);
+        hunks.push(Hunk {
+            old_start: start(&old_range),
+            old_lines: old_range.len(),
+            new_start: start(&new_range),
+            new_lines: new_range.len(),
+            header: String::new(),
+            lines,
+        });
+    }
+    hunks
+}
+
+/// Compare two texts as one file's diff; identical texts give no hunks
+pub fn diff_file(path: String, old_path: Option<String>, old: &str, new: &str, context: usize) -> FileDiff {
+    let hunks = diff_text(old, new, context);
+    let count = |kind: &str| hunks.iter().flat_map(|h| &h.lines).filter(|l| l.kind == kind).count();
+    FileDiff {
+        additions: count("add"),
+        deletions: count("del"),
+        path,
+        old_path,
+        status: "modified",
+        binary: false,
+        hunks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(hunk: &Hunk) -> Vec<&'static str> {
+        hunk.lines.iter().map(|l| l.kind).collect()
+    }
+
+    #[test]
+    fn parse_modified() {
+        let text = "\
+diff --git a/src/main.rs b/src/main.rs
+index 1111111..2222222 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,4 +1,4 @@ fn main() {
+ one
+
+-two
++TWO
+ three
+@@ -10 +10,2 @@
+ ten
++eleven
+\\ No newline at end of file
+";
+        let files = parse_git(text);
+        assert_eq!(files.len(), 1);
+        let f = &files[0];
+        assert_eq!((f.path.as_str(), f.old_path.as_deref(), f.status), ("src/main.rs", None, "modified"));
+        assert_eq!((f.additions, f.deletions, f.binary), (2, 1, false));
+        assert_eq!(f.hunks.len(), 2);
+
+        let h = &f.hunks[0];
+        assert_eq!((h.old_start, h.old_lines, h.new_start, h.new_lines), (1, 4, 1, 4));
+        assert_eq!(h.header, "fn main() {");
+        // The empty line is a context line with its leading space stripped
+        assert_eq!(kinds(h), ["context", "context", "del", "add", "context"]);
+        assert_eq!(h.lines[1].text, "");
+        assert_eq!((h.lines[2].old_line, h.lines[2].new_line), (Some(3), None));
+        assert_eq!((h.lines[3].old_line, h.lines[3].new_line), (None, Some(3)));
+        assert_eq!((h.lines[4].old_line, h.lines[4].new_line), (Some(4), Some(4)));
+
+        // A one-line range has no count; the "\ No newline" marker is dropped
+        let h = &f.hunks[1];
+        assert_eq!((h.old_start, h.old_lines, h.new_start, h.new_lines), (10, 1, 10, 2));
+        assert_eq!(kinds(h), ["context", "add"]);
+        assert_eq!(h.lines[1].new_line, Some(11));
+    }
+
+    #[test]
+    fn parse_file_status() {
+        let text = "\
+diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++hello
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+index 3333333..0000000
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+diff --git a/old name.rs b/new name.rs
+similarity index 90%
+rename from old name.rs
+rename to new name.rs
+index 4444444..5555555 100644
+--- a/old name.rs
++++ b/new name.rs
+@@ -1 +1 @@
+-let a = 1;
++let a = 2;
+diff --git a/copy.rs b/copy2.rs
+similarity index 100%
+copy from copy.rs
+copy to copy2.rs
+diff --git a/img.png b/img.png
+index 6666666..7777777 100644
+Binary files a/img.png and b/img.png differ
+diff --git \"a/caf\\303\\251.txt\" \"b/caf\\303\\251.txt\"
+old mode 100644
+new mode 100755
+";
+        let files = parse_git(text);
+        let summary: Vec<_> = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.old_path.as_deref(), f.status, f.binary, f.additions, f.deletions))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("new.txt", None, "added", false, 1, 0),
+                ("gone.txt", None, "deleted", false, 0, 1),
+                ("new name.rs", Some("old name.rs"), "renamed", false, 1, 1),
+                ("copy2.rs", Some("copy.rs"), "copied", false, 0, 0),
+                ("img.png", None, "modified", true, 0, 0),
+                ("café.txt", None, "modified", false, 0, 0),
+            ]
+        );
+        let h = &files[0].hunks[0];
+        assert_eq!((h.old_start, h.old_lines, h.new_start, h.new_lines), (0, 0, 1, 1));
+        assert_eq!(h.lines[0].new_line, Some(1));
+    }
+
+    #[test]
+    fn parse_quoted_paths() {
+        assert_eq!(unquote("\"a/caf\\303\\251\""), "a/café");
+        assert_eq!(unquote("\"tab\\there \\\"q\\\"\""), "tab\there \"q\"");
+        assert_eq!(unquote("plain"), "plain");
+        assert_eq!(header_path("\"b/sp ace\"\t"), Some("sp ace".to_string()));
+        assert_eq!(header_path("/dev/null"), None);
+        assert_eq!(git_header_path("a/x y b/x y"), "x y");
+    }
+
+    #[test]
+    fn intraline_ranges() {
+        type Case = (&'static str, &'static str, Option<(Ranges, Ranges)>);
+        let cases: &[Case] = &[
+            ("let a = 1;", "let a = 2;", Some((vec![(8, 9)], vec![(8, 9)]))),
+            ("foo(bar)", "foo(bar, baz)", Some((vec![], vec![(7, 12)]))),
+            ("abcdef", "abef", Some((vec![(2, 4)], vec![]))),
+            // Ranges count chars, not bytes
+            ("café au lait", "cafe au lait", Some((vec![(3, 4)], vec![(3, 4)]))),
+            ("same", "same", Some((vec![], vec![]))),
+            // Too little in common to be worth highlighting
+            ("completely", "different!", None),
+            ("", "new", None),
+        ];
+        for (old, new, want) in cases {
+            assert_eq!(intraline(old, new), *want, "{:?} -> {:?}", old, new);
+        }
+        let long = "x".repeat(MAX_INTRALINE_CHARS + 1);
+        assert_eq!(intraline(&long, &format!("{}y", long)), None, "over the length limit");
+        // The limit counts characters, not bytes
+        let cjk = "字".repeat(MAX_INTRALINE_CHARS - 1);
+        assert!(intraline(&cjk, &format!("{}文", cjk)).is_some(), "within the limit in chars");
+    }
+
+    #[test]
+    fn intraline_pairs_runs() {
+        let line = |kind, text: &str| DiffLine { kind, old_line: None, new_line: None, text: text.to_string(), changes: None };
+        let mut lines = vec![
+            line("context", "x"),
+            line("del", "alpha = 1"),
+            line("del", "beta = 2"),
+            line("del", "gamma = 3"),
+            line("add", "alpha = 10"),
+            line("add", "beta = 20"),
+            line("context", "y"),
+            line("add", "lonely"),
+        ];
+        mark_intraline(&mut lines);
+        let changes: Vec<_> = lines.iter().map(|l| l.changes.clone()).collect();
+        assert_eq!(
+            changes,
+            [
+                None,
+                Some(vec![]),
+                Some(vec![]),
+                None, // no add left to pair with
+                Some(vec![(9, 10)]),
+                Some(vec![(8, 9)]),
+                None,
+                None,
+            ]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+/// Disk usage of a directory broken down by child, and free space on the
+/// filesystem holding a path
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path};
+use std::time::{Duration, Instant};
+
+const DEFAULT_BUDGET: Duration = Duration::from_secs(10);
+const MAX_BUDGET: Duration = Duration::from_secs(60);
+const DEFAULT_CHILDREN: usize = 100;
+const MAX_CHILDREN: usize = 5000;
+
+/// `fs_du` params
+#[derive(Debug, Deserialize)]
+pub struct DuRequest {
+    pub path: String,
+    /// Time the scan may take before it reports what it has so far
+    pub timeout_ms: Option<u64>,
+    /// Largest children to return; the totals always count all of them
+    pub max_children: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Usage {
+    /// Apparent size, the sum of file lengths
+    pub size: u64,
+    /// Space actually allocated on disk
+    pub disk: u64,
+    pub files: u64,
+    pub dirs: u64,
+}
+
+impl Usage {
+    fn add(&mut self, meta: &std::fs::Metadata) {
+        self.size += meta.len();
+        self.disk += meta.blocks() * 512;
+        if meta.is_dir() {
+            self.dirs += 1;
+        } else {
+            self.files += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChildUsage {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: &'static str,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskUsage {
+    pub path: String,
+    #[serde(flatten)]
+    pub total: Usage,
+    /// Largest first, by space on disk
+    pub children: Vec<ChildUsage>,
+    /// Children left out past `max_children`
+    pub omitted: usize,
+    /// False when the time budget ran out first
+    pub complete: bool,
+    pub elapsed_ms: u64,
+}
+
+/// Walk everything under `path`, adding each entry to the child of `path`
+/// it lives in. Hard links are counted once, links and mount points are not
+/// followed, and what the file access policy denies is left out. Blocking.
+pub fn du(req: DuRequest) -> Result<DiskUsage, String> {
+    let root = sandbox::check(&expand_path(&req.path), Op::Read)?;
+    let started = Instant::now();
+    let budget = req.timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_BUDGET).min(MAX_BUDGET);
+    let walker = ignore::WalkBuilder::new(&root)
+        .standard_filters(false)
+        .same_file_system(true)
+        .filter_entry(|e| sandbox::readable_resolved(e.path()))
+        .build();
+    let mut total = Usage::default();
+    let mut children: HashMap<String, ChildUsage> = HashMap::new();
+    let mut linked = HashSet::new();
+    let mut complete = true;
+    for entry in walker.flatten() {
+        if started.elapsed() > budget {
+            complete = false;
+            break;
+        }
+        let Ok(meta) = entry.metadata() else { continue };
+        if meta.nlink() > 1 && !meta.is_dir() && !linked.insert((meta.dev(), meta.ino())) {
+            continue;
+        }
+        total.add(&meta);
+        let rel = entry.path().strip_prefix(&root).unwrap_or(Path::new(""));
+        let Some(Component::Normal(name)) = rel.components().next() else { continue };
+        let name = name.to_string_lossy().to_string();
+        let child = children.entry(name.clone()).or_insert_with(|| ChildUsage {
+            path: root.join(&name).to_string_lossy().to_string(),
+            name,
+            // The first entry seen for a child is the child itself
+            file_type: if entry.path_is_symlink() { "symlink" } else if meta.is_dir() { "dir" } else { "file" },
+            usage: Usage::default(),
+        });
+        child.usage.add(&meta);
+    }
+    let mut children: Vec<ChildUsage> = children.into_values().collect();
+    children.sort_by(|a, b| b.usage.disk.cmp(&a.usage.disk).then_with(|| a.name.cmp(&b.name)));
+    let keep = req.max_children.unwrap_or(DEFAULT_CHILDREN).clamp(1, MAX_CHILDREN);
+    let omitted = children.len().saturating_sub(keep);
+    children.truncate(keep);
+    Ok(DiskUsage {
+        path: root.to_string_lossy().to_string(),
+        total,
+        children,
+        omitted,
+        complete,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FreeSpace {
+    pub path: String,
+    /// Where the filesystem holding `path` is mounted
+    pub mount_point: String,
+    pub total: u64,
+    pub used: u64,
+    /// Free to anyone, including space reserved for root
+    pub free: u64,
+    /// Free to unprivileged users
+    pub available: u64,
+    pub inodes_total: u64,
+    pub inodes_free: u64,
+}
+
+/// Free space on the filesystem holding `path`
+// statvfs field types differ between platforms (u32 on some, u64 here)
+#[allow(clippy::useless_conversion)]
+pub fn df(path: &str) -> Result<FreeSpace, String> {
+    let p = sandbox::check(&expand_path(path), Op::Read)?;
+    let c_path = CString::new(p.as_os_str().as_bytes()).map_err(|_| format!("invalid path: {}", p.display()))?;
+    let mut st = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path is NUL-terminated and st is only read after statvfs fills it in
+    if unsafe { libc::statvfs(c_path.as_ptr(), st.as_mut_ptr()) } != 0 {
+        return Err(format!("statvfs error: {}", std::io::Error::last_os_error()));
+    }
+    let st = unsafe { st.assume_init() };
+    let unit = u64::from(st.f_frsize);
+    Ok(FreeSpace {
+        path: p.to_string_lossy().to_string(),
+        mount_point: mount_point(&p).to_string_lossy().to_string(),
+        total: u64::from(st.f_blocks) * unit,
+        used: u64::from(st.f_blocks).saturating_sub(u64::from(st.f_bfree)) * unit,
+        free: u64::from(st.f_bfree) * unit,
+        available: u64::from(st.f_bavail) * unit,
+        inodes_total: u64::from(st.f_files),
+        inodes_free: u64::from(st.f_ffree),
+    })
+}
+
+/// The highest ancestor of `path` on the same device
+fn mount_point(path: &Path) -> &Path {
+    let dev = |p: &Path| std::fs::metadata(p).map(|m| m.dev()).ok();
+    let Some(own) = dev(path) else { return path };
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        if dev(parent) != Some(own) {
+            break;
+        }
+        top = parent;
+    }
+    top
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn request(path: &Path) -> DuRequest {
+        DuRequest { path: path.to_string_lossy().into_owned(), timeout_ms: None, max_children: None }
+    }
+
+    #[test]
+    fn usage_is_attributed_to_children() {
+        let dir = scratch();
+        fs::create_dir_all(dir.join("a/deep")).unwrap();
+        fs::write(dir.join("a/x"), vec![0u8; 100]).unwrap();
+        fs::write(dir.join("a/deep/y"), vec![0u8; 50]).unwrap();
+        fs::write(dir.join("b"), vec![0u8; 30]).unwrap();
+        fs::write(dir.join(".env"), "SECRET=1").unwrap();
+        std::os::unix::fs::symlink(dir.join("a"), dir.join("link")).unwrap();
+
+        let du = du(request(&dir)).unwrap();
+        assert!(du.complete);
+        let names: Vec<_> = du.children.iter().map(|c| (c.name.as_str(), c.file_type)).collect();
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert!(names.contains(&("link", "symlink")) && !names.iter().any(|n| n.0 == ".env"), "{:?}", names);
+        let child = |name: &str| du.children.iter().find(|c| c.name == name).unwrap();
+        let a = &child("a").usage;
+        assert_eq!((a.files, a.dirs), (2, 2));
+        assert!(a.size >= 150, "{:?}", a);
+        let b = &child("b").usage;
+        assert_eq!((b.size, b.files, b.dirs), (30, 1, 0));
+        assert_eq!(child("link").usage.files, 1, "the link itself, not what it points to");
+        assert_eq!((du.total.files, du.total.dirs), (4, 3));
+        assert_eq!(du.children[0].name, "a", "largest on disk first");
+
+        let one = super::du(DuRequest { max_children: Some(1), ..request(&dir) }).unwrap();
+        assert_eq!((one.children.len(), one.omitted), (1, 2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hard_links_count_once() {
+        let dir = scratch();
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/data"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(dir.join("a/data"), dir.join("b/same")).unwrap();
+        fs::hard_link(dir.join("a/data"), dir.join("a/again")).unwrap();
+        let du = du(request(&dir)).unwrap();
+        assert_eq!(du.total.files, 1);
+        let files: u64 = du.children.iter().map(|c| c.usage.files).sum();
+        assert_eq!(files, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn out_of_time_reports_partial_results() {
+        let dir = scratch();
+        fs::write(dir.join("f"), "x").unwrap();
+        let du = du(DuRequest { timeout_ms: Some(0), ..request(&dir) }).unwrap();
+        assert!(!du.complete);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn free_space_of_the_temp_dir() {
+        let dir = scratch();
+        let free = df(&dir.to_string_lossy()).unwrap();
+        assert!(free.total > 0 && free.available <= free.free && free.free <= free.total, "{:?}", free);
+        assert_eq!(free.used + free.free, free.total);
+        assert!(dir.starts_with(&free.mount_point), "{:?}", free);
+        assert_eq!(mount_point(Path::new("/")), Path::new("/"));
+        assert!(df(&dir.join("missing").to_string_lossy()).unwrap_err().starts_with("statvfs error"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
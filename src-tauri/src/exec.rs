@@ -0,0 +1,404 @@
+/// Out-of-band process execution (`exec`), independent of tmux panes.
+/// Off unless `[exec] enabled = true` in `config.toml`.
+use crate::pipe;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+
+fn default_max_timeout_secs() -> u64 { 600 }
+fn default_timeout_secs() -> u64 { 60 }
+fn default_max_output_bytes() -> usize { 1024 * 1024 }
+fn default_max_running() -> usize { 4 }
+
+/// `[exec]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Used when a request has no `timeout`
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_max_timeout_secs")]
+    pub max_timeout_secs: u64,
+    /// stdout + stderr per process; the process is killed past this
+    #[serde(default = "default_max_output_bytes")]
+    pub max_output_bytes: usize,
+    /// Processes running at once, server-wide
+    #[serde(default = "default_max_running")]
+    pub max_running: usize,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_secs: default_timeout_secs(),
+            max_timeout_secs: default_max_timeout_secs(),
+            max_output_bytes: default_max_output_bytes(),
+            max_running: default_max_running(),
+        }
+    }
+}
+
+static CONFIG: OnceLock<ExecConfig> = OnceLock::new();
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init(cfg: ExecConfig) {
+    let _ = CONFIG.set(cfg);
+}
+
+fn config() -> ExecConfig {
+    CONFIG.get().cloned().unwrap_or_default()
+}
+
+pub fn enabled() -> bool {
+    config().enabled
+}
+
+/// `exec` params
+#[derive(Debug, Deserialize)]
+pub struct ExecRequest {
+    /// Run through `sh -c`
+    pub command: Option<String>,
+    /// Run directly, without a shell
+    pub argv: Option<Vec<String>>,
+    pub cwd: Option<String>,
+    /// Added to the server's environment
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub timeout: Option<f64>,
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExitInfo {
+    pub id: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// `exit`, `timeout`, `killed` or `output_limit`
+    pub reason: &'static str,
+    pub duration_ms: u64,
+    pub output_bytes: usize,
+    pub truncated: bool,
+}
+
+// Holds one of the `max_running` slots until dropped
+struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct Process {
+    pub id: String,
+    pub pid: Option<u32>,
+    child: Child,
+    timeout: Duration,
+    max_output: usize,
+    started: Instant,
+    _slot: Slot,
+}
+
+/// Validate a request against the config and start the process
+pub fn spawn(req: ExecRequest) -> Result<Process, String> {
+    let cfg = config();
+    let mut cmd = match (req.command, req.argv) {
+        (Some(c), None) => {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(c);
+            cmd
+        }
+        (None, Some(argv)) if !argv.is_empty() => {
+            let mut cmd = Command::new(&argv[0]);
+            cmd.args(&argv[1..]);
+            cmd
+        }
+        _ => return Err("exactly one of command or argv (non-empty) is required".into()),
+    };
+    let timeout = req.timeout.unwrap_or(cfg.timeout_secs as f64);
+    if !timeout.is_finite() || timeout <= 0.0 || timeout > cfg.max_timeout_secs as f64 {
+        return Err(format!("timeout must be between 0 and {} seconds", cfg.max_timeout_secs));
+    }
+    if let Some(cwd) = req.cwd {
+        let dir = crate::config::expand_home(&cwd);
+        if !dir.is_dir() {
+            return Err(format!("not a directory: {}", dir.display()));
+        }
+        cmd.current_dir(dir);
+    }
+    // In a process group of its own, so killing it takes pipelines and
+    // background jobs along
+    cmd.envs(req.env)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    if RUNNING.fetch_add(1, Ordering::SeqCst) >= cfg.max_running {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        return Err(format!("too many running processes (max {})", cfg.max_running));
+    }
+    let slot = Slot;
+    let child = cmd.spawn().map_err(|e| format!("cannot start process: {}", e))?;
+    Ok(Process {
+        id: uuid::Uuid::new_v4().to_string()[..8].to_string(),
+        pid: child.id(),
+        child,
+        timeout: Duration::from_secs_f64(timeout),
+        max_output: req.max_output_bytes.unwrap_or(cfg.max_output_bytes).min(cfg.max_output_bytes),
+        started: Instant::now(),
+        _slot: slot,
+    })
+}
+
+/// SIGKILL the child's whole process group. `id()` is None once the child
+/// has been waited for, so its pid (the group id) can't have been reused.
+fn kill_group(child: &Child) {
+    if let Some(pid) = child.id() {
+        unsafe { libc::killpg(pid as i32, libc::SIGKILL) };
+    }
+}
+
+impl Drop for Process {
+    // kill_on_drop only reaches the shell, not what it started
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            kill_group(&self.child);
+        }
+    }
+}
+
+async fn read_stream<R: AsyncRead + Unpin>(mut r: R, name: &'static str, tx: mpsc::Sender<(&'static str, Vec<u8>)>) {
+    let mut buf = vec![0u8; 8192];
+    while let Ok(n) = r.read(&mut buf).await {
+        if n == 0 || tx.send((name, buf[..n].to_vec())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Stream output as `exec_output` and finish with `exec_exit` (method, params)
+/// pairs on `out`. Resolving `kill` terminates the process.
+pub async fn run(mut p: Process, mut kill: oneshot::Receiver<()>, out: mpsc::UnboundedSender<(&'static str, serde_json::Value)>) {
+    let (tx, mut rx) = mpsc::channel(16);
+    let readers = [
+        p.child.stdout.take().map(|s| tokio::spawn(read_stream(s, "stdout", tx.clone()))),
+        p.child.stderr.take().map(|s| tokio::spawn(read_stream(s, "stderr", tx.clone()))),
+    ];
+    drop(tx);
+
+    let deadline = tokio::time::sleep(p.timeout);
+    tokio::pin!(deadline);
+    let mut pending: HashMap<&'static str, Vec<u8>> = HashMap::new();
+    let mut total = 0;
+    let mut truncated = false;
+    let mut reason = None;
+    let emit = |stream: &str, data: String| {
+        let _ = out.send(("exec_output", serde_json::json!({ "id": p.id, "stream": stream, "data": data })));
+    };
+
+    // Output until both pipes close, then the exit status
+    let status = loop {
+        tokio::select! {
+            chunk = rx.recv(), if reason.is_none() => match chunk {
+                Some((stream, mut data)) => {
+                    let room = p.max_output - total;
+                    if data.len() > room {
+                        data.truncate(room);
+                        truncated = true;
+                        reason = Some("output_limit");
+                        kill_group(&p.child);
+                    }
+                    total += data.len();
+                    let buf = pending.entry(stream).or_default();
+                    buf.extend_from_slice(&data);
+                    let text = pipe::take_utf8(buf);
+                    if !text.is_empty() {
+                        emit(stream, text);
+                    }
+                }
+                None => reason = Some("exit"),
+            },
+            status = p.child.wait(), if reason.is_some() => break status,
+            _ = &mut deadline, if reason.is_none() || reason == Some("exit") => {
+                reason = Some("timeout");
+                kill_group(&p.child);
+            }
+            _ = &mut kill, if reason.is_none() || reason == Some("exit") => {
+                reason = Some("killed");
+                kill_group(&p.child);
+            }
+        }
+    };
+    for reader in readers.into_iter().flatten() {
+        reader.abort();
+    }
+    for (stream, rest) in pending {
+        if !rest.is_empty() {
+            emit(stream, String::from_utf8_lossy(&rest).into_owned());
+        }
+    }
+
+    let (exit_code, signal) = match &status {
+        Ok(s) => (s.code(), s.signal()),
+        Err(_) => (None, None),
+    };
+    let info = ExitInfo {
+        id: p.id.clone(),
+        exit_code,
+        signal,
+        reason: reason.unwrap_or("exit"),
+        duration_ms: p.started.elapsed().as_millis() as u64,
+        output_bytes: total,
+        truncated,
+    };
+    let _ = out.send(("exec_exit", serde_json::to_value(info).unwrap()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() {
+        // Tests run in parallel; don't let the default limit get in the way
+        init(ExecConfig { max_running: 64, ..ExecConfig::default() });
+    }
+
+    fn shell(command: &str) -> ExecRequest {
+        ExecRequest {
+            command: Some(command.into()),
+            argv: None,
+            cwd: None,
+            env: HashMap::new(),
+            timeout: None,
+            max_output_bytes: None,
+        }
+    }
+
+    /// stdout, stderr and the exec_exit params
+    async fn finish(p: Process, kill: oneshot::Receiver<()>) -> (String, String, serde_json::Value) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        run(p, kill, tx).await;
+        let (mut stdout, mut stderr, mut exit) = (String::new(), String::new(), serde_json::Value::Null);
+        while let Ok((method, params)) = rx.try_recv() {
+            match (method, params["stream"].as_str()) {
+                ("exec_output", Some("stdout")) => stdout += params["data"].as_str().unwrap(),
+                ("exec_output", _) => stderr += params["data"].as_str().unwrap(),
+                _ => exit = params,
+            }
+        }
+        (stdout, stderr, exit)
+    }
+
+    /// Whether anything but a zombie is left in process group `pgid`
+    fn group_running(pgid: u32) -> bool {
+        std::fs::read_dir("/proc").unwrap().flatten().any(|e| {
+            let Ok(stat) = std::fs::read_to_string(e.path().join("stat")) else { return false };
+            // pid (comm) state ppid pgrp ...
+            let fields: Vec<&str> = stat.rsplit_once(')').map(|(_, rest)| rest.split_whitespace().collect()).unwrap_or_default();
+            fields.first() != Some(&"Z") && fields.get(2) == Some(&pgid.to_string().as_str())
+        })
+    }
+
+    async fn group_gone(pgid: u32) -> bool {
+        for _ in 0..40 {
+            if !group_running(pgid) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn cwd_env_and_exit_code() {
+        setup();
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut req = shell("pwd; echo \"$GREETING\"; echo oops >&2; exit 3");
+        req.cwd = Some(dir.to_string_lossy().into_owned());
+        req.env.insert("GREETING".into(), "hello there".into());
+        let (_kill, kill_rx) = oneshot::channel();
+        let (stdout, stderr, exit) = finish(spawn(req).unwrap(), kill_rx).await;
+        assert_eq!(stdout, format!("{}\nhello there\n", dir.canonicalize().unwrap().display()));
+        assert_eq!(stderr, "oops\n");
+        assert_eq!((exit["reason"].as_str(), exit["exit_code"].as_i64(), exit["truncated"].as_bool()), (Some("exit"), Some(3), Some(false)));
+        assert_eq!(exit["output_bytes"].as_u64(), Some((stdout.len() + stderr.len()) as u64));
+
+        let argv = ExecRequest { command: None, argv: Some(vec!["printf".into(), "%s|".into(), "a b".into(), "$HOME".into()]), ..shell("") };
+        let (_kill, kill_rx) = oneshot::channel();
+        assert_eq!(finish(spawn(argv).unwrap(), kill_rx).await.0, "a b|$HOME|", "no shell expansion");
+
+        let mut missing = shell("true");
+        missing.cwd = Some(dir.join("nope").to_string_lossy().into_owned());
+        assert!(spawn(missing).err().unwrap().starts_with("not a directory"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_the_group() {
+        setup();
+        let mut req = shell("sleep 30 | cat & sleep 30");
+        req.timeout = Some(0.3);
+        let p = spawn(req).unwrap();
+        let pgid = p.pid.unwrap();
+        let (_kill, kill_rx) = oneshot::channel();
+        let (_, _, exit) = finish(p, kill_rx).await;
+        assert_eq!((exit["reason"].as_str(), exit["signal"].as_i64()), (Some("timeout"), Some(libc::SIGKILL as i64)));
+        assert!(exit["duration_ms"].as_u64().unwrap() < 5000);
+        assert!(group_gone(pgid).await, "pipeline and background job left running");
+    }
+
+    #[tokio::test]
+    async fn kill_stops_the_group() {
+        setup();
+        let p = spawn(shell("sleep 30 & echo started; wait")).unwrap();
+        let pgid = p.pid.unwrap();
+        let (kill, kill_rx) = oneshot::channel();
+        let killer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let _ = kill.send(());
+        });
+        let (stdout, _, exit) = finish(p, kill_rx).await;
+        killer.await.unwrap();
+        assert_eq!(stdout, "started\n");
+        assert_eq!(exit["reason"].as_str(), Some("killed"));
+        assert!(group_gone(pgid).await, "background job left running");
+    }
+
+    #[tokio::test]
+    async fn output_limit_truncates_and_kills() {
+        setup();
+        let mut req = shell("yes | cat");
+        req.max_output_bytes = Some(1000);
+        let p = spawn(req).unwrap();
+        let pgid = p.pid.unwrap();
+        let (_kill, kill_rx) = oneshot::channel();
+        let (stdout, _, exit) = finish(p, kill_rx).await;
+        assert_eq!(stdout.len(), 1000);
+        assert!(stdout.starts_with("y\ny\n"));
+        assert_eq!((exit["reason"].as_str(), exit["truncated"].as_bool(), exit["output_bytes"].as_u64()), (Some("output_limit"), Some(true), Some(1000)));
+        assert!(group_gone(pgid).await, "pipeline left running");
+    }
+
+    #[tokio::test]
+    async fn dropping_the_process_kills_the_group() {
+        setup();
+        let p = spawn(shell("sleep 30 | cat")).unwrap();
+        let pgid = p.pid.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(group_running(pgid));
+        drop(p);
+        assert!(group_gone(pgid).await);
+    }
+}
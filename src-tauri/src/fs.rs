@@ -1,12 +1,20 @@
 /// File system operations for remote file browsing
-use serde::Serialize;
+use crate::sandbox::{self, Op};
+use crate::text::Encoding;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::time::UNIX_EPOCH;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const MAX_READ_SIZE: u64 = 50 * 1024 * 1024; // 50MB
 const MAX_PREVIEW_SIZE: u64 = 512 * 1024; // 512KB for text preview
+const MAX_DIFF_SIZE: u64 = 2 * 1024 * 1024; // per side
+const MAX_SNAPSHOT_BYTES: usize = 32 * 1024 * 1024; // all snapshots together
+const MAX_CACHED: usize = 4096; // per content cache
 
 #[derive(Debug, Clone, Serialize)]
 pub struct FileEntry {
@@ -18,6 +26,16 @@ pub struct FileEntry {
     pub modified: u64, // unix timestamp
     pub permissions: String,
     pub hidden: bool,
+    /// Symlinks: what the link points to, as stored in it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    /// Symlinks: `file` or `dir` for what the link resolves to; None when
+    /// it is broken. Size and times are then the target's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_type: Option<String>,
+    /// `data:` URL of a small preview, for images when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,11 +50,23 @@ pub struct FileStat {
     pub readable: bool,
     pub writable: bool,
     pub is_text: bool,
+    /// Detected from the content when it has a signature, else from the name
     pub mime_hint: String,
+    /// Text files only: encoding, `lf`/`crlf`/`cr`/`mixed`/`none`, and
+    /// line count (the last two up to 16MB)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_ending: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_count: Option<usize>,
+    /// Changes whenever the content does; pass to `fs_write` as `if_match`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
 }
 
-fn resolve_path(p: &str) -> PathBuf {
-    let expanded = if p.starts_with('~') {
+pub(crate) fn expand_path(p: &str) -> PathBuf {
+    if p.starts_with('~') {
         if let Some(home) = dirs::home_dir() {
             home.join(&p[1..].trim_start_matches('/'))
         } else {
@@ -44,12 +74,21 @@ fn resolve_path(p: &str) -> PathBuf {
         }
     } else {
         PathBuf::from(p)
-    };
+    }
+}
+
+pub(crate) fn resolve_path(p: &str) -> PathBuf {
+    let expanded = expand_path(p);
     // Canonicalize if exists, otherwise return as-is
     expanded.canonicalize().unwrap_or(expanded)
 }
 
-fn format_permissions(mode: u32) -> String {
+/// Resolve `p` and apply the sandbox policy for `op`
+pub(crate) fn allowed_path(p: &str, op: Op) -> Result<PathBuf, String> {
+    sandbox::check(&expand_path(p), op)
+}
+
+pub(crate) fn format_permissions(mode: u32) -> String {
     let mut s = String::with_capacity(10);
     let types = [(0o400, 'r'), (0o200, 'w'), (0o100, 'x'),
                  (0o040, 'r'), (0o020, 'w'), (0o010, 'x'),
@@ -60,57 +99,6 @@ fn format_permissions(mode: u32) -> String {
     s
 }
 
-fn mime_hint(name: &str) -> String {
-    let ext = Path::new(name).extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-    match ext.as_str() {
-        "md" | "markdown" => "text/markdown",
-        "csv" => "text/csv",
-        "json" => "application/json",
-        "toml" => "application/toml",
-        "yaml" | "yml" => "application/yaml",
-        "xml" | "html" | "htm" => "text/html",
-        "js" | "mjs" | "cjs" => "text/javascript",
-        "ts" | "tsx" => "text/typescript",
-        "rs" => "text/rust",
-        "py" => "text/python",
-        "rb" => "text/ruby",
-        "go" => "text/go",
-        "java" => "text/java",
-        "c" | "h" => "text/c",
-        "cpp" | "cc" | "cxx" | "hpp" => "text/cpp",
-        "css" | "scss" | "less" => "text/css",
-        "sh" | "bash" | "zsh" | "fish" => "text/shell",
-        "sql" => "text/sql",
-        "svelte" => "text/svelte",
-        "vue" => "text/vue",
-        "txt" | "log" | "env" | "gitignore" | "dockerignore" => "text/plain",
-        "png" => "image/png",
-        "jpg" | "jpeg" => "image/jpeg",
-        "gif" => "image/gif",
-        "svg" => "image/svg+xml",
-        "webp" => "image/webp",
-        "pdf" => "application/pdf",
-        "zip" | "tar" | "gz" | "bz2" | "xz" | "7z" => "application/archive",
-        _ => "application/octet-stream",
-    }.to_string()
-}
-
-fn is_text_file(path: &Path, name: &str) -> bool {
-    let mime = mime_hint(name);
-    if mime.starts_with("text/") || mime == "application/json" || mime == "application/toml" || mime == "application/yaml" {
-        return true;
-    }
-    // Check first 512 bytes for binary content
-    if let Ok(bytes) = fs::read(path) {
-        let check = &bytes[..bytes.len().min(512)];
-        return !check.iter().any(|&b| b == 0);
-    }
-    false
-}
-
 pub fn get_cwd(session: &str) -> Result<String, String> {
     // Get the CWD of the active pane in the session
     let output = std::process::Command::new("tmux")
@@ -125,8 +113,8 @@ pub fn get_cwd(session: &str) -> Result<String, String> {
     }
 }
 
-pub fn list_dir(path: &str, show_hidden: bool) -> Result<Vec<FileEntry>, String> {
-    let dir = resolve_path(path);
+pub fn list_dir(path: &str, show_hidden: bool, thumbnails: bool) -> Result<Vec<FileEntry>, String> {
+    let dir = allowed_path(path, Op::Read)?;
     let entries = fs::read_dir(&dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
 
     let mut result: Vec<FileEntry> = Vec::new();
@@ -134,12 +122,24 @@ pub fn list_dir(path: &str, show_hidden: bool) -> Result<Vec<FileEntry>, String>
         let name = entry.file_name().to_string_lossy().to_string();
         let hidden = name.starts_with('.');
         if !show_hidden && hidden { continue; }
+        // Don't show what can't be opened anyway (deny globs, symlinks out of the roots)
+        if !sandbox::readable(&entry.path()) { continue; }
 
-        let meta = match entry.metadata() {
-            Ok(m) => m,
-            Err(_) => continue,
+        // DirEntry::metadata doesn't follow links, so links show up as such
+        let Ok(mut meta) = entry.metadata() else { continue };
+        let (mut link_target, mut target_type) = (None, None);
+        let file_type = if meta.file_type().is_symlink() {
+            link_target = fs::read_link(entry.path()).ok().map(|t| t.to_string_lossy().to_string());
+            if let Ok(target) = fs::metadata(entry.path()) {
+                target_type = Some(if target.is_dir() { "dir" } else { "file" }.to_string());
+                meta = target;
+            }
+            "symlink"
+        } else if meta.is_dir() {
+            "dir"
+        } else {
+            "file"
         };
-        let file_type = if meta.is_dir() { "dir" } else if meta.file_type().is_symlink() { "symlink" } else { "file" };
         let modified = meta.modified().ok()
             .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
             .map(|d| d.as_secs()).unwrap_or(0);
@@ -153,12 +153,24 @@ pub fn list_dir(path: &str, show_hidden: bool) -> Result<Vec<FileEntry>, String>
             modified,
             permissions: format_permissions(mode),
             hidden,
+            link_target,
+            target_type,
+            thumbnail: None,
         });
     }
 
-    // Sort: dirs first, then alphabetical (case-insensitive)
+    if thumbnails {
+        let deadline = std::time::Instant::now() + crate::thumbnail::LIST_BUDGET;
+        let is_file = |e: &FileEntry| e.file_type == "file" || e.target_type.as_deref() == Some("file");
+        for entry in result.iter_mut().filter(|e| is_file(e) && crate::thumbnail::supported(&e.name)) {
+            entry.thumbnail = crate::thumbnail::list_thumbnail(Path::new(&entry.path), deadline);
+        }
+    }
+
+    // Sort: dirs (and links to them) first, then alphabetical (case-insensitive)
+    let is_dir = |e: &FileEntry| e.file_type == "dir" || e.target_type.as_deref() == Some("dir");
     result.sort_by(|a, b| {
-        let dir_ord = (a.file_type != "dir").cmp(&(b.file_type != "dir"));
+        let dir_ord = (!is_dir(a)).cmp(&!is_dir(b));
         dir_ord.then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
     });
 
@@ -166,7 +178,7 @@ pub fn list_dir(path: &str, show_hidden: bool) -> Result<Vec<FileEntry>, String>
 }
 
 pub fn stat_file(path: &str) -> Result<FileStat, String> {
-    let p = resolve_path(path);
+    let p = allowed_path(path, Op::Read)?;
     let meta = fs::metadata(&p).map_err(|e| format!("stat error: {}", e))?;
     let name = p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
     let file_type = if meta.is_dir() { "dir" } else { "file" };
@@ -174,7 +186,14 @@ pub fn stat_file(path: &str) -> Result<FileStat, String> {
     let modified = meta.modified().ok()
         .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
         .map(|d| d.as_secs()).unwrap_or(0);
-    let is_text = if meta.is_file() { is_text_file(&p, &name) } else { false };
+    let sniffed = if meta.is_file() { crate::mime::sniff(&p).ok() } else { None };
+    let text = sniffed.as_ref().and_then(|s| s.text.clone());
+    let mime = match &sniffed {
+        Some(s) => s.mime,
+        None if meta.is_dir() => "inode/directory",
+        None => crate::mime::by_name(&name).unwrap_or("application/octet-stream"),
+    };
+    let etag = if meta.is_file() { ETAGS.get_or_compute(FileKey::of(&meta), || file_etag(&p, &meta)) } else { None };
 
     Ok(FileStat {
         path: p.to_string_lossy().to_string(),
@@ -185,33 +204,327 @@ pub fn stat_file(path: &str) -> Result<FileStat, String> {
         permissions: format_permissions(mode),
         readable: mode & 0o400 != 0,
         writable: mode & 0o200 != 0,
-        is_text,
-        mime_hint: mime_hint(&p.to_string_lossy()),
+        is_text: text.is_some(),
+        mime_hint: mime.to_string(),
+        charset: text.as_ref().map(|t| t.charset.to_string()),
+        line_ending: text.as_ref().and_then(|t| t.line_ending.map(String::from)),
+        line_count: text.as_ref().and_then(|t| t.line_count),
+        etag,
     })
 }
 
-pub fn read_file(path: &str) -> Result<String, String> {
-    let p = resolve_path(path);
+pub(crate) fn sha256_file(p: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+    let mut file = fs::File::open(p)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn sha256_bytes(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// `<mtime ns>-<size>-<sha256 prefix>`; the hash catches same-size edits
+/// within the mtime granularity
+fn etag(meta: &fs::Metadata, sha256: &str) -> String {
+    let mtime = meta.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:x}-{:x}-{}", mtime, meta.len(), &sha256[..16])
+}
+
+/// Etag of a file on disk; None past MAX_READ_SIZE, where hashing on every
+/// stat would cost too much
+fn file_etag(p: &Path, meta: &fs::Metadata) -> Option<String> {
+    if meta.len() > MAX_READ_SIZE {
+        return None;
+    }
+    sha256_file(p).ok().map(|h| etag(meta, &h))
+}
+
+/// Identity of a file's contents as far as its metadata tells: any write
+/// moves mtime and ctime, and ctime can't be set back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FileKey {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl FileKey {
+    pub(crate) fn of(meta: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.len(),
+            mtime: (meta.mtime(), meta.mtime_nsec()),
+            ctime: (meta.ctime(), meta.ctime_nsec()),
+        }
+    }
+}
+
+/// Values worked out from a file's contents, kept by `FileKey` so stats
+/// of an unchanged file don't read it again
+pub(crate) struct ContentCache<V>(OnceLock<Mutex<HashMap<FileKey, V>>>);
+
+impl<V: Clone> ContentCache<V> {
+    pub(crate) const fn new() -> Self {
+        Self(OnceLock::new())
+    }
+
+    /// The cached value for `key`, or what `compute` makes of the file now.
+    /// Failures (None) aren't cached.
+    pub(crate) fn get_or_compute(&self, key: FileKey, compute: impl FnOnce() -> Option<V>) -> Option<V> {
+        let map = self.0.get_or_init(|| Mutex::new(HashMap::new()));
+        if let Some(v) = map.lock().unwrap().get(&key) {
+            return Some(v.clone());
+        }
+        // Not under the lock: reading the file may take a while
+        let v = compute()?;
+        let mut map = map.lock().unwrap();
+        if map.len() >= MAX_CACHED {
+            map.clear();
+        }
+        map.insert(key, v.clone());
+        Some(v)
+    }
+}
+
+static ETAGS: ContentCache<String> = ContentCache::new();
+
+/// Whole text file, decoded from whatever encoding it is in. Returns the
+/// content, its etag and the encoding name.
+pub fn read_file(path: &str) -> Result<(String, String, &'static str), String> {
+    let p = allowed_path(path, Op::Read)?;
     let meta = fs::metadata(&p).map_err(|e| format!("read error: {}", e))?;
     if meta.len() > MAX_PREVIEW_SIZE {
-        return Err(format!("File too large for preview: {} bytes (max {})", meta.len(), MAX_PREVIEW_SIZE));
+        return Err(format!(
+            "File too large for preview: {} bytes (max {}); read a range with offset/line or use fs_tail",
+            meta.len(), MAX_PREVIEW_SIZE,
+        ));
+    }
+    let bytes = fs::read(&p).map_err(|e| format!("read error: {}", e))?;
+    let tag = etag(&meta, &sha256_bytes(&bytes));
+    let (content, enc) = decode_file(&bytes);
+    save_snapshot(p, &content);
+    Ok((content, tag, enc.name()))
+}
+
+/// Decode a whole file the way `read_file` does, without its BOM
+fn decode_file(bytes: &[u8]) -> (String, Encoding) {
+    let (enc, bom) = crate::text::detect(&bytes[..bytes.len().min(crate::text::SAMPLE)]);
+    (crate::text::decode(&bytes[bom..], enc), enc)
+}
+
+/// Encoding and BOM length of an existing file, as `read_file` sees them
+fn file_encoding(p: &Path) -> Option<(Encoding, usize)> {
+    use std::io::Read;
+    let mut head = Vec::new();
+    fs::File::open(p).ok()?.take(crate::text::SAMPLE as u64).read_to_end(&mut head).ok()?;
+    Some(crate::text::detect(&head))
+}
+
+/// The file as it is now, returned with a write conflict
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentFile {
+    pub exists: bool,
+    /// Decoded as `read_file` would; None when the file is missing or too large
+    pub content: Option<String>,
+    pub etag: Option<String>,
+}
+
+pub enum WriteError {
+    /// `if_match` did not match the file on disk
+    Conflict(Box<CurrentFile>),
+    Failed(String),
+}
+
+// Serializes the etag check and the write that follows it
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+fn current_file(p: &Path) -> CurrentFile {
+    let Ok(meta) = fs::metadata(p) else {
+        return CurrentFile { exists: false, content: None, etag: None };
+    };
+    if meta.len() > MAX_PREVIEW_SIZE {
+        return CurrentFile { exists: true, content: None, etag: file_etag(p, &meta) };
+    }
+    match fs::read(p) {
+        Ok(bytes) => CurrentFile {
+            exists: true,
+            etag: Some(etag(&meta, &sha256_bytes(&bytes))),
+            content: Some(decode_file(&bytes).0),
+        },
+        Err(_) => CurrentFile { exists: true, content: None, etag: None },
+    }
+}
+
+/// Write via a temp file in the same directory and rename it over `p`, so
+/// readers never see a half-written file. Keeps an existing file's mode
+/// (and owner, where permitted).
+fn atomic_write(p: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+    let existing = fs::metadata(p).ok();
+    if existing.as_ref().is_some_and(|m| !m.is_file()) {
+        return Err(std::io::Error::other("not a regular file"));
+    }
+    let dir = p.parent().unwrap_or(Path::new("/"));
+    let name = p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp = dir.join(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4().simple()));
+    let result = (|| {
+        let mut f = fs::OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        f.write_all(data)?;
+        if let Some(m) = &existing {
+            f.set_permissions(m.permissions())?;
+            let _ = std::os::unix::fs::fchown(&f, Some(m.uid()), Some(m.gid()));
+        }
+        f.sync_all()?;
+        fs::rename(&tmp, p)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Save `content`; with `if_match`, only if the file still has that etag.
+/// It is encoded as `encoding`, or else as the existing file is (keeping
+/// its BOM), so a read and save round trip leaves the encoding alone.
+/// Returns the new etag.
+pub fn write_file(path: &str, content: &str, if_match: Option<&str>, encoding: Option<&str>) -> Result<String, WriteError> {
+    let p = allowed_path(path, Op::Write).map_err(WriteError::Failed)?;
+    let requested = match encoding {
+        Some(name) => Some(Encoding::from_name(name).ok_or_else(|| WriteError::Failed(format!("unknown encoding: {}", name)))?),
+        None => None,
+    };
+    let _guard = WRITE_LOCK.lock().unwrap();
+    if let Some(expected) = if_match {
+        let current = current_file(&p);
+        if current.etag.as_deref() != Some(expected) {
+            return Err(WriteError::Conflict(Box::new(current)));
+        }
+    }
+    let existing = file_encoding(&p);
+    let enc = requested.or(existing.map(|(e, _)| e)).unwrap_or(Encoding::Utf8);
+    let bom = match existing {
+        Some((e, len)) if e == enc => len > 0,
+        // New UTF-16 files get one, so they are recognized when read back
+        _ => matches!(enc, Encoding::Utf16Le | Encoding::Utf16Be),
+    };
+    let mut data = if bom { enc.bom().to_vec() } else { Vec::new() };
+    data.extend(crate::text::encode(content, enc).map_err(|e| WriteError::Failed(format!("write error: {}", e)))?);
+    atomic_write(&p, &data).map_err(|e| WriteError::Failed(format!("write error: {}", e)))?;
+    let meta = fs::metadata(&p).map_err(|e| WriteError::Failed(format!("write error: {}", e)))?;
+    save_snapshot(p, content);
+    Ok(etag(&meta, &sha256_bytes(&data)))
+}
+
+// Last content each file had when a client read or saved it, so `fs_diff`
+// can show what changed since. Oldest entries go first past the size cap.
+struct Snapshot {
+    content: String,
+    time: SystemTime,
+}
+
+static SNAPSHOTS: OnceLock<Mutex<HashMap<PathBuf, Snapshot>>> = OnceLock::new();
+
+fn snapshots() -> &'static Mutex<HashMap<PathBuf, Snapshot>> {
+    SNAPSHOTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn save_snapshot(path: PathBuf, content: &str) {
+    let mut map = snapshots().lock().unwrap();
+    map.insert(path, Snapshot { content: content.to_string(), time: SystemTime::now() });
+    let mut total: usize = map.values().map(|s| s.content.len()).sum();
+    while total > MAX_SNAPSHOT_BYTES {
+        let Some(oldest) = map.iter().min_by_key(|(_, s)| s.time).map(|(p, _)| p.clone()) else { break };
+        total -= map.remove(&oldest).map(|s| s.content.len()).unwrap_or(0);
+    }
+}
+
+/// What `fs_diff` compares a file against
+pub enum DiffBase<'a> {
+    Path(&'a str),
+    Content(&'a str),
+    Snapshot,
+}
+
+pub struct FileComparison {
+    pub diff: crate::diff::FileDiff,
+    pub identical: bool,
+    /// When the snapshot compared against was taken (unix seconds)
+    pub snapshot_time: Option<u64>,
+}
+
+/// Text of a file for diffing; None if it looks binary
+fn diff_input(p: &Path) -> Result<Option<String>, String> {
+    let meta = fs::metadata(p).map_err(|e| format!("diff error: {}: {}", p.display(), e))?;
+    if !meta.is_file() {
+        return Err(format!("not a file: {}", p.display()));
+    }
+    if meta.len() > MAX_DIFF_SIZE {
+        return Err(format!("File too large to diff: {} bytes (max {})", meta.len(), MAX_DIFF_SIZE));
     }
-    fs::read_to_string(&p).map_err(|e| format!("read error: {}", e))
+    let bytes = fs::read(p).map_err(|e| format!("diff error: {}: {}", p.display(), e))?;
+    if bytes.contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
 }
 
-pub fn write_file(path: &str, content: &str) -> Result<(), String> {
-    let p = resolve_path(path);
-    fs::write(&p, content).map_err(|e| format!("write error: {}", e))
+/// Diff `base` (old side) against the current content of `path` (new side)
+pub fn diff_file(path: &str, base: DiffBase, context: usize) -> Result<FileComparison, String> {
+    let p = allowed_path(path, Op::Read)?;
+    let new = diff_input(&p)?;
+    let mut snapshot_time = None;
+    let (old_path, old) = match base {
+        DiffBase::Path(other) => {
+            let o = allowed_path(other, Op::Read)?;
+            let text = diff_input(&o)?;
+            (Some(o.to_string_lossy().to_string()), text)
+        }
+        DiffBase::Content(c) => (None, Some(c.to_string())),
+        DiffBase::Snapshot => {
+            let map = snapshots().lock().unwrap();
+            let snap = map.get(&p).ok_or_else(|| format!("no snapshot of {} (it has not been opened with fs_read)", p.display()))?;
+            snapshot_time = snap.time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+            (None, Some(snap.content.clone()))
+        }
+    };
+    let path = p.to_string_lossy().to_string();
+    let (Some(old), Some(new)) = (old, new) else {
+        let mut diff = crate::diff::diff_file(path, old_path, "", "", 0);
+        diff.binary = true;
+        return Ok(FileComparison { diff, identical: false, snapshot_time });
+    };
+    let identical = old == new;
+    let diff = crate::diff::diff_file(path, old_path, &old, &new, context);
+    Ok(FileComparison { diff, identical, snapshot_time })
 }
 
 pub fn create_dir(path: &str) -> Result<(), String> {
-    let p = resolve_path(path);
+    let p = allowed_path(path, Op::Write)?;
     fs::create_dir_all(&p).map_err(|e| format!("mkdir error: {}", e))
 }
 
 pub fn delete_path(path: &str) -> Result<(), String> {
-    let p = resolve_path(path);
-    if p.is_dir() {
+    let p = sandbox::check_entry(&expand_path(path), Op::Delete)?;
+    let is_dir = fs::symlink_metadata(&p).map(|m| m.is_dir()).unwrap_or(false);
+    if is_dir {
         fs::remove_dir_all(&p).map_err(|e| format!("delete error: {}", e))
     } else {
         fs::remove_file(&p).map_err(|e| format!("delete error: {}", e))
@@ -219,16 +532,320 @@ pub fn delete_path(path: &str) -> Result<(), String> {
 }
 
 pub fn rename_path(from: &str, to: &str) -> Result<(), String> {
-    let f = resolve_path(from);
-    let t = resolve_path(to);
+    let f = sandbox::check_entry(&expand_path(from), Op::Delete)?;
+    let t = sandbox::check_entry(&expand_path(to), Op::Write)?;
     fs::rename(&f, &t).map_err(|e| format!("rename error: {}", e))
 }
 
+/// What `fs_copy` does with files that already exist at the destination
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overwrite {
+    /// Copy nothing if any file would be replaced
+    Error,
+    Replace,
+    Skip,
+}
+
+enum CopyKind {
+    Dir(u32),
+    File(u32, u64),
+    Link(PathBuf),
+}
+
+/// A checked copy of a file or tree, ready to run
+pub struct CopyPlan {
+    to: PathBuf,
+    /// Source and destination of every entry, parents before children
+    entries: Vec<(PathBuf, PathBuf, CopyKind)>,
+    overwrite: Overwrite,
+    pub total_files: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CopyProgress {
+    pub files: u64,
+    pub bytes: u64,
+    pub total_files: u64,
+    pub total_bytes: u64,
+    /// Existing files left alone with `overwrite: skip`
+    pub skipped: u64,
+}
+
+/// Walk `from` and work out what copying it to `to` involves. Links inside
+/// a tree are copied as links; `from` itself is followed.
+pub fn plan_copy(from: &str, to: &str, overwrite: Overwrite) -> Result<CopyPlan, String> {
+    let src = allowed_path(from, Op::Read)?;
+    let dst = allowed_path(to, Op::Write)?;
+    if dst.starts_with(&src) {
+        return Err(format!("cannot copy {} into itself", src.display()));
+    }
+    if !dst.parent().is_some_and(|d| d.is_dir()) {
+        return Err(format!("parent directory does not exist: {}", dst.display()));
+    }
+    let walker = ignore::WalkBuilder::new(&src)
+        .standard_filters(false)
+        .filter_entry(|e| sandbox::readable_resolved(e.path()))
+        .build();
+    let mut plan = CopyPlan { to: dst.clone(), entries: Vec::new(), overwrite, total_files: 0, total_bytes: 0 };
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("copy error: {}", e))?;
+        let meta = fs::symlink_metadata(entry.path()).map_err(|e| format!("copy error: {}", e))?;
+        let rel = entry.path().strip_prefix(&src).unwrap_or(Path::new(""));
+        let target = if rel.as_os_str().is_empty() { dst.clone() } else { dst.join(rel) };
+        let mode = meta.permissions().mode();
+        let kind = if meta.is_dir() {
+            CopyKind::Dir(mode)
+        } else if meta.is_file() {
+            CopyKind::File(mode, meta.len())
+        } else if meta.file_type().is_symlink() {
+            CopyKind::Link(fs::read_link(entry.path()).map_err(|e| format!("copy error: {}", e))?)
+        } else {
+            continue; // sockets, fifos, devices
+        };
+        let existing = fs::symlink_metadata(&target).ok();
+        match (&kind, &existing) {
+            (CopyKind::Dir(_), Some(m)) if !m.is_dir() => {
+                return Err(format!("{} exists and is not a directory", target.display()));
+            }
+            (CopyKind::File(..) | CopyKind::Link(_), Some(m)) if m.is_dir() => {
+                return Err(format!("{} exists and is a directory", target.display()));
+            }
+            (CopyKind::File(..) | CopyKind::Link(_), Some(_)) if overwrite == Overwrite::Error => {
+                return Err(format!("{} already exists (pass overwrite: replace or skip)", target.display()));
+            }
+            _ => {}
+        }
+        if let CopyKind::File(_, len) = kind {
+            plan.total_files += 1;
+            plan.total_bytes += len;
+        }
+        plan.entries.push((entry.into_path(), target, kind));
+    }
+    Ok(plan)
+}
+
+/// Copy one file in chunks, checking for cancellation between them
+fn copy_data(src: &Path, dst: &Path, mode: u32, cancel: &AtomicBool, mut copied: impl FnMut(u64)) -> std::io::Result<()> {
+    use std::io::{Read, Write};
+    let mut from = fs::File::open(src)?;
+    let mut to = fs::File::create(dst)?;
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let n = from.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        to.write_all(&buf[..n])?;
+        copied(n as u64);
+    }
+    to.set_permissions(fs::Permissions::from_mode(mode & 0o7777))
+}
+
+impl CopyPlan {
+    /// Copy everything, calling `progress` after each chunk. A partly
+    /// written file is removed when the copy fails or is cancelled. Blocking.
+    pub fn run(self, cancel: &AtomicBool, mut progress: impl FnMut(&CopyProgress)) -> Result<CopyProgress, (String, CopyProgress)> {
+        let mut done = CopyProgress { total_files: self.total_files, total_bytes: self.total_bytes, ..Default::default() };
+        for (src, dst, kind) in &self.entries {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(("cancelled".into(), done));
+            }
+            if dst != &self.to {
+                // The plan was checked, but the tree may have changed since
+                if let Err(e) = sandbox::check_entry(dst, Op::Write) {
+                    return Err((e, done));
+                }
+            }
+            let exists = fs::symlink_metadata(dst).is_ok();
+            let result = match kind {
+                CopyKind::Dir(_) if exists => Ok(()),
+                CopyKind::Dir(mode) => fs::create_dir(dst).and_then(|_| fs::set_permissions(dst, fs::Permissions::from_mode(mode & 0o7777))),
+                _ if exists && self.overwrite == Overwrite::Skip => {
+                    if let CopyKind::File(_, len) = kind {
+                        done.skipped += 1;
+                        done.total_bytes -= len;
+                        done.total_files -= 1;
+                    }
+                    continue;
+                }
+                CopyKind::Link(target) => {
+                    if exists {
+                        let _ = fs::remove_file(dst);
+                    }
+                    std::os::unix::fs::symlink(target, dst)
+                }
+                CopyKind::File(mode, _) => {
+                    // Replace a link rather than write through it
+                    if fs::symlink_metadata(dst).is_ok_and(|m| m.file_type().is_symlink()) {
+                        let _ = fs::remove_file(dst);
+                    }
+                    let copied = copy_data(src, dst, *mode, cancel, |n| {
+                        done.bytes += n;
+                        progress(&done);
+                    });
+                    if copied.is_err() {
+                        let _ = fs::remove_file(dst);
+                    }
+                    copied.map(|_| done.files += 1)
+                }
+            };
+            if let Err(e) = result {
+                let msg = if cancel.load(Ordering::Relaxed) { "cancelled".to_string() } else { format!("cannot copy {}: {}", src.display(), e) };
+                return Err((msg, done));
+            }
+        }
+        Ok(done)
+    }
+}
+
+/// Apply a `chmod`-style mode to `current`: octal (`755`) or symbolic
+/// clauses like `+x`, `u+x,go-w` or `a=r`
+fn parse_mode(spec: &str, current: u32, is_dir: bool) -> Result<u32, String> {
+    if !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit()) {
+        return u32::from_str_radix(spec, 8)
+            .ok()
+            .filter(|m| *m <= 0o7777)
+            .ok_or_else(|| format!("invalid mode: {}", spec));
+    }
+    let invalid = || format!("invalid mode: {} (octal like 755, or symbolic like u+x,go-w)", spec);
+    let mut mode = current & 0o7777;
+    for clause in spec.split(',') {
+        let op_at = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+        let (who, rest) = clause.split_at(op_at);
+        let mut mask = 0;
+        for w in who.chars() {
+            mask |= match w {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return Err(invalid()),
+            };
+        }
+        if who.is_empty() {
+            mask = 0o7777;
+        }
+        let (op, perms) = rest.split_at(1);
+        let mut bits = 0;
+        for p in perms.chars() {
+            bits |= match p {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                // Execute only for directories and files something can already run
+                'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                'X' => 0,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return Err(invalid()),
+            };
+        }
+        match op {
+            "+" => mode |= bits & mask,
+            "-" => mode &= !(bits & mask),
+            _ => mode = (mode & !mask) | (bits & mask),
+        }
+    }
+    Ok(mode)
+}
+
+/// Change permissions of `path`, or of everything under it with
+/// `recursive` (links are left alone). Returns the new permissions of `path`.
+pub fn chmod(path: &str, mode: &str, recursive: bool) -> Result<(String, u32), String> {
+    let p = allowed_path(path, Op::Write)?;
+    let meta = fs::metadata(&p).map_err(|e| format!("chmod error: {}", e))?;
+    let new = parse_mode(mode, meta.permissions().mode(), meta.is_dir())?;
+    if recursive && meta.is_dir() {
+        chmod_tree(&p, mode)?;
+    } else {
+        fs::set_permissions(&p, fs::Permissions::from_mode(new)).map_err(|e| format!("chmod error: {}: {}", p.display(), e))?;
+    }
+    Ok((p.to_string_lossy().to_string(), new))
+}
+
+/// Recursive `chmod` of `p` and what is under it. Directories have to stay
+/// open while the walk is inside them, so the bits a mode adds are set on
+/// the way down and the bits it takes away on the way back up; both can
+/// come from one mode (`a-r,u+x`). Links and entries the policy denies are
+/// skipped; anything else that can't be read or changed is an error.
+fn chmod_tree(p: &Path, mode: &str) -> Result<(), String> {
+    let set = |bits: u32| {
+        fs::set_permissions(p, fs::Permissions::from_mode(bits)).map_err(|e| format!("chmod error: {}: {}", p.display(), e))
+    };
+    let meta = fs::symlink_metadata(p).map_err(|e| format!("chmod error: {}: {}", p.display(), e))?;
+    if meta.file_type().is_symlink() || sandbox::check_resolved(p, Op::Write).is_err() {
+        return Ok(());
+    }
+    let current = meta.permissions().mode() & 0o7777;
+    let new = parse_mode(mode, current, meta.is_dir())?;
+    if new & !current != 0 {
+        set(current | new)?;
+    }
+    if meta.is_dir() {
+        let entries = fs::read_dir(p).map_err(|e| format!("chmod error: {}: {}", p.display(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("chmod error: {}: {}", p.display(), e))?;
+            chmod_tree(&entry.path(), mode)?;
+        }
+    }
+    if current & !new != 0 {
+        set(new)?;
+    }
+    Ok(())
+}
+
+/// Create a symlink at `path` pointing to `target`, stored as given. The
+/// target isn't checked: anything read through the link is.
+pub fn symlink(target: &str, path: &str) -> Result<String, String> {
+    let p = sandbox::check_entry(&expand_path(path), Op::Write)?;
+    if target.is_empty() {
+        return Err("empty target".into());
+    }
+    std::os::unix::fs::symlink(expand_path(target), &p).map_err(|e| format!("symlink error: {}", e))?;
+    Ok(p.to_string_lossy().to_string())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkInfo {
+    pub path: String,
+    /// As stored in the link
+    pub target: String,
+    /// Where it leads, if that exists and may be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+    /// `file` or `dir`; None for a broken link
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_type: Option<String>,
+}
+
+pub fn read_link(path: &str) -> Result<LinkInfo, String> {
+    let p = sandbox::check_entry(&expand_path(path), Op::Read)?;
+    let target = fs::read_link(&p).map_err(|e| match e.kind() {
+        std::io::ErrorKind::InvalidInput => format!("not a symlink: {}", p.display()),
+        _ => format!("readlink error: {}", e),
+    })?;
+    let resolved = p.canonicalize().ok().filter(|r| sandbox::readable_resolved(r));
+    let target_type = resolved.as_ref()
+        .and_then(|r| fs::metadata(r).ok())
+        .map(|m| if m.is_dir() { "dir" } else { "file" }.to_string());
+    Ok(LinkInfo {
+        path: p.to_string_lossy().to_string(),
+        target: target.to_string_lossy().to_string(),
+        resolved: resolved.map(|r| r.to_string_lossy().to_string()),
+        target_type,
+    })
+}
+
 pub fn download_file(path: &str) -> Result<(String, String), String> {
-    let p = resolve_path(path);
+    let p = allowed_path(path, Op::Read)?;
     let meta = fs::metadata(&p).map_err(|e| format!("download error: {}", e))?;
     if meta.len() > MAX_READ_SIZE {
-        return Err(format!("File too large: {} bytes (max {})", meta.len(), MAX_READ_SIZE));
+        return Err(format!("File too large: {} bytes (max {}); use transfer_download", meta.len(), MAX_READ_SIZE));
     }
     let bytes = fs::read(&p).map_err(|e| format!("download error: {}", e))?;
     use base64::Engine;
@@ -238,9 +855,164 @@ pub fn download_file(path: &str) -> Result<(String, String), String> {
 }
 
 pub fn upload_file(path: &str, data_b64: &str) -> Result<(), String> {
-    let p = resolve_path(path);
+    let p = allowed_path(path, Op::Write)?;
+    if data_b64.len() as u64 / 4 * 3 > MAX_READ_SIZE {
+        return Err(format!("Upload too large (max {} bytes); use transfer_upload", MAX_READ_SIZE));
+    }
     use base64::Engine;
     let bytes = base64::engine::general_purpose::STANDARD.decode(data_b64)
         .map_err(|e| format!("invalid base64: {}", e))?;
     fs::write(&p, &bytes).map_err(|e| format!("upload error: {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn content_cache_follows_file_key() {
+        let dir = scratch();
+        let p = dir.join("a.txt");
+        fs::write(&p, "one").unwrap();
+        let cache: ContentCache<usize> = ContentCache::new();
+        let calls = Cell::new(0);
+        let compute = || {
+            calls.set(calls.get() + 1);
+            Some(calls.get())
+        };
+        let key = FileKey::of(&fs::metadata(&p).unwrap());
+        assert_eq!(cache.get_or_compute(key, compute), Some(1));
+        assert_eq!(cache.get_or_compute(key, compute), Some(1), "unchanged file is not read again");
+        // Same size, and the mtime put back: ctime still moves
+        let mtime = fs::metadata(&p).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&p, "two").unwrap();
+        fs::File::options().write(true).open(&p).unwrap().set_modified(mtime).unwrap();
+        let changed = FileKey::of(&fs::metadata(&p).unwrap());
+        assert_ne!(changed, key);
+        assert_eq!(cache.get_or_compute(changed, compute), Some(2));
+        assert_eq!(cache.get_or_compute(changed, || None), Some(2));
+        // Failures are not remembered
+        let other = FileKey { ino: key.ino + 1, ..key };
+        assert_eq!(cache.get_or_compute(other, || None::<usize>), None);
+        assert_eq!(cache.get_or_compute(other, compute), Some(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stat_etag_tracks_content() {
+        let dir = scratch();
+        let p = dir.join("b.txt");
+        fs::write(&p, "hello\n").unwrap();
+        let path = p.to_str().unwrap();
+        let first = stat_file(path).unwrap().etag.unwrap();
+        assert_eq!(stat_file(path).unwrap().etag.unwrap(), first);
+        assert_eq!(current_file(&p).etag.unwrap(), first, "write conflicts compare against the same etag");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&p, "HELLO\n").unwrap();
+        let second = stat_file(path).unwrap().etag.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(current_file(&p).etag.unwrap(), second);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_keeps_encoding_and_bom() {
+        let dir = scratch();
+        let p = dir.join("t.txt");
+        let path = p.to_str().unwrap();
+        let write = |content: &str, encoding: Option<&str>| write_file(path, content, None, encoding).ok().unwrap();
+        // existing bytes, what is saved, the bytes that should end up on disk
+        let cases: Vec<(&[u8], &str, Vec<u8>)> = vec![
+            (b"\xEF\xBB\xBFold\n", "new é\n", b"\xEF\xBB\xBFnew \xC3\xA9\n".to_vec()),
+            (b"caf\xE9\n", "caf\u{E9}s\n", b"caf\xE9s\n".to_vec()),
+            (b"\xFF\xFEo\x00\n\x00", "ok\n", b"\xFF\xFEo\x00k\x00\n\x00".to_vec()),
+            (b"plain\n", "é\n", "é\n".as_bytes().to_vec()),
+        ];
+        for (before, content, after) in cases {
+            fs::write(&p, before).unwrap();
+            let (_, _, enc) = read_file(path).unwrap();
+            let tag = write(content, None);
+            assert_eq!(fs::read(&p).unwrap(), after, "{}", enc);
+            let (read_back, read_tag, read_enc) = read_file(path).unwrap();
+            assert_eq!((read_back.as_str(), read_tag, read_enc), (content, tag, enc));
+        }
+        // Converting on request; a new UTF-16 file gets a BOM
+        fs::write(&p, b"caf\xE9\n").unwrap();
+        write("café\n", Some("utf-8"));
+        assert_eq!(fs::read(&p).unwrap(), "café\n".as_bytes());
+        fs::remove_file(&p).unwrap();
+        write("hi", Some("utf-16be"));
+        assert_eq!(fs::read(&p).unwrap(), b"\xFE\xFF\x00h\x00i");
+        // Nothing is written when the text doesn't fit the encoding
+        fs::write(&p, b"caf\xE9\n").unwrap();
+        let Err(WriteError::Failed(e)) = write_file(path, "5 €", None, None) else { panic!("€ written as latin-1") };
+        assert!(e.contains("can't be encoded as iso-8859-1"), "{}", e);
+        assert!(matches!(write_file(path, "x", None, Some("ebcdic")), Err(WriteError::Failed(_))));
+        assert_eq!(fs::read(&p).unwrap(), b"caf\xE9\n");
+        // A conflict returns the content decoded the same way
+        let Err(WriteError::Conflict(current)) = write_file(path, "x", Some("stale"), None) else { panic!("no conflict") };
+        assert_eq!(current.content.as_deref(), Some("café\n"));
+        assert_eq!(current.etag, Some(read_file(path).unwrap().1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chmod_recursive_adds_and_removes() {
+        let dir = scratch();
+        let top = dir.join("top");
+        fs::create_dir_all(top.join("sub/deeper")).unwrap();
+        fs::write(top.join("sub/deeper/f"), "x").unwrap();
+        fs::write(top.join("g"), "x").unwrap();
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        for p in [top.join("sub/deeper/f"), top.join("g")] {
+            fs::set_permissions(&p, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        for p in [top.join("sub/deeper"), top.join("sub"), top.clone()] {
+            fs::set_permissions(&p, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        // Takes the read bits away from directories the walk still has to enter
+        let (_, new) = chmod(top.to_str().unwrap(), "a-r,u+x", true).unwrap();
+        assert_eq!(new, 0o311);
+        assert_eq!(mode(&top.join("sub")), 0o311);
+        assert_eq!(mode(&top.join("sub/deeper")), 0o311);
+        assert_eq!(mode(&top.join("sub/deeper/f")), 0o300);
+        assert_eq!(mode(&top.join("g")), 0o300);
+
+        // And gives them back to the whole tree, top-down
+        let (_, new) = chmod(top.to_str().unwrap(), "u+r,go+rX", true).unwrap();
+        assert_eq!(new, 0o755);
+        assert_eq!(mode(&top.join("sub/deeper")), 0o755);
+        assert_eq!(mode(&top.join("sub/deeper/f")), 0o755, "X: u+x was already set");
+        assert_eq!(chmod(top.to_str().unwrap(), "700", false).unwrap().1, 0o700);
+        assert_eq!(mode(&top.join("sub")), 0o755, "not recursive");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chmod_reports_walk_errors() {
+        let dir = scratch();
+        let locked = dir.join("locked");
+        fs::create_dir_all(locked.join("inner")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root can read it anyway, and then there is nothing to report
+        let readable = fs::read_dir(&locked).is_ok();
+        let result = chmod(dir.to_str().unwrap(), "go-w", true);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            assert!(result.is_ok());
+        } else {
+            let err = result.unwrap_err();
+            assert!(err.starts_with("chmod error") && err.contains("locked"), "{}", err);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
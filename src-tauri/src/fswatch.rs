@@ -0,0 +1,263 @@
+/// Change notifications for watched files and directories (inotify on
+/// Linux), one watcher per connection
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Watched paths per connection
+pub const MAX_WATCHES: usize = 32;
+/// Changes are collected this long after the first one, then sent together
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub path: String,
+    /// `created`, `modified`, `deleted` or `renamed`
+    pub kind: &'static str,
+    /// Old path of a rename
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchInfo {
+    pub path: String,
+    pub recursive: bool,
+}
+
+pub struct Watches {
+    watcher: notify::RecommendedWatcher,
+    paths: HashMap<PathBuf, bool>,
+}
+
+fn change(path: &Path, kind: &'static str) -> Change {
+    Change { path: path.to_string_lossy().to_string(), kind, from: None }
+}
+
+/// Turn a raw event into changes, leaving out paths the policy hides
+fn changes(event: notify::Event) -> Vec<Change> {
+    let kind = match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            let (from, to) = (&event.paths[0], &event.paths[1]);
+            return match (sandbox::readable(from), sandbox::readable(to)) {
+                (true, true) => vec![Change { from: Some(from.to_string_lossy().to_string()), ..change(to, "renamed") }],
+                (false, true) => vec![change(to, "created")],
+                (true, false) => vec![change(from, "deleted")],
+                (false, false) => Vec::new(),
+            };
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => "deleted",
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => "created",
+        EventKind::Create(_) => "created",
+        EventKind::Modify(_) => "modified",
+        EventKind::Remove(_) => "deleted",
+        _ => return Vec::new(),
+    };
+    event.paths.iter().filter(|p| sandbox::readable(p)).map(|p| change(p, kind)).collect()
+}
+
+impl Watches {
+    pub fn new(tx: mpsc::UnboundedSender<Change>) -> Result<Self, String> {
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                for c in changes(event) {
+                    let _ = tx.send(c);
+                }
+            }
+        })
+        .map_err(|e| format!("cannot start watcher: {}", e))?;
+        Ok(Self { watcher, paths: HashMap::new() })
+    }
+
+    pub fn watch(&mut self, path: &str, recursive: bool) -> Result<WatchInfo, String> {
+        let p = sandbox::check(&expand_path(path), Op::Read)?;
+        if !self.paths.contains_key(&p) && self.paths.len() >= MAX_WATCHES {
+            return Err(format!("too many watches (max {} per connection)", MAX_WATCHES));
+        }
+        if self.paths.contains_key(&p) {
+            let _ = self.watcher.unwatch(&p);
+        }
+        let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        self.watcher.watch(&p, mode).map_err(|e| format!("cannot watch {}: {}", p.display(), e))?;
+        self.paths.insert(p.clone(), recursive);
+        Ok(WatchInfo { path: p.to_string_lossy().to_string(), recursive })
+    }
+
+    pub fn unwatch(&mut self, path: &str) -> Result<String, String> {
+        let expanded = expand_path(path);
+        let p = expanded.canonicalize().unwrap_or(expanded);
+        if self.paths.remove(&p).is_none() {
+            return Err(format!("not watching {}", p.display()));
+        }
+        let _ = self.watcher.unwatch(&p);
+        Ok(p.to_string_lossy().to_string())
+    }
+
+    pub fn list(&self) -> Vec<WatchInfo> {
+        let mut list: Vec<WatchInfo> = self.paths.iter()
+            .map(|(p, &recursive)| WatchInfo { path: p.to_string_lossy().to_string(), recursive })
+            .collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        list
+    }
+}
+
+/// Changes collected within one debounce window
+#[derive(Default)]
+struct Batch {
+    changes: Vec<Change>,
+    /// Paths that came into existence during the window
+    born: HashSet<String>,
+}
+
+impl Batch {
+    fn position(&self, path: &str) -> Option<usize> {
+        self.changes.iter().position(|c| c.path == path)
+    }
+
+    fn take(&mut self, path: &str) -> Option<Change> {
+        self.position(path).map(|i| self.changes.remove(i))
+    }
+
+    /// Fold a change in: a file created and deleted within the window never
+    /// shows up, one deleted and recreated is just modified
+    fn merge(&mut self, c: Change) {
+        if c.kind == "created" {
+            self.born.insert(c.path.clone());
+        }
+        if let Some(from) = c.from.clone() {
+            // inotify reports a rename as delete + create + rename; keep only the rename
+            let source = self.take(&from);
+            let target = self.take(&c.path);
+            self.born.insert(c.path.clone());
+            let from_new = self.born.contains(&from) || source.is_some_and(|s| s.kind == "created");
+            if !from_new {
+                self.changes.push(c);
+                return;
+            }
+            // A file that only appeared in this window (an editor's temp file) moved into place
+            let kind = match target.map(|t| t.kind) {
+                Some("deleted") | Some("modified") => "modified",
+                _ => "created",
+            };
+            self.changes.push(change(Path::new(&c.path), kind));
+            return;
+        }
+        let Some(i) = self.position(&c.path) else {
+            self.changes.push(c);
+            return;
+        };
+        match (self.changes[i].kind, c.kind) {
+            ("created", "deleted") => {
+                self.changes.remove(i);
+            }
+            ("deleted", "created") => self.changes[i].kind = "modified",
+            (_, "deleted") => {
+                // A rename's old path is gone too, whatever happens to the new one
+                let from = std::mem::replace(&mut self.changes[i], c).from;
+                if let Some(from) = from {
+                    self.changes.insert(i, change(Path::new(&from), "deleted"));
+                }
+            }
+            _ => {} // created/modified/renamed already cover it
+        }
+    }
+}
+
+/// Wait for the next change, collect whatever follows within the debounce
+/// window and return the merged batch. None once the watcher is gone.
+pub async fn next_batch(rx: &mut mpsc::UnboundedReceiver<Change>) -> Option<Vec<Change>> {
+    loop {
+        let mut batch = Batch::default();
+        batch.merge(rx.recv().await?);
+        let deadline = tokio::time::Instant::now() + DEBOUNCE;
+        while let Ok(Some(c)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+            batch.merge(c);
+        }
+        if !batch.changes.is_empty() {
+            return Some(batch.changes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ev(kind: &'static str, path: &str) -> Change {
+        Change { path: path.to_string(), kind, from: None }
+    }
+
+    fn renamed(from: &str, to: &str) -> Change {
+        Change { from: Some(from.to_string()), ..ev("renamed", to) }
+    }
+
+    #[test]
+    fn merge_sequences() {
+        type Case = (&'static str, Vec<Change>, Vec<(&'static str, &'static str, Option<&'static str>)>);
+        let cases: Vec<Case> = vec![
+            ("created then deleted", vec![ev("created", "a"), ev("deleted", "a")], vec![]),
+            ("deleted then recreated", vec![ev("deleted", "a"), ev("created", "a")], vec![("a", "modified", None)]),
+            ("modified then deleted", vec![ev("modified", "a"), ev("deleted", "a")], vec![("a", "deleted", None)]),
+            ("created then modified", vec![ev("created", "a"), ev("modified", "a"), ev("modified", "a")], vec![("a", "created", None)]),
+            (
+                "separate paths keep their order",
+                vec![ev("modified", "b"), ev("created", "a")],
+                vec![("b", "modified", None), ("a", "created", None)],
+            ),
+            (
+                "inotify rename: delete + create + rename",
+                vec![ev("deleted", "a"), ev("created", "b"), renamed("a", "b")],
+                vec![("b", "renamed", Some("a"))],
+            ),
+            ("plain rename", vec![renamed("a", "b")], vec![("b", "renamed", Some("a"))]),
+            (
+                "editor save: temp file renamed over a modified file",
+                vec![ev("modified", "f"), ev("created", "f.tmp"), ev("modified", "f.tmp"), renamed("f.tmp", "f")],
+                vec![("f", "modified", None)],
+            ),
+            (
+                "temp file renamed over a deleted file",
+                vec![ev("deleted", "f"), ev("created", "f.tmp"), renamed("f.tmp", "f")],
+                vec![("f", "modified", None)],
+            ),
+            ("new file moved into place", vec![ev("created", "n.tmp"), renamed("n.tmp", "n")], vec![("n", "created", None)]),
+            (
+                "renamed target deleted later",
+                vec![renamed("a", "b"), ev("deleted", "b")],
+                vec![("a", "deleted", None), ("b", "deleted", None)],
+            ),
+        ];
+        for (name, events, want) in cases {
+            let mut batch = Batch::default();
+            for c in events {
+                batch.merge(c);
+            }
+            let got: Vec<_> = batch.changes.iter().map(|c| (c.path.as_str(), c.kind, c.from.as_deref())).collect();
+            assert_eq!(got, want, "{}", name);
+        }
+    }
+
+    #[test]
+    fn renames_across_the_policy() {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        let (open, hidden) = (dir.join("notes"), dir.join(".env"));
+        let rename = |from: &Path, to: &Path| {
+            let event = notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from.to_path_buf())
+                .add_path(to.to_path_buf());
+            changes(event).into_iter().map(|c| (c.path, c.kind)).collect::<Vec<_>>()
+        };
+        let s = |p: &Path| p.to_string_lossy().to_string();
+        assert_eq!(rename(&open, &dir.join("other")), [(s(&dir.join("other")), "renamed")]);
+        assert_eq!(rename(&hidden, &open), [(s(&open), "created")], "moved out of hiding");
+        assert_eq!(rename(&open, &hidden), [(s(&open), "deleted")], "moved into hiding");
+        assert!(rename(&hidden, &dir.join(".env.bak")).is_empty());
+    }
+}
@@ -0,0 +1,731 @@
+/// Git integration for the repository containing a path, via the git CLI
+use crate::diff::{self, FileDiff};
+use crate::fs::{allowed_path, resolve_path};
+use crate::sandbox::{self, Op};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+const MAX_DIFF_BYTES: usize = 4 * 1024 * 1024;
+const MAX_UNTRACKED_DIFFS: usize = 50;
+const MAX_LOG: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Branch {
+    /// Branch name, or None when detached
+    pub head: Option<String>,
+    /// None before the first commit
+    pub oid: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusEntry {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// Change in the index: modified, added, deleted, renamed, copied, typechange
+    pub staged: Option<&'static str>,
+    /// Change in the working tree, or `untracked`
+    pub unstaged: Option<&'static str>,
+    pub conflicted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub root: String,
+    pub branch: Branch,
+    pub files: Vec<StatusEntry>,
+    /// Changes whenever HEAD, the index, the working tree or the stash does;
+    /// write methods take it back as `expected_state`
+    pub state: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Commit {
+    pub hash: String,
+    pub short_hash: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub email: String,
+    pub time: u64,
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diff {
+    pub root: String,
+    pub staged: Vec<FileDiff>,
+    pub unstaged: Vec<FileDiff>,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Show {
+    pub commit: Commit,
+    pub files: Vec<FileDiff>,
+    pub truncated: bool,
+}
+
+fn command(root: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(root)
+        .args(["-c", "core.quotePath=false", "-c", "color.ui=false"])
+        // Don't take index.lock for read-only commands (an agent may be using git)
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        // Pathspecs are checked against the fs policy one by one; no globs
+        .env("GIT_LITERAL_PATHSPECS", "1")
+        .env("LC_ALL", "C");
+    cmd
+}
+
+/// Run git in `root`; `ok_codes` lists exit codes besides 0 that aren't failures
+pub(crate) fn run_git(root: &Path, args: &[&str], ok_codes: &[i32]) -> Result<Vec<u8>, String> {
+    let output = command(root)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    let code = output.status.code().unwrap_or(-1);
+    if code == 0 || ok_codes.contains(&code) {
+        Ok(output.stdout)
+    } else {
+        Err(format!("git error: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+pub(crate) fn git(root: &Path, args: &[&str]) -> Result<String, String> {
+    run_git(root, args, &[]).map(|out| String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Top-level directory of the repository containing `path` (a file or
+/// directory). Git reads the whole work tree, so the top level itself must
+/// be inside the fs policy's roots, not just `path`.
+pub fn repo_root(path: &str) -> Result<PathBuf, String> {
+    let p = allowed_path(path, Op::Read)?;
+    let dir = if p.is_dir() { p.clone() } else { p.parent().map(Path::to_path_buf).unwrap_or_default() };
+    let root = git(&dir, &["rev-parse", "--show-toplevel"])
+        .map(|s| PathBuf::from(s.trim_end_matches('\n')))
+        .map_err(|_| format!("not in a git repository: {}", p.display()))?;
+    sandbox::check(&root, Op::Read)
+}
+
+/// Path relative to the repo root, for `-- <pathspec>`, if the fs policy
+/// allows `op` on it
+fn relative(root: &Path, file: &str, op: Op) -> Result<String, String> {
+    let p = if file.starts_with('/') || file.starts_with('~') { resolve_path(file) } else { PathBuf::from(file) };
+    sandbox::check(&root.join(&p), op)?;
+    Ok(p.strip_prefix(root).unwrap_or(&p).to_string_lossy().into_owned())
+}
+
+/// Whether the fs policy lets clients see a file's changes; a rename
+/// needs both sides
+fn visible(root: &Path, f: &FileDiff) -> bool {
+    std::iter::once(&f.path).chain(&f.old_path).all(|p| sandbox::check(&root.join(p), Op::Read).is_ok())
+}
+
+fn change_word(c: char) -> Option<&'static str> {
+    match c {
+        'M' => Some("modified"),
+        'A' => Some("added"),
+        'D' => Some("deleted"),
+        'R' => Some("renamed"),
+        'C' => Some("copied"),
+        'T' => Some("typechange"),
+        _ => None,
+    }
+}
+
+/// Parse `git status --porcelain=v2 --branch -z`
+fn parse_status(raw: &str) -> (Branch, Vec<StatusEntry>) {
+    let mut branch = Branch { head: None, oid: None, upstream: None, ahead: 0, behind: 0 };
+    let mut files = Vec::new();
+    let mut records = raw.split('\0');
+    while let Some(rec) = records.next() {
+        if let Some(h) = rec.strip_prefix("# ") {
+            let (key, val) = h.split_once(' ').unwrap_or((h, ""));
+            match key {
+                "branch.oid" if val != "(initial)" => branch.oid = Some(val.to_string()),
+                "branch.head" if val != "(detached)" => branch.head = Some(val.to_string()),
+                "branch.upstream" => branch.upstream = Some(val.to_string()),
+                "branch.ab" => {
+                    for part in val.split(' ') {
+                        if let Some(n) = part.strip_prefix('+') {
+                            branch.ahead = n.parse().unwrap_or(0);
+                        } else if let Some(n) = part.strip_prefix('-') {
+                            branch.behind = n.parse().unwrap_or(0);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let kind = rec.chars().next().unwrap_or(' ');
+        // Field count before the path: ordinary 8, rename/copy 9, unmerged 10
+        let fields = match kind {
+            '1' => 8,
+            '2' => 9,
+            'u' => 10,
+            '?' => {
+                files.push(StatusEntry { path: rec[2..].to_string(), old_path: None, staged: None, unstaged: Some("untracked"), conflicted: false });
+                continue;
+            }
+            _ => continue,
+        };
+        let parts: Vec<&str> = rec.splitn(fields + 1, ' ').collect();
+        let (Some(xy), Some(path)) = (parts.get(1), parts.get(fields)) else { continue };
+        let mut xy = xy.chars();
+        let (x, y) = (xy.next().unwrap_or('.'), xy.next().unwrap_or('.'));
+        let old_path = if kind == '2' { records.next().map(str::to_string) } else { None };
+        files.push(StatusEntry {
+            path: path.to_string(),
+            old_path,
+            staged: if kind == 'u' { None } else { change_word(x) },
+            unstaged: if kind == 'u' { None } else { change_word(y) },
+            conflicted: kind == 'u',
+        });
+    }
+    (branch, files)
+}
+
+pub fn status(path: &str) -> Result<Status, String> {
+    let root = repo_root(path)?;
+    let raw = git(&root, &["status", "--porcelain=v2", "--branch", "-z"])?;
+    let (branch, mut files) = parse_status(&raw);
+    // Denied files are left out, as in fs_list and git_diff
+    files.retain(|f| std::iter::once(&f.path).chain(&f.old_path).all(|p| sandbox::readable_resolved(&root.join(p))));
+
+    // Status alone misses further edits to already-modified files, so mix in
+    // their size and mtime, plus the stash tip
+    let mut h = DefaultHasher::new();
+    raw.hash(&mut h);
+    for f in files.iter().filter(|f| f.unstaged.is_some() || f.conflicted) {
+        if let Ok(meta) = std::fs::symlink_metadata(root.join(&f.path)) {
+            meta.len().hash(&mut h);
+            meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_nanos()).hash(&mut h);
+        }
+    }
+    run_git(&root, &["rev-parse", "-q", "--verify", "refs/stash"], &[1])?.hash(&mut h);
+
+    Ok(Status {
+        root: root.to_string_lossy().into_owned(),
+        branch,
+        files,
+        state: format!("{:016x}", h.finish()),
+    })
+}
+
+/// Cap raw diff output at a line boundary
+fn bounded(mut out: Vec<u8>, budget: &mut usize) -> (String, bool) {
+    let truncated = out.len() > *budget;
+    if truncated {
+        let cut = out[..*budget].iter().rposition(|&b| b == b'\n').map(|i| i + 1).unwrap_or(0);
+        out.truncate(cut);
+    }
+    *budget -= out.len();
+    (String::from_utf8_lossy(&out).into_owned(), truncated)
+}
+
+fn unified(context: Option<usize>) -> String {
+    format!("-U{}", context.unwrap_or(3).min(1000))
+}
+
+/// `git diff` arguments shared by `diff` and `stage_hunk`, so hunk indices
+/// from one are valid in the other
+fn diff_args<'a>(unified: &'a str, cached: bool, specs: &'a [String]) -> Vec<&'a str> {
+    let mut args = vec!["diff", "--no-ext-diff", "-M", unified];
+    if cached {
+        args.push("--cached");
+    }
+    args.push("--");
+    args.extend(specs.iter().map(String::as_str));
+    args
+}
+
+/// `file` plus the path it was renamed from in the staged (`cached`) or
+/// unstaged diff, so a diff limited to `file` still pairs the rename
+fn with_rename_source(root: &Path, file: &str, cached: bool) -> Result<Vec<String>, String> {
+    let mut args = vec!["diff", "--no-ext-diff", "-M", "--name-status", "-z"];
+    if cached {
+        args.push("--cached");
+    }
+    let out = git(root, &args)?;
+    let mut fields = out.split('\0');
+    let mut specs = vec![file.to_string()];
+    while let Some(status) = fields.next() {
+        if status.starts_with('R') || status.starts_with('C') {
+            let (Some(from), Some(to)) = (fields.next(), fields.next()) else { break };
+            if to == file {
+                specs.push(from.to_string());
+                break;
+            }
+        } else {
+            fields.next();
+        }
+    }
+    Ok(specs)
+}
+
+/// Staged and unstaged changes, optionally for one file. Untracked files are
+/// shown as additions in `unstaged` unless `untracked` is false.
+pub fn diff(path: &str, file: Option<&str>, context: Option<usize>, untracked: bool) -> Result<Diff, String> {
+    let root = repo_root(path)?;
+    let unified = unified(context);
+    let spec = file.map(|f| relative(&root, f, Op::Read)).transpose()?;
+    let mut budget = MAX_DIFF_BYTES;
+    let mut truncated = false;
+
+    let mut run = |cached: bool| -> Result<Vec<FileDiff>, String> {
+        let specs = match &spec {
+            Some(f) => with_rename_source(&root, f, cached)?,
+            None => Vec::new(),
+        };
+        let args = diff_args(&unified, cached, &specs);
+        let (text, cut) = bounded(run_git(&root, &args, &[])?, &mut budget);
+        truncated |= cut;
+        Ok(diff::parse_git(&text).into_iter().filter(|f| visible(&root, f)).collect())
+    };
+    let staged = run(true)?;
+    let mut unstaged = run(false)?;
+
+    if untracked {
+        let mut args = vec!["ls-files", "--others", "--exclude-standard", "-z", "--"];
+        args.extend(spec.as_deref());
+        let list = git(&root, &args)?;
+        let files = list.split('\0').filter(|f| !f.is_empty() && sandbox::check(&root.join(f), Op::Read).is_ok());
+        for (i, f) in files.enumerate() {
+            if i >= MAX_UNTRACKED_DIFFS || budget == 0 {
+                truncated = true;
+                break;
+            }
+            // `--no-index` exits with 1 when the files differ
+            let out = run_git(&root, &["diff", "--no-ext-diff", "--no-index", unified.as_str(), "--", "/dev/null", f], &[1])?;
+            let (text, cut) = bounded(out, &mut budget);
+            truncated |= cut;
+            unstaged.extend(diff::parse_git(&text));
+        }
+    }
+
+    Ok(Diff { root: root.to_string_lossy().into_owned(), staged, unstaged, truncated })
+}
+
+const COMMIT_FORMAT: &str = "%H%x1f%h%x1f%P%x1f%an%x1f%ae%x1f%at%x1f%s";
+
+fn parse_commit(rec: &str, body: Option<String>) -> Option<Commit> {
+    let f: Vec<&str> = rec.splitn(7, '\x1f').collect();
+    if f.len() < 7 {
+        return None;
+    }
+    Some(Commit {
+        hash: f[0].trim_start().to_string(),
+        short_hash: f[1].to_string(),
+        parents: f[2].split_whitespace().map(str::to_string).collect(),
+        author: f[3].to_string(),
+        email: f[4].to_string(),
+        time: f[5].parse().unwrap_or(0),
+        subject: f[6].trim_end().to_string(),
+        body,
+    })
+}
+
+/// Recent commits on HEAD, newest first, optionally touching one file
+pub fn log(path: &str, limit: usize, skip: usize, file: Option<&str>) -> Result<Vec<Commit>, String> {
+    let root = repo_root(path)?;
+    if run_git(&root, &["rev-parse", "-q", "--verify", "HEAD"], &[1])?.is_empty() {
+        return Ok(Vec::new()); // no commits yet
+    }
+    let format = format!("--format={}%x1e", COMMIT_FORMAT);
+    let limit = format!("-n{}", limit.clamp(1, MAX_LOG));
+    let skip = format!("--skip={}", skip);
+    let spec = file.map(|f| relative(&root, f, Op::Read)).transpose()?;
+    let mut args = vec!["log", format.as_str(), limit.as_str(), skip.as_str(), "--"];
+    args.extend(spec.as_deref());
+    let out = git(&root, &args)?;
+    Ok(out.split('\x1e').filter_map(|rec| parse_commit(rec, None)).collect())
+}
+
+/// Refs from clients end up on the git command line; keep them to ref syntax
+pub(crate) fn check_ref(rev: &str) -> Result<(), String> {
+    let ok = !rev.is_empty()
+        && !rev.starts_with('-')
+        && rev.chars().all(|c| c.is_ascii_alphanumeric() || "/._-~^@{}".contains(c));
+    if ok { Ok(()) } else { Err(format!("invalid revision: {}", rev)) }
+}
+
+/// A commit's metadata, full message and changes (merges diff against the first parent)
+pub fn show(path: &str, rev: &str) -> Result<Show, String> {
+    check_ref(rev)?;
+    let root = repo_root(path)?;
+    let meta = git(&root, &["show", "-s", &format!("--format={}%x1f%b", COMMIT_FORMAT), rev, "--"])?;
+    let (head, body) = meta.rsplit_once('\x1f').ok_or_else(|| format!("unexpected git show output for {}", rev))?;
+    let body = Some(body.trim().to_string()).filter(|b| !b.is_empty());
+    let commit = parse_commit(head, body).ok_or_else(|| format!("unexpected git show output for {}", rev))?;
+
+    let out = run_git(&root, &["show", "--format=", "--no-ext-diff", "-M", "--diff-merges=first-parent", rev, "--"], &[])?;
+    let mut budget = MAX_DIFF_BYTES;
+    let (text, truncated) = bounded(out, &mut budget);
+    let files = diff::parse_git(&text).into_iter().filter(|f| visible(&root, f)).collect();
+    Ok(Show { commit, files, truncated })
+}
+
+pub enum GitError {
+    /// The repo changed since the client read `state`; carries the current status
+    Conflict(Box<Status>),
+    Failed(String),
+}
+
+impl From<String> for GitError {
+    fn from(e: String) -> Self {
+        GitError::Failed(e)
+    }
+}
+
+// Serializes state check + write against our own concurrent writes
+static WRITE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Run `write` in the repo containing `path` if its state still matches
+/// `expected`, then return the new status
+fn guarded<T>(
+    path: &str,
+    expected: &str,
+    write: impl FnOnce(&Path) -> Result<T, String>,
+) -> Result<(T, Status), GitError> {
+    let _guard = WRITE_LOCK.lock().unwrap();
+    let current = status(path)?;
+    if current.state != expected {
+        return Err(GitError::Conflict(Box::new(current)));
+    }
+    sandbox::check(Path::new(&current.root), Op::Write)?;
+    let out = write(Path::new(&current.root))?;
+    Ok((out, status(path)?))
+}
+
+fn pathspecs(root: &Path, files: &[String]) -> Result<Vec<String>, String> {
+    if files.is_empty() {
+        return Err("files must not be empty".into());
+    }
+    files.iter().map(|f| relative(root, f, Op::Write)).collect()
+}
+
+fn with_files<'a>(args: &[&'a str], files: &'a [String]) -> Vec<&'a str> {
+    let mut v = args.to_vec();
+    v.push("--");
+    v.extend(files.iter().map(String::as_str));
+    v
+}
+
+pub fn stage(path: &str, files: &[String], expected: &str) -> Result<Status, GitError> {
+    guarded(path, expected, |root| {
+        let files = pathspecs(root, files)?;
+        git(root, &with_files(&["add", "-A"], &files)).map(drop)
+    }).map(|(_, s)| s)
+}
+
+pub fn unstage(path: &str, files: &[String], expected: &str) -> Result<Status, GitError> {
+    guarded(path, expected, |root| {
+        let files = pathspecs(root, files)?;
+        // Before the first commit there is no HEAD to restore from
+        let has_head = !run_git(root, &["rev-parse", "-q", "--verify", "HEAD"], &[1])?.is_empty();
+        let args: &[&str] = if has_head { &["restore", "--staged"] } else { &["rm", "--cached", "-r", "-q"] };
+        git(root, &with_files(args, &files)).map(drop)
+    }).map(|(_, s)| s)
+}
+
+/// Split raw `git diff` output for one file into its header and hunks
+fn raw_hunks(text: &str) -> (String, Vec<String>) {
+    let mut header = String::new();
+    let mut hunks: Vec<String> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("@@ ") {
+            hunks.push(String::new());
+        }
+        match hunks.last_mut() {
+            Some(h) => h.push_str(line),
+            None => header.push_str(line),
+        }
+    }
+    (header, hunks)
+}
+
+/// Header for applying a single hunk of `file`. A rename or copy header is
+/// replaced by a plain one for the new path, so the hunk changes that file
+/// in place instead of moving it back.
+fn hunk_header(header: &str) -> String {
+    let paired = header.lines().any(|l| l.starts_with("rename from ") || l.starts_with("copy from "));
+    let Some(new) = header.lines().find(|l| l.starts_with("+++ ")).filter(|_| paired) else {
+        return header.to_string();
+    };
+    let path = &new[4..];
+    let old = match path.strip_prefix("\"b/") {
+        Some(rest) => format!("\"a/{}", rest),
+        None => format!("a/{}", path.strip_prefix("b/").unwrap_or(path)),
+    };
+    format!("--- {}\n{}\n", old, new)
+}
+
+/// Stage one hunk of a file's unstaged diff, or with `unstage` remove one
+/// hunk of its staged diff from the index. `hunk` indexes the hunks as
+/// returned by `git_diff` with the same `context`.
+pub fn stage_hunk(
+    path: &str,
+    file: &str,
+    hunk: usize,
+    context: Option<usize>,
+    unstage: bool,
+    expected: &str,
+) -> Result<Status, GitError> {
+    guarded(path, expected, |root| {
+        let file = relative(root, file, Op::Write)?;
+        let unified = unified(context);
+        let specs = with_rename_source(root, &file, unstage)?;
+        let (header, hunks) = raw_hunks(&git(root, &diff_args(&unified, unstage, &specs))?);
+        let selected = hunks.get(hunk).ok_or_else(|| {
+            format!("{} has {} {} hunk(s), no hunk {}", file, hunks.len(), if unstage { "staged" } else { "unstaged" }, hunk)
+        })?;
+        let patch = format!("{}{}", hunk_header(&header), selected);
+
+        let mut apply = command(root);
+        apply.args(["apply", "--cached", "--recount", "--whitespace=nowarn"]);
+        if unstage {
+            apply.arg("-R");
+        }
+        let mut child = apply
+            .arg("-")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), patch.as_bytes())
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+        let output = child.wait_with_output().map_err(|e| format!("Failed to run git: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("git error: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    }).map(|(_, s)| s)
+}
+
+/// Commit what is staged; returns the new commit's hash
+pub fn commit(path: &str, message: &str, expected: &str) -> Result<(String, Status), GitError> {
+    if message.trim().is_empty() {
+        return Err(GitError::Failed("commit message must not be empty".into()));
+    }
+    guarded(path, expected, |root| {
+        git(root, &["commit", "-q", "-m", message])?;
+        git(root, &["rev-parse", "HEAD"]).map(|s| s.trim().to_string())
+    })
+}
+
+/// Throw away unstaged changes to tracked files (the staged version is kept)
+pub fn discard(path: &str, files: &[String], expected: &str) -> Result<Status, GitError> {
+    guarded(path, expected, |root| {
+        let files = pathspecs(root, files)?;
+        let untracked = git(root, &with_files(&["ls-files", "--others", "--exclude-standard"], &files))?;
+        if let Some(f) = untracked.lines().next() {
+            return Err(format!("{} is untracked; delete it instead", f));
+        }
+        git(root, &with_files(&["restore", "--worktree"], &files)).map(drop)
+    }).map(|(_, s)| s)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StashEntry {
+    pub index: usize,
+    pub hash: String,
+    pub message: String,
+    pub time: u64,
+}
+
+pub fn stash_list(path: &str) -> Result<Vec<StashEntry>, String> {
+    let root = repo_root(path)?;
+    let out = git(&root, &["stash", "list", "--format=%H%x1f%gs%x1f%ct"])?;
+    Ok(out.lines().enumerate().filter_map(|(index, line)| {
+        let mut f = line.splitn(3, '\x1f');
+        Some(StashEntry {
+            index,
+            hash: f.next()?.to_string(),
+            message: f.next()?.to_string(),
+            time: f.next()?.parse().unwrap_or(0),
+        })
+    }).collect())
+}
+
+/// `push` (optionally with a message and untracked files), `pop`, `apply` or `drop`
+pub fn stash(path: &str, action: &str, message: Option<&str>, index: usize, untracked: bool, expected: &str) -> Result<Status, GitError> {
+    let entry = format!("stash@{{{}}}", index);
+    let args: Vec<&str> = match action {
+        "push" => {
+            let mut a = vec!["stash", "push", "-q"];
+            if untracked {
+                a.push("--include-untracked");
+            }
+            if let Some(m) = message {
+                a.extend(["-m", m]);
+            }
+            a
+        }
+        "pop" | "apply" | "drop" => vec!["stash", action, "-q", entry.as_str()],
+        _ => return Err(GitError::Failed(format!("unknown stash action: {:?} (push, pop, apply, drop)", action))),
+    };
+    guarded(path, expected, |root| git(root, &args).map(drop)).map(|(_, s)| s)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub current: bool,
+    pub upstream: Option<String>,
+    pub time: u64,
+    pub subject: String,
+}
+
+/// Local branches, most recently committed first
+pub fn branches(path: &str) -> Result<Vec<BranchInfo>, String> {
+    let root = repo_root(path)?;
+    let out = git(&root, &[
+        "for-each-ref", "--sort=-committerdate",
+        "--format=%(refname:short)%1f%(HEAD)%1f%(upstream:short)%1f%(committerdate:unix)%1f%(contents:subject)",
+        "refs/heads",
+    ])?;
+    Ok(out.lines().filter_map(|line| {
+        let f: Vec<&str> = line.splitn(5, '\x1f').collect();
+        (f.len() == 5).then(|| BranchInfo {
+            name: f[0].to_string(),
+            current: f[1] == "*",
+            upstream: Some(f[2].to_string()).filter(|u| !u.is_empty()),
+            time: f[3].parse().unwrap_or(0),
+            subject: f[4].to_string(),
+        })
+    }).collect())
+}
+
+/// Switch to `branch`, creating it from HEAD with `create`. Fails (leaving
+/// the tree alone) if local changes would be overwritten.
+pub fn checkout(path: &str, branch: &str, create: bool, expected: &str) -> Result<Status, GitError> {
+    check_ref(branch)?;
+    guarded(path, expected, |root| {
+        git(root, &["check-ref-format", "--branch", branch]).map_err(|_| format!("invalid branch name: {}", branch))?;
+        let args: &[&str] = if create { &["switch", "-q", "-c", branch] } else { &["switch", "-q", branch] };
+        git(root, args).map(drop)
+    }).map(|(_, s)| s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(f: &StatusEntry) -> (&str, Option<&str>, Option<&str>, Option<&str>, bool) {
+        (f.path.as_str(), f.old_path.as_deref(), f.staged, f.unstaged, f.conflicted)
+    }
+
+    #[test]
+    fn parse_status_records() {
+        let raw = [
+            "# branch.oid 0123456789abcdef0123456789abcdef01234567",
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -5",
+            "1 .M N... 100644 100644 100644 aaaa bbbb src/lib.rs",
+            "1 A. N... 000000 100644 100644 0000 cccc with space.txt",
+            "1 MD N... 100644 100644 000000 dddd eeee both.rs",
+            "2 R. N... 100644 100644 100644 ffff ffff R100 new.rs",
+            "old.rs",
+            "u UU N... 100644 100644 100644 100644 1111 2222 3333 conflict.rs",
+            "? untracked file",
+            "! ignored.log",
+            "",
+        ]
+        .join("\0");
+        let (branch, files) = parse_status(&raw);
+        assert_eq!(branch.head.as_deref(), Some("main"));
+        assert_eq!(branch.oid.as_deref(), Some("0123456789abcdef0123456789abcdef01234567"));
+        assert_eq!(branch.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((branch.ahead, branch.behind), (2, 5));
+        let files: Vec<_> = files.iter().map(entry).collect();
+        assert_eq!(
+            files,
+            [
+                ("src/lib.rs", None, None, Some("modified"), false),
+                ("with space.txt", None, Some("added"), None, false),
+                ("both.rs", None, Some("modified"), Some("deleted"), false),
+                ("new.rs", Some("old.rs"), Some("renamed"), None, false),
+                ("conflict.rs", None, None, None, true),
+                ("untracked file", None, None, Some("untracked"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_status_fresh_and_detached() {
+        let (branch, files) = parse_status("# branch.oid (initial)\0# branch.head master\0");
+        assert_eq!((branch.oid, branch.head.as_deref()), (None, Some("master")));
+        assert_eq!((branch.upstream, branch.ahead, branch.behind), (None, 0, 0));
+        assert!(files.is_empty());
+
+        let (branch, _) = parse_status("# branch.oid abc\0# branch.head (detached)\0");
+        assert_eq!((branch.oid.as_deref(), branch.head), (Some("abc"), None));
+    }
+
+    #[test]
+    fn hunk_header_keeps_rename_in_place() {
+        let plain = "diff --git a/x b/x\nindex 1..2 100644\n--- a/x\n+++ b/x\n";
+        assert_eq!(hunk_header(plain), plain);
+        let renamed = "diff --git a/old b/new\nsimilarity index 90%\nrename from old\nrename to new\nindex 1..2 100644\n--- a/old\n+++ b/new\n";
+        assert_eq!(hunk_header(renamed), "--- a/new\n+++ b/new\n");
+        let quoted = "diff --git \"a/o\\tld\" \"b/n\\tew\"\ncopy from o\\tld\ncopy to n\\tew\n--- \"a/o\\tld\"\n+++ \"b/n\\tew\"\n";
+        assert_eq!(hunk_header(quoted), "--- \"a/n\\tew\"\n+++ \"b/n\\tew\"\n");
+    }
+
+    #[test]
+    fn rename_source_pairs_staged_renames() {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let body: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(dir.join("old name.txt"), &body).unwrap();
+        for args in [&["init", "-q"][..], &["add", "."], &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-qm", "init"]] {
+            git(&dir, args).unwrap();
+        }
+        git(&dir, &["mv", "old name.txt", "new name.txt"]).unwrap();
+        std::fs::write(dir.join("new name.txt"), body.replace("line 3\n", "line three\n")).unwrap();
+        git(&dir, &["add", "new name.txt"]).unwrap();
+
+        assert_eq!(with_rename_source(&dir, "new name.txt", true).unwrap(), ["new name.txt", "old name.txt"]);
+        assert_eq!(with_rename_source(&dir, "new name.txt", false).unwrap(), ["new name.txt"]);
+        // Limited to the new path, the diff still shows the rename
+        let specs = with_rename_source(&dir, "new name.txt", true).unwrap();
+        let files = diff::parse_git(&git(&dir, &diff_args("-U10", true, &specs)).unwrap());
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[0].status, files[0].old_path.as_deref()), ("renamed", Some("old name.txt")));
+        assert_eq!(files[0].hunks[0].lines.len(), 14, "10 lines of context");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status_hides_denied_files() {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("app")).unwrap();
+        for f in ["notes.txt", ".env", "app/.env.local"] {
+            std::fs::write(dir.join(f), "v1\n").unwrap();
+        }
+        for args in [&["init", "-q"][..], &["add", "."], &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-qm", "init"]] {
+            git(&dir, args).unwrap();
+        }
+        for f in ["notes.txt", ".env", "app/.env.local", "new.txt", ".env.new"] {
+            std::fs::write(dir.join(f), "v2\n").unwrap();
+        }
+        git(&dir, &["mv", "notes.txt", ".env.moved"]).unwrap();
+
+        let st = status(&dir.to_string_lossy()).unwrap();
+        let paths: Vec<_> = st.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["new.txt"], "modified, untracked and renamed-into .env files are hidden");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
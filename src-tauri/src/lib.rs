@@ -2,11 +2,35 @@ pub mod tmux;
 pub mod server;
 pub mod config;
 pub mod fs;
+pub mod fswatch;
+pub mod search;
+pub mod tree;
+pub mod du;
+pub mod text;
+pub mod mime;
+pub mod sandbox;
+pub mod trash;
+pub mod transfer;
+pub mod thumbnail;
+pub mod archive;
+pub mod triggers;
+pub mod watchers;
+pub mod pipe;
+pub mod recording;
+pub mod logs;
+pub mod replay;
+pub mod terminal;
+pub mod snippets;
+pub mod scheduler;
+pub mod wait;
+pub mod exec;
+pub mod diff;
+pub mod git;
 
 use config::Config;
 
 #[tauri::command]
-fn get_local_config() -> serde_json::Value {
+fn get_local_config() -> Result<serde_json::Value, String> {
     config::get_config_json()
 }
 
@@ -15,9 +39,9 @@ pub fn run() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![get_local_config])
         .setup(|_app| {
-            let cfg = Config::load();
+            let cfg = Config::load()?;
             tauri::async_runtime::spawn(async move {
-                if let Err(e) = server::start_with_socket(&cfg.host, cfg.port, &cfg.token, cfg.tmux_socket).await {
+                if let Err(e) = server::start_with_config(cfg).await {
                     eprintln!("Server error: {}", e);
                 }
             });
@@ -0,0 +1,558 @@
+/// Continuous per-pane output logs with size/age rotation, plus tail and search
+use crate::pipe;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, oneshot};
+
+fn default_max_bytes() -> u64 { 10 * 1024 * 1024 }
+fn default_keep() -> usize { 5 }
+fn default_max_age_days() -> u64 { 7 }
+fn default_strip_ansi() -> bool { true }
+
+/// `[logs]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogConfig {
+    pub dir: Option<String>,
+    /// Rotate the current file once it grows past this size
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// Number of rotated files kept per pane (`name.log.1` .. `name.log.N`)
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// The current file is rotated once it is this old, and rotated files
+    /// older than this are deleted
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
+    #[serde(default = "default_strip_ansi")]
+    pub strip_ansi: bool,
+    /// Panes logged from startup
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_bytes: default_max_bytes(),
+            keep: default_keep(),
+            max_age_days: default_max_age_days(),
+            strip_ansi: default_strip_ansi(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogFile {
+    pub file: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub active: bool,
+    pub files: Vec<LogFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogMatch {
+    pub file: String,
+    pub line_no: usize,
+    pub line: String,
+}
+
+struct Settings {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    max_age: Duration,
+    strip_ansi: bool,
+}
+
+struct Active {
+    name: String,
+    stop: oneshot::Sender<()>,
+}
+
+static SETTINGS: OnceLock<RwLock<Settings>> = OnceLock::new();
+static ACTIVE: OnceLock<Mutex<HashMap<String, Active>>> = OnceLock::new();
+
+fn settings() -> &'static RwLock<Settings> {
+    SETTINGS.get_or_init(|| RwLock::new(Settings::from(&LogConfig::default())))
+}
+
+fn active() -> &'static Mutex<HashMap<String, Active>> {
+    ACTIVE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+impl From<&LogConfig> for Settings {
+    fn from(cfg: &LogConfig) -> Self {
+        Settings {
+            dir: cfg.dir.as_deref().map(crate::config::expand_home)
+                .unwrap_or_else(|| crate::config::config_dir().join("logs")),
+            max_bytes: cfg.max_bytes.max(1024),
+            keep: cfg.keep,
+            max_age: Duration::from_secs(cfg.max_age_days * 24 * 3600),
+            strip_ansi: cfg.strip_ansi,
+        }
+    }
+}
+
+/// Apply config and start logging the configured panes
+pub fn init(cfg: LogConfig) {
+    *settings().write().unwrap() = Settings::from(&cfg);
+    for target in &cfg.targets {
+        if let Err(e) = start(target, None) {
+            eprintln!("⚠️  Cannot log {}: {}", target, e);
+        }
+    }
+}
+
+/// Removes terminal escape sequences and control characters from a stream.
+/// Keeps state so sequences split across chunks are handled.
+#[derive(Default)]
+pub struct AnsiStripper {
+    state: StripState,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum StripState {
+    #[default]
+    Text,
+    Esc,
+    Csi,
+    Str,    // OSC/DCS/APC/PM payload, ends with BEL or ESC \
+    StrEsc,
+    Charset,
+}
+
+impl AnsiStripper {
+    pub fn push(&mut self, input: &str) -> String {
+        use StripState::*;
+        let mut out = String::with_capacity(input.len());
+        for c in input.chars() {
+            self.state = match (self.state, c) {
+                (Text, '\x1b') => Esc,
+                (Text, '\n' | '\t') => { out.push(c); Text }
+                (Text, c) if c.is_control() => Text,
+                (Text, c) => { out.push(c); Text }
+                (Esc, '[') => Csi,
+                (Esc, ']' | 'P' | 'X' | '^' | '_') => Str,
+                (Esc, '(' | ')' | '*' | '+' | '#' | '%') => Charset,
+                (Esc, _) | (Charset, _) => Text,
+                (Csi, '\x40'..='\x7e') => Text,
+                (Csi, _) => Csi,
+                (Str, '\x07') => Text,
+                (Str, '\x1b') => StrEsc,
+                (Str, _) => Str,
+                (StrEsc, '\\') => Text,
+                (StrEsc, _) => Str,
+            };
+        }
+        out
+    }
+}
+
+fn log_name(target: &str) -> String {
+    pipe::sanitize_target(target)
+}
+
+/// Log names double as file names, so keep them to a safe charset
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("invalid log name: {}", name));
+    }
+    Ok(())
+}
+
+fn current_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.log", name))
+}
+
+fn rotated_path(dir: &Path, name: &str, n: usize) -> PathBuf {
+    dir.join(format!("{}.log.{}", name, n))
+}
+
+/// Files of a log, newest first: `name.log`, `name.log.1`, ...
+fn log_files(dir: &Path, name: &str, keep: usize) -> Vec<PathBuf> {
+    std::iter::once(current_path(dir, name))
+        .chain((1..=keep).map(|n| rotated_path(dir, name, n)))
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// Shift `name.log` -> `name.log.1` -> ... dropping the oldest
+fn rotate(dir: &Path, name: &str, keep: usize) {
+    if keep == 0 {
+        let _ = std::fs::remove_file(current_path(dir, name));
+        return;
+    }
+    let _ = std::fs::remove_file(rotated_path(dir, name, keep));
+    for n in (1..keep).rev() {
+        let _ = std::fs::rename(rotated_path(dir, name, n), rotated_path(dir, name, n + 1));
+    }
+    let _ = std::fs::rename(current_path(dir, name), rotated_path(dir, name, 1));
+}
+
+/// Delete rotated files past the age limit
+fn prune(dir: &Path, name: &str, keep: usize, max_age: Duration) {
+    for n in 1..=keep {
+        let path = rotated_path(dir, name, n);
+        let expired = std::fs::metadata(&path).ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age > max_age);
+        if expired {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Start logging a pane. Returns the log name used by `tail_log`/`search_log`.
+pub fn start(target: &str, strip_ansi: Option<bool>) -> Result<String, String> {
+    let mut logs = active().lock().unwrap();
+    if logs.contains_key(target) {
+        return Err(format!("{} is already being logged", target));
+    }
+    let (dir, strip) = {
+        let s = settings().read().unwrap();
+        (s.dir.clone(), strip_ansi.unwrap_or(s.strip_ansi))
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    let name = log_name(target);
+    if logs.values().any(|a| a.name == name) {
+        return Err(format!("log name {} is already in use", name));
+    }
+    let rx = pipe::subscribe(target)?;
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(write_log(target.to_string(), name.clone(), strip, rx, stop_rx));
+    logs.insert(target.to_string(), Active { name: name.clone(), stop: stop_tx });
+    Ok(name)
+}
+
+pub fn stop(target: &str) -> Result<(), String> {
+    let log = active().lock().unwrap().remove(target).ok_or_else(|| format!("{} is not being logged", target))?;
+    let _ = log.stop.send(());
+    Ok(())
+}
+
+async fn write_log(
+    target: String,
+    name: String,
+    strip: bool,
+    mut rx: broadcast::Receiver<pipe::Chunk>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let (dir, max_bytes, keep, max_age) = {
+        let s = settings().read().unwrap();
+        (s.dir.clone(), s.max_bytes, s.keep, s.max_age)
+    };
+    let path = current_path(&dir, &name);
+    prune(&dir, &name, keep, max_age);
+
+    let mut file: Option<tokio::fs::File> = None;
+    let existing = std::fs::metadata(&path).ok();
+    let mut size = existing.as_ref().map_or(0, |m| m.len());
+    // When the current file was started; a pane that logs all the time
+    // would otherwise never rotate by age
+    let mut started = existing.and_then(|m| m.created().ok()).unwrap_or_else(SystemTime::now);
+    let mut pending = Vec::new();
+    let mut stripper = AnsiStripper::default();
+    let mut prune_tick = tokio::time::interval(Duration::from_secs(600));
+    loop {
+        let chunk = tokio::select! {
+            _ = &mut stop => break,
+            _ = prune_tick.tick() => {
+                prune(&dir, &name, keep, max_age);
+                continue;
+            }
+            c = rx.recv() => c,
+        };
+        let chunk = match chunk {
+            Ok(c) => c,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        pending.extend_from_slice(&chunk.data);
+        let mut text = pipe::take_utf8(&mut pending);
+        if strip {
+            text = stripper.push(&text);
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let too_old = started.elapsed().is_ok_and(|age| age > max_age);
+        if size > 0 && (size + text.len() as u64 > max_bytes || too_old) {
+            file = None;
+            rotate(&dir, &name, keep);
+            prune(&dir, &name, keep, max_age);
+            size = 0;
+            started = SystemTime::now();
+        }
+        if file.is_none() {
+            match tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await {
+                Ok(f) => file = Some(f),
+                Err(e) => {
+                    eprintln!("❌ Cannot open log {}: {}", path.display(), e);
+                    break;
+                }
+            }
+        }
+        if let Some(f) = file.as_mut() {
+            if let Err(e) = f.write_all(text.as_bytes()).await {
+                eprintln!("❌ Write error on log {}: {}", path.display(), e);
+                break;
+            }
+        }
+        size += text.len() as u64;
+    }
+    pipe::release(&target);
+    drop(stop);
+    active().lock().unwrap().retain(|_, a| !a.stop.is_closed());
+}
+
+fn resolve_name(target: Option<&str>, name: Option<&str>) -> Result<String, String> {
+    let name = match (name, target) {
+        (Some(n), _) => n.to_string(),
+        (None, Some(t)) => log_name(t),
+        (None, None) => return Err("missing required param: target or name".into()),
+    };
+    check_name(&name)?;
+    Ok(name)
+}
+
+fn file_info(path: &Path) -> Option<LogFile> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some(LogFile {
+        file: path.file_name()?.to_string_lossy().to_string(),
+        size: meta.len(),
+        modified: meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()).unwrap_or(0),
+    })
+}
+
+/// Active logs plus any log files left on disk
+pub fn list() -> Result<Vec<LogInfo>, String> {
+    let (dir, keep) = {
+        let s = settings().read().unwrap();
+        (s.dir.clone(), s.keep)
+    };
+    let logs = active().lock().unwrap();
+    let mut names: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries.flatten()
+            .filter_map(|e| e.file_name().to_str()?.strip_suffix(".log").map(|n| n.to_string()))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("Cannot read {}: {}", dir.display(), e)),
+    };
+    names.extend(logs.values().map(|a| a.name.clone()));
+    names.sort();
+    names.dedup();
+    Ok(names.into_iter()
+        .map(|name| {
+            let target = logs.iter().find(|(_, a)| a.name == name).map(|(t, _)| t.clone());
+            LogInfo {
+                active: target.is_some(),
+                target,
+                files: log_files(&dir, &name, keep).iter().filter_map(|p| file_info(p)).collect(),
+                name,
+            }
+        })
+        .collect())
+}
+
+fn line_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches(['\n', '\r']).to_string()
+}
+
+/// The last `n` lines of a file, reading backwards from the end in blocks
+fn last_lines(path: &Path, n: usize) -> Result<Vec<String>, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("read error: {}", e))?;
+    let size = file.metadata().map_err(|e| format!("read error: {}", e))?.len();
+    let mut start = size;
+    let mut buf: Vec<u8> = Vec::new();
+    loop {
+        // A newline at the very end closes the last line, it doesn't start one
+        let body = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        let newlines = body.iter().filter(|&&b| b == b'\n').count();
+        if newlines >= n || start == 0 {
+            let lines: Vec<&[u8]> = body.split(|&b| b == b'\n').collect();
+            // Without the start of the file, the first piece is a partial line
+            let skip = lines.len().saturating_sub(n).max(usize::from(start > 0));
+            if body.is_empty() {
+                return Ok(Vec::new());
+            }
+            return Ok(lines[skip..].iter().map(|l| line_text(l)).collect());
+        }
+        let from = start.saturating_sub(64 * 1024);
+        let mut block = vec![0u8; (start - from) as usize];
+        file.seek(SeekFrom::Start(from))
+            .and_then(|_| file.read_exact(&mut block))
+            .map_err(|e| format!("read error: {}", e))?;
+        block.extend_from_slice(&buf);
+        buf = block;
+        start = from;
+    }
+}
+
+/// Last `n` lines of a log, continuing into rotated files if needed
+pub fn tail(target: Option<&str>, name: Option<&str>, n: usize) -> Result<Vec<String>, String> {
+    let name = resolve_name(target, name)?;
+    let (dir, keep) = {
+        let s = settings().read().unwrap();
+        (s.dir.clone(), s.keep)
+    };
+    let files = log_files(&dir, &name, keep);
+    if files.is_empty() {
+        return Err(format!("no log for {}", name));
+    }
+    let mut result: Vec<String> = Vec::new();
+    for path in files {
+        let mut lines = last_lines(&path, n - result.len())?;
+        lines.append(&mut result);
+        result = lines;
+        if result.len() >= n {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// Regex search over a log and its rotated files, most recent matches first.
+/// Files are streamed, keeping only the last matches that can still be returned.
+pub fn search(target: Option<&str>, name: Option<&str>, pattern: &str, max_results: usize) -> Result<(Vec<LogMatch>, bool), String> {
+    let name = resolve_name(target, name)?;
+    let re = Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+    let max_results = max_results.max(1);
+    let (dir, keep) = {
+        let s = settings().read().unwrap();
+        (s.dir.clone(), s.keep)
+    };
+    let mut matches = Vec::new();
+    for path in log_files(&dir, &name, keep) {
+        let room = max_results - matches.len();
+        if room == 0 {
+            return Ok((matches, true));
+        }
+        let file = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+        let reader = std::fs::File::open(&path).map(BufReader::new).map_err(|e| format!("read error: {}", e))?;
+        let mut last: VecDeque<LogMatch> = VecDeque::new();
+        let mut dropped = false;
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let line = line_text(&line.map_err(|e| format!("read error: {}", e))?);
+            if !re.is_match(&line) {
+                continue;
+            }
+            if last.len() == room {
+                last.pop_front();
+                dropped = true;
+            }
+            last.push_back(LogMatch { file: file.clone(), line_no: i + 1, line });
+        }
+        matches.extend(last.into_iter().rev());
+        if dropped {
+            return Ok((matches, true));
+        }
+    }
+    Ok((matches, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn strips_escapes_split_across_chunks() {
+        let mut s = AnsiStripper::default();
+        let out = s.push("\x1b[1;3") + &s.push("2mred\x1b[0m \x1b]0;title\x07ok\r\n\x1b(Bx\x1b]8;;u\x1b\\y");
+        assert_eq!(out, "red ok\nxy");
+    }
+
+    #[test]
+    fn last_lines_reads_backwards() {
+        let dir = temp_dir();
+        let path = dir.join("a.log");
+        let long: String = (0..20_000).map(|i| format!("line {}\n", i)).collect();
+        let cases: &[(&str, usize, &[&str])] = &[
+            ("", 3, &[]),
+            ("one", 3, &["one"]),
+            ("one\n", 3, &["one"]),
+            ("a\nb\nc\n", 2, &["b", "c"]),
+            ("a\r\nb\r\npartial", 2, &["b", "partial"]),
+            ("a\n\nb\n", 3, &["a", "", "b"]),
+        ];
+        for (content, n, want) in cases {
+            std::fs::write(&path, content).unwrap();
+            assert_eq!(last_lines(&path, *n).unwrap(), *want, "{:?}", content);
+        }
+        // Spans several 64KB blocks
+        std::fs::write(&path, &long).unwrap();
+        let lines = last_lines(&path, 15_000).unwrap();
+        assert_eq!(lines.len(), 15_000);
+        assert_eq!(lines[0], "line 5000");
+        assert_eq!(lines[14_999], "line 19999");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotation_shifts_and_drops_the_oldest() {
+        let dir = temp_dir();
+        for round in 0..4 {
+            std::fs::write(current_path(&dir, "p"), format!("{}\n", round)).unwrap();
+            rotate(&dir, "p", 2);
+        }
+        assert!(!current_path(&dir, "p").exists());
+        assert_eq!(std::fs::read_to_string(rotated_path(&dir, "p", 1)).unwrap(), "3\n");
+        assert_eq!(std::fs::read_to_string(rotated_path(&dir, "p", 2)).unwrap(), "2\n");
+        assert!(!rotated_path(&dir, "p", 3).exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn tail_and_search_span_rotated_files() {
+        let dir = temp_dir();
+        *settings().write().unwrap() = Settings::from(&LogConfig {
+            dir: Some(dir.to_string_lossy().to_string()),
+            ..LogConfig::default()
+        });
+        std::fs::write(rotated_path(&dir, "s_0_0", 2), "old 1\nerr A\n").unwrap();
+        std::fs::write(rotated_path(&dir, "s_0_0", 1), "err B\nmid\nerr C\n").unwrap();
+        std::fs::write(current_path(&dir, "s_0_0"), "err D\nnew").unwrap();
+
+        assert_eq!(tail(Some("s:0.0"), None, 3).unwrap(), ["err C", "err D", "new"]);
+        assert_eq!(tail(None, Some("s_0_0"), 100).unwrap().len(), 7);
+        assert!(tail(None, Some("../x"), 1).is_err(), "invalid name");
+        assert!(tail(None, Some("missing"), 1).is_err());
+
+        let (found, truncated) = search(None, Some("s_0_0"), "^err", 10).unwrap();
+        let lines: Vec<&str> = found.iter().map(|m| m.line.as_str()).collect();
+        assert_eq!(lines, ["err D", "err C", "err B", "err A"]);
+        assert!(!truncated);
+        assert_eq!((found[1].file.as_str(), found[1].line_no), ("s_0_0.log.1", 3));
+
+        let (found, truncated) = search(None, Some("s_0_0"), "^err", 2).unwrap();
+        let lines: Vec<&str> = found.iter().map(|m| m.line.as_str()).collect();
+        assert_eq!(lines, ["err D", "err C"]);
+        assert!(truncated);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
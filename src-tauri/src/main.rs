@@ -111,4 +111,14 @@ mod tests {
         println!("✅ Scrollback capture works");
         cleanup();
     }
+
+    #[test]
+    fn t08_new_lines() {
+        let prev = "$ make\nbuilding\n$ ";
+        let next = "$ make\nbuilding\n$ cargo test\nrunning 3 tests\n";
+        assert_eq!(tmux::new_lines(prev, next), vec!["$ cargo test", "running 3 tests"]);
+        assert!(tmux::new_lines(next, next).is_empty());
+        assert_eq!(tmux::new_lines("old\nscreen", "fresh"), vec!["fresh"]);
+        println!("✅ New output detection works");
+    }
 }
@@ -0,0 +1,376 @@
+/// File type detection: magic bytes first, then well-known names and
+/// extensions, then a look at whether the start of the file is text
+use crate::fs::{ContentCache, FileKey};
+use crate::text::{self, Encoding, LineStats};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes read from the start of a file to classify it
+const HEAD: usize = 8192;
+/// Line count and line endings are only worked out for files up to this size
+const MAX_LINE_SCAN: u64 = 16 * 1024 * 1024;
+
+static LINE_STATS: ContentCache<LineStats> = ContentCache::new();
+
+/// What a file is, from its name and first bytes
+#[derive(Debug, Clone)]
+pub struct Sniffed {
+    pub mime: &'static str,
+    pub text: Option<TextInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextInfo {
+    pub charset: &'static str,
+    /// `lf`, `crlf`, `cr`, `mixed` or `none`; None past MAX_LINE_SCAN or
+    /// when the file can't be read to the end
+    pub line_ending: Option<&'static str>,
+    pub line_count: Option<usize>,
+}
+
+/// Files known by their whole name rather than an extension
+fn by_file_name(name: &str) -> Option<&'static str> {
+    let mime = match name {
+        "Dockerfile" | "Containerfile" => "text/dockerfile",
+        "Makefile" | "makefile" | "GNUmakefile" => "text/makefile",
+        "Gemfile" | "Rakefile" | "Vagrantfile" | "Podfile" | "Brewfile" => "text/ruby",
+        "Jenkinsfile" => "text/groovy",
+        "CMakeLists.txt" => "text/cmake",
+        ".bashrc" | ".bash_profile" | ".bash_logout" | ".profile" | ".zshrc" | ".zprofile" | ".zshenv" => "text/shell",
+        ".gitignore" | ".gitattributes" | ".gitmodules" | ".dockerignore" | ".editorconfig" | ".npmrc"
+        | ".env" | ".tmux.conf" | "LICENSE" | "COPYING" | "AUTHORS" | "README" | "CHANGELOG" | "NOTICE"
+        | "Procfile" | "CODEOWNERS" => "text/plain",
+        "Cargo.lock" | "poetry.lock" => "application/toml",
+        _ if name.starts_with("Dockerfile.") || name.ends_with(".dockerfile") => "text/dockerfile",
+        _ if name.starts_with(".env.") => "text/plain",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+fn by_extension(name: &str) -> Option<&'static str> {
+    let ext = Path::new(name).extension()?.to_str()?.to_lowercase();
+    let mime = match ext.as_str() {
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "tsv" => "text/tab-separated-values",
+        "json" | "jsonl" | "ndjson" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/yaml",
+        "xml" | "html" | "htm" => "text/html",
+        "js" | "mjs" | "cjs" | "jsx" => "text/javascript",
+        "ts" | "tsx" | "mts" | "cts" => "text/typescript",
+        "rs" => "text/rust",
+        "py" | "pyi" => "text/python",
+        "rb" => "text/ruby",
+        "go" => "text/go",
+        "java" => "text/java",
+        "c" | "h" => "text/c",
+        "cpp" | "cc" | "cxx" | "hpp" => "text/cpp",
+        "css" | "scss" | "less" => "text/css",
+        "sh" | "bash" | "zsh" | "fish" => "text/shell",
+        "sql" => "text/sql",
+        "svelte" => "text/svelte",
+        "vue" => "text/vue",
+        "mk" => "text/makefile",
+        "diff" | "patch" => "text/x-diff",
+        "txt" | "log" | "env" | "ini" | "cfg" | "conf" | "lock" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "tif" | "tiff" => "image/tiff",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "jar" => "application/java-archive",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "bz2" => "application/x-bzip2",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "sqlite" | "sqlite3" | "db" => "application/vnd.sqlite3",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Type by name alone
+pub fn by_name(name: &str) -> Option<&'static str> {
+    let base = Path::new(name).file_name()?.to_str()?;
+    by_file_name(base).or_else(|| by_extension(base))
+}
+
+/// Type by signature, for formats that have a reliable one
+pub fn by_magic(head: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, sig: &[u8]| head.get(offset..offset + sig.len()) == Some(sig);
+    let mime = if at(0, b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if at(0, b"\xFF\xD8\xFF") {
+        "image/jpeg"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        "image/tiff"
+    } else if at(0, b"\0\0\x01\0") && head.len() > 6 {
+        "image/x-icon"
+    } else if at(4, b"ftyp") {
+        match head.get(8..12) {
+            Some(b"avif") | Some(b"avis") => "image/avif",
+            Some(b"heic") | Some(b"heix") | Some(b"mif1") => "image/heic",
+            Some(b"qt  ") => "video/quicktime",
+            _ => "video/mp4",
+        }
+    } else if at(0, b"\x1A\x45\xDF\xA3") {
+        "video/webm"
+    } else if at(0, b"OggS") {
+        "audio/ogg"
+    } else if at(0, b"fLaC") {
+        "audio/flac"
+    } else if at(0, b"ID3") {
+        "audio/mpeg"
+    } else if at(0, b"%PDF-") {
+        "application/pdf"
+    } else if at(0, b"\x7FELF") {
+        "application/x-elf"
+    } else if at(0, b"\xCF\xFA\xED\xFE") || at(0, b"\xCE\xFA\xED\xFE") {
+        "application/x-mach-binary"
+    } else if at(0, b"MZ") && pe_offset(head).is_some_and(|o| at(o, b"PE\0\0")) {
+        "application/vnd.microsoft.portable-executable"
+    } else if at(0, b"\0asm") {
+        "application/wasm"
+    } else if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") {
+        "application/zip"
+    } else if at(0, b"\x1F\x8B") {
+        "application/gzip"
+    } else if at(0, b"BZh") && head.get(3).is_some_and(|b| (b'1'..=b'9').contains(b)) {
+        "application/x-bzip2"
+    } else if at(0, b"\xFD7zXZ\0") {
+        "application/x-xz"
+    } else if at(0, b"\x28\xB5\x2F\xFD") {
+        "application/zstd"
+    } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+        "application/x-7z-compressed"
+    } else if at(257, b"ustar") {
+        "application/x-tar"
+    } else if at(0, b"SQLite format 3\0") {
+        "application/vnd.sqlite3"
+    } else {
+        return None;
+    };
+    Some(mime)
+}
+
+/// Where a DOS header says the PE header starts
+fn pe_offset(head: &[u8]) -> Option<usize> {
+    let field = head.get(0x3C..0x40)?;
+    Some(u32::from_le_bytes([field[0], field[1], field[2], field[3]]) as usize)
+}
+
+/// Types whose content is text, whatever the top-level type says
+fn is_text_mime(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(mime, "application/json" | "application/toml" | "application/yaml" | "image/svg+xml")
+}
+
+/// Whether the start of a file looks like text in `enc`: no NULs and
+/// hardly any control characters
+fn looks_like_text(head: &[u8], enc: Encoding) -> bool {
+    if matches!(enc, Encoding::Utf16Le | Encoding::Utf16Be) {
+        // detect() only picks UTF-16 for a BOM or a regular zero pattern
+        return true;
+    }
+    let control = |b: u8| {
+        matches!(b, 0..=8 | 0x0E..=0x1A | 0x1C..=0x1F | 0x7F)
+            || (enc == Encoding::Latin1 && (0x80..0xA0).contains(&b))
+    };
+    !head.contains(&0) && head.iter().filter(|&&b| control(b)).count() * 20 <= head.len()
+}
+
+/// Interpreter named on a `#!` line
+fn by_shebang(head: &[u8]) -> Option<&'static str> {
+    let line = head.strip_prefix(b"#!")?;
+    let line = &line[..line.iter().position(|&b| b == b'\n').unwrap_or(line.len())];
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split_whitespace();
+    let mut prog = words.next()?.rsplit('/').next()?;
+    if prog == "env" {
+        prog = words.find(|w| !w.starts_with('-'))?;
+    }
+    let mime = match prog.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish" => "text/shell",
+        "python" => "text/python",
+        "node" | "deno" | "bun" => "text/javascript",
+        "ruby" => "text/ruby",
+        _ => "text/plain",
+    };
+    Some(mime)
+}
+
+fn read_head(file: &mut File) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEAD);
+    file.take(HEAD as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Classify a regular file, reading no more than its first few KB unless
+/// it is text small enough to count lines in
+pub fn sniff(path: &Path) -> std::io::Result<Sniffed> {
+    let mut file = File::open(path)?;
+    let meta = file.metadata()?;
+    let size = meta.len();
+    let head = read_head(&mut file)?;
+    let named = path.file_name().and_then(|n| n.to_str()).and_then(by_name);
+    if let Some(magic) = by_magic(&head) {
+        // Containers like .docx and .jar are zips with a more specific name
+        let mime = match named {
+            Some(n) if magic == "application/zip" && !is_text_mime(n) => n,
+            _ => magic,
+        };
+        return Ok(Sniffed { mime, text: None });
+    }
+    let (enc, bom) = text::detect(&head);
+    if !looks_like_text(&head[bom..], enc) {
+        let mime = named.filter(|n| !is_text_mime(n)).unwrap_or("application/octet-stream");
+        return Ok(Sniffed { mime, text: None });
+    }
+    let mime = named.filter(|n| is_text_mime(n))
+        .or_else(|| by_shebang(&head[bom..]))
+        .unwrap_or("text/plain");
+    // The scan reads the whole file, so it is done once per version of it
+    let scan = || {
+        file.seek(SeekFrom::Start(bom as u64)).ok()?;
+        text::line_stats(&mut file, enc).ok()
+    };
+    let stats = if size <= MAX_LINE_SCAN { LINE_STATS.get_or_compute(FileKey::of(&meta), scan) } else { None };
+    Ok(Sniffed {
+        mime,
+        text: Some(TextInfo {
+            charset: enc.name(),
+            line_ending: stats.map(|s| s.ending),
+            line_count: stats.map(|s| s.lines),
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_stats_follow_edits() {
+        let p = std::env::temp_dir().join(format!("tmux-mobile-test-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&p, "a\r\nb\r\n").unwrap();
+        let lines = |p: &Path| {
+            let t = sniff(p).unwrap().text.unwrap();
+            (t.line_count, t.line_ending)
+        };
+        assert_eq!(lines(&p), (Some(2), Some("crlf")));
+        assert_eq!(lines(&p), (Some(2), Some("crlf")));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&p, "a\nb\nc").unwrap();
+        assert_eq!(lines(&p), (Some(3), Some("lf")));
+        std::fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn magic_signatures() {
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some("image/png")),
+            (b"%PDF-1.7\n", Some("application/pdf")),
+            (b"\x7FELF\x02\x01\x01", Some("application/x-elf")),
+            (b"PK\x03\x04\x14\0", Some("application/zip")),
+            (b"PK\x05\x06", Some("application/zip")),
+            (b"\x1F\x8B\x08\0", Some("application/gzip")),
+            (b"SQLite format 3\0\x10\0", Some("application/vnd.sqlite3")),
+            (b"SQLite format 2", None),
+            (&tar, Some("application/x-tar")),
+            (b"\x89PN", None),
+            (b"", None),
+            (b"hello world", None),
+        ];
+        for (head, mime) in cases {
+            assert_eq!(by_magic(head), *mime, "{:?}", String::from_utf8_lossy(&head[..head.len().min(16)]));
+        }
+    }
+
+    #[test]
+    fn names_and_extensions() {
+        let cases = [
+            ("Dockerfile", Some("text/dockerfile")),
+            ("Dockerfile.dev", Some("text/dockerfile")),
+            ("api.dockerfile", Some("text/dockerfile")),
+            ("Makefile", Some("text/makefile")),
+            ("GNUmakefile", Some("text/makefile")),
+            ("rules.mk", Some("text/makefile")),
+            ("/srv/app/.env.local", Some("text/plain")),
+            ("Cargo.lock", Some("application/toml")),
+            ("photo.JPG", Some("image/jpeg")),
+            ("makefile.bak", None),
+            ("dockerfile", None),
+            ("noext", None),
+        ];
+        for (name, mime) in cases {
+            assert_eq!(by_name(name), mime, "{}", name);
+        }
+    }
+
+    #[test]
+    fn shebang_interpreters() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"#!/bin/sh\necho", Some("text/shell")),
+            (b"#!/usr/bin/env bash", Some("text/shell")),
+            (b"#!/usr/bin/env -S python3 -u\n", Some("text/python")),
+            (b"#!/usr/bin/python3.12\n", Some("text/python")),
+            (b"#!/usr/bin/env node\n", Some("text/javascript")),
+            (b"#!/usr/bin/perl -w\n", Some("text/plain")),
+            (b"#!\n", None),
+            (b"# !/bin/sh\n", None),
+        ];
+        for (head, mime) in cases {
+            assert_eq!(by_shebang(head), *mime, "{}", String::from_utf8_lossy(head));
+        }
+    }
+
+    #[test]
+    fn text_or_binary() {
+        let utf16: Vec<u8> = "\u{feff}hello\nworld\n".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let mut bin = b"text".to_vec();
+        bin.extend((0u8..=255).cycle().take(512));
+        let cases: &[(&[u8], bool)] = &[
+            (b"plain ascii\n", true),
+            ("caf\u{e9} \u{4e2d}\u{6587}\n".as_bytes(), true),
+            (b"caf\xe9 latin-1\n", true),
+            (&utf16, true),
+            (b"nul\0byte", false),
+            (b"\x01\x02\x03\x04 mostly control", false),
+            (&bin, false),
+        ];
+        for (head, text) in cases {
+            let (enc, bom) = text::detect(head);
+            assert_eq!(looks_like_text(&head[bom..], enc), *text, "{:?} as {:?}", String::from_utf8_lossy(head), enc);
+        }
+        assert_eq!(text::detect(&utf16).0, Encoding::Utf16Le);
+    }
+}
@@ -0,0 +1,188 @@
+/// Pane output taps: one `tmux pipe-pane` per pane, fanned out to any number
+/// of consumers (recordings, logs) through a broadcast channel
+use crate::tmux;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::net::unix::pipe;
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A piece of raw pane output, timestamped when it was read
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub time: SystemTime,
+    pub data: Arc<Vec<u8>>,
+}
+
+struct Tap {
+    users: usize,
+    tx: broadcast::Sender<Chunk>,
+    task: tokio::task::JoinHandle<()>,
+    fifo: PathBuf,
+}
+
+static TAPS: OnceLock<Mutex<HashMap<String, Tap>>> = OnceLock::new();
+
+fn taps() -> &'static Mutex<HashMap<String, Tap>> {
+    TAPS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn spool_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("tmux-mobile-{}", std::process::id()))
+}
+
+/// Quote a string for /bin/sh
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// File-name-safe form of a pane target, e.g. `agent:0.1` -> `agent_0_1`
+pub fn sanitize_target(target: &str) -> String {
+    target.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// Decode as much of `buf` as possible, keeping an incomplete trailing UTF-8
+/// sequence for the next chunk. Invalid bytes are replaced.
+pub fn take_utf8(buf: &mut Vec<u8>) -> String {
+    let mut split = buf.len();
+    for i in (buf.len().saturating_sub(3)..buf.len()).rev() {
+        let b = buf[i];
+        if b & 0xC0 == 0x80 {
+            continue; // continuation byte, keep looking for the lead byte
+        }
+        let need = if b >= 0xF0 { 4 } else if b >= 0xE0 { 3 } else if b >= 0xC0 { 2 } else { 1 };
+        if buf.len() - i < need {
+            split = i;
+        }
+        break;
+    }
+    let rest = buf.split_off(split);
+    let text = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    text
+}
+
+/// Subscribe to the output stream of a pane, starting `pipe-pane` on first use.
+/// Every successful call must be paired with `release`.
+pub fn subscribe(target: &str) -> Result<broadcast::Receiver<Chunk>, String> {
+    let mut taps = taps().lock().unwrap();
+    if let Some(tap) = taps.get_mut(target) {
+        tap.users += 1;
+        return Ok(tap.tx.subscribe());
+    }
+
+    let dir = spool_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    let fifo = dir.join(format!("{}.fifo", uuid::Uuid::new_v4()));
+    make_fifo(&fifo)?;
+
+    let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+    let task = tokio::spawn(pump(fifo.clone(), tx.clone()));
+    if let Err(e) = tmux::pipe_pane(target, Some(&format!("cat > {}", shell_quote(&fifo.to_string_lossy())))) {
+        task.abort();
+        let _ = std::fs::remove_file(&fifo);
+        return Err(e);
+    }
+    taps.insert(target.to_string(), Tap { users: 1, tx, task, fifo });
+    Ok(rx)
+}
+
+/// Drop one consumer; the pipe is closed when the last one goes away
+pub fn release(target: &str) {
+    let mut taps = taps().lock().unwrap();
+    let Some(tap) = taps.get_mut(target) else { return };
+    tap.users -= 1;
+    if tap.users > 0 {
+        return;
+    }
+    if let Some(tap) = taps.remove(target) {
+        let _ = tmux::pipe_pane(target, None);
+        tap.task.abort();
+        let _ = std::fs::remove_file(&tap.fifo);
+    }
+}
+
+fn make_fifo(path: &Path) -> Result<(), String> {
+    let status = std::process::Command::new("mkfifo")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to run mkfifo: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("mkfifo failed for {}", path.display()))
+    }
+}
+
+// Read the FIFO that `pipe-pane` writes into and broadcast each chunk
+async fn pump(fifo: PathBuf, tx: broadcast::Sender<Chunk>) {
+    let mut rx = match pipe::OpenOptions::new().open_receiver(&fifo) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("❌ Cannot open {}: {}", fifo.display(), e);
+            return;
+        }
+    };
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match rx.read(&mut buf).await {
+            // No writer attached (yet, or pipe-pane restarted)
+            Ok(0) => tokio::time::sleep(Duration::from_millis(50)).await,
+            Ok(n) => {
+                let _ = tx.send(Chunk { time: SystemTime::now(), data: Arc::new(buf[..n].to_vec()) });
+            }
+            Err(e) => {
+                eprintln!("❌ Pane pipe read error: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_utf8_keeps_partial_sequences() {
+        let cases: &[(&[u8], &str, &[u8])] = &[
+            (b"plain", "plain", b""),
+            (b"caf\xC3", "caf", b"\xC3"),                  // é cut after its lead byte
+            (b"caf\xC3\xA9", "café", b""),
+            (b"\xE2\x82", "", b"\xE2\x82"),                // € missing its last byte
+            (b"1\xE2\x82\xAC", "1€", b""),
+            (b"x\xF0\x9F\x98", "x", b"\xF0\x9F\x98"),      // 😀 missing one byte
+            (b"x\xF0\x9F\x98\x80", "x😀", b""),
+            (b"bad\xFFend", "bad\u{FFFD}end", b""),        // invalid bytes are replaced
+            (b"\x80", "\u{FFFD}", b""),                    // a stray continuation byte too
+        ];
+        for (input, text, rest) in cases {
+            let mut buf = input.to_vec();
+            assert_eq!(take_utf8(&mut buf), *text, "input {:?}", input);
+            assert_eq!(buf, *rest, "leftover for {:?}", input);
+        }
+    }
+
+    #[test]
+    fn take_utf8_across_chunks() {
+        let mut buf = Vec::new();
+        let mut out = String::new();
+        for chunk in "añ€😀z".as_bytes().chunks(1) {
+            buf.extend_from_slice(chunk);
+            out.push_str(&take_utf8(&mut buf));
+        }
+        assert_eq!(out, "añ€😀z");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn quoting_and_names() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(sanitize_target("agent:0.1"), "agent_0_1");
+    }
+}
@@ -0,0 +1,345 @@
+/// Session recordings: pane output saved as asciicast v2 `.cast` files
+use crate::pipe;
+use crate::tmux;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, oneshot};
+
+const MAX_FETCH_BYTES: usize = 8 * 1024 * 1024; // per get_recording response
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingInfo {
+    pub id: String,
+    pub title: String,
+    pub width: u64,
+    pub height: u64,
+    pub timestamp: u64, // unix seconds when recording started
+    pub duration: f64,  // seconds, up to the last event
+    pub size: u64,
+    pub active: bool,
+}
+
+struct Active {
+    target: String,
+    stop: oneshot::Sender<()>,
+}
+
+static DIR: OnceLock<RwLock<PathBuf>> = OnceLock::new();
+static ACTIVE: OnceLock<Mutex<HashMap<String, Active>>> = OnceLock::new();
+
+fn dir_lock() -> &'static RwLock<PathBuf> {
+    DIR.get_or_init(|| RwLock::new(crate::config::config_dir().join("recordings")))
+}
+
+fn active() -> &'static Mutex<HashMap<String, Active>> {
+    ACTIVE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Set the directory recordings are written to
+pub fn init(dir: PathBuf) {
+    *dir_lock().write().unwrap() = dir;
+}
+
+fn recordings_dir() -> PathBuf {
+    dir_lock().read().unwrap().clone()
+}
+
+/// Recording ids double as file names, so keep them to a safe charset
+fn cast_path(id: &str) -> Result<PathBuf, String> {
+    let valid = !id.is_empty()
+        && !id.starts_with('.')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!("invalid recording id: {}", id));
+    }
+    Ok(recordings_dir().join(format!("{}.cast", id)))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Start recording a pane. Only one recording per pane at a time.
+pub fn start(target: &str, title: Option<&str>) -> Result<RecordingInfo, String> {
+    let mut recs = active().lock().unwrap();
+    if let Some((id, _)) = recs.iter().find(|(_, a)| a.target == target) {
+        return Err(format!("{} is already being recorded as {}", target, id));
+    }
+    let (width, height) = tmux::pane_size(target)?;
+    let dir = recordings_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+
+    let timestamp = unix_now();
+    let base = format!("{}-{}", pipe::sanitize_target(target), timestamp);
+    let mut id = base.clone();
+    let mut n = 1;
+    while cast_path(&id)?.exists() {
+        n += 1;
+        id = format!("{}-{}", base, n);
+    }
+    let path = cast_path(&id)?;
+    let title = title.unwrap_or(target).to_string();
+    std::fs::write(&path, header_line(width as u64, height as u64, timestamp, &title)).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+
+    let rx = match pipe::subscribe(target) {
+        Ok(rx) => rx,
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+    };
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tokio::spawn(write_events(path, target.to_string(), rx, stop_rx));
+    recs.insert(id.clone(), Active { target: target.to_string(), stop: stop_tx });
+
+    Ok(RecordingInfo {
+        id, title, width: width as u64, height: height as u64, timestamp,
+        duration: 0.0, size: 0, active: true,
+    })
+}
+
+/// Stop a recording by id, or the recording of a pane target
+pub fn stop(id: Option<&str>, target: Option<&str>) -> Result<String, String> {
+    let mut recs = active().lock().unwrap();
+    let id = match (id, target) {
+        (Some(id), _) => id.to_string(),
+        (None, Some(t)) => recs.iter().find(|(_, a)| a.target == t).map(|(id, _)| id.clone())
+            .ok_or_else(|| format!("{} is not being recorded", t))?,
+        (None, None) => return Err("missing required param: id or target".into()),
+    };
+    let rec = recs.remove(&id).ok_or_else(|| format!("no active recording: {}", id))?;
+    let _ = rec.stop.send(());
+    Ok(id)
+}
+
+/// First line of a `.cast` file
+fn header_line(width: u64, height: u64, timestamp: u64, title: &str) -> String {
+    let header = serde_json::json!({
+        "version": 2,
+        "width": width,
+        "height": height,
+        "timestamp": timestamp,
+        "title": title,
+        "env": { "TERM": "xterm-256color" },
+    });
+    format!("{}\n", header)
+}
+
+/// One output event line, with the time rounded to microseconds
+fn event_line(t: f64, text: &str) -> String {
+    let t = (t * 1e6).round() / 1e6;
+    format!("{}\n", serde_json::json!([t, "o", text]))
+}
+
+// Append `[time, "o", data]` events until stopped or the tap closes
+async fn write_events(
+    path: PathBuf,
+    target: String,
+    mut rx: broadcast::Receiver<pipe::Chunk>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let started = SystemTime::now();
+    let file = tokio::fs::OpenOptions::new().append(true).open(&path).await;
+    let mut file = match file {
+        Ok(f) => tokio::io::BufWriter::new(f),
+        Err(e) => {
+            eprintln!("❌ Cannot open recording {}: {}", path.display(), e);
+            pipe::release(&target);
+            return;
+        }
+    };
+    let mut pending = Vec::new();
+    loop {
+        let chunk = tokio::select! {
+            _ = &mut stop => break,
+            c = rx.recv() => c,
+        };
+        let chunk = match chunk {
+            Ok(c) => c,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                eprintln!("⚠️  Recording {} dropped {} chunks", path.display(), n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        pending.extend_from_slice(&chunk.data);
+        let text = pipe::take_utf8(&mut pending);
+        if text.is_empty() {
+            continue;
+        }
+        let t = chunk.time.duration_since(started).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        if file.write_all(event_line(t, &text).as_bytes()).await.is_err() || file.flush().await.is_err() {
+            eprintln!("❌ Write error on recording {}", path.display());
+            break;
+        }
+    }
+    let _ = file.flush().await;
+    pipe::release(&target);
+    // If we stopped on our own (write error, tap closed), forget the entry
+    drop(stop);
+    active().lock().unwrap().retain(|_, a| !a.stop.is_closed());
+}
+
+/// Time of the last event, read from the end of the file
+fn last_event_time(file: &mut std::fs::File, size: u64) -> f64 {
+    let start = size.saturating_sub(64 * 1024);
+    let mut tail = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut tail).is_err() {
+        return 0.0;
+    }
+    String::from_utf8_lossy(&tail).lines().rev()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .find_map(|v| v.get(0).and_then(|t| t.as_f64()))
+        .unwrap_or(0.0)
+}
+
+fn read_info(id: &str, path: &PathBuf, is_active: bool) -> Option<RecordingInfo> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut header = String::new();
+    BufReader::new(&mut file).read_line(&mut header).ok()?;
+    let header: serde_json::Value = serde_json::from_str(&header).ok()?;
+    Some(RecordingInfo {
+        id: id.to_string(),
+        title: header["title"].as_str().unwrap_or("").to_string(),
+        width: header["width"].as_u64().unwrap_or(0),
+        height: header["height"].as_u64().unwrap_or(0),
+        timestamp: header["timestamp"].as_u64().unwrap_or(0),
+        duration: last_event_time(&mut file, size),
+        size,
+        active: is_active,
+    })
+}
+
+/// All recordings, newest first
+pub fn list() -> Result<Vec<RecordingInfo>, String> {
+    let dir = recordings_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Cannot read {}: {}", dir.display(), e)),
+    };
+    let recs = active().lock().unwrap();
+    let mut result: Vec<RecordingInfo> = entries.flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let id = path.file_name()?.to_str()?.strip_suffix(".cast")?.to_string();
+            read_info(&id, &path, recs.contains_key(&id))
+        })
+        .collect();
+    result.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+    Ok(result)
+}
+
+type Lines = std::io::Lines<BufReader<std::fs::File>>;
+
+/// Parsed header and the remaining (event) lines of a recording
+fn open(id: &str) -> Result<(serde_json::Value, Lines), String> {
+    let path = cast_path(id)?;
+    let file = std::fs::File::open(&path).map_err(|e| format!("Cannot open recording {}: {}", id, e))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next()
+        .and_then(|l| l.ok())
+        .and_then(|l| serde_json::from_str(&l).ok())
+        .ok_or_else(|| format!("invalid recording header: {}", id))?;
+    Ok((header, lines))
+}
+
+/// Header and all output events as `(time, data)`, for replay
+pub fn load_output(id: &str) -> Result<(serde_json::Value, Vec<(f64, String)>), String> {
+    let (header, lines) = open(id)?;
+    let mut events = Vec::new();
+    for line in lines {
+        let line = line.map_err(|e| format!("read error: {}", e))?;
+        let Ok((t, kind, data)) = serde_json::from_str::<(f64, String, String)>(&line) else { continue };
+        if kind == "o" {
+            events.push((t, data));
+        }
+    }
+    Ok((header, events))
+}
+
+/// Header and events of a recording, optionally limited to `[start, end]` seconds.
+/// Large ranges are cut off; `next` is then the time to continue from.
+pub fn get(id: &str, start: Option<f64>, end: Option<f64>) -> Result<serde_json::Value, String> {
+    let (header, lines) = open(id)?;
+    let mut events = Vec::new();
+    let mut bytes = 0;
+    let mut next = None;
+    for line in lines {
+        let line = line.map_err(|e| format!("read error: {}", e))?;
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(&line) else { continue };
+        let t = event.get(0).and_then(|t| t.as_f64()).unwrap_or(0.0);
+        if start.is_some_and(|s| t < s) {
+            continue;
+        }
+        if end.is_some_and(|e| t > e) {
+            break;
+        }
+        if bytes + line.len() > MAX_FETCH_BYTES {
+            next = Some(t);
+            break;
+        }
+        bytes += line.len();
+        events.push(event);
+    }
+    Ok(serde_json::json!({
+        "id": id,
+        "header": header,
+        "events": events,
+        "truncated": next.is_some(),
+        "next": next,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_is_asciicast_v2() {
+        let line = header_line(120, 40, 1700000000, "dev \"main\"");
+        assert!(line.ends_with('\n') && line.matches('\n').count() == 1);
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["version"], 2);
+        assert_eq!((v["width"].as_u64(), v["height"].as_u64()), (Some(120), Some(40)));
+        assert_eq!(v["timestamp"], 1700000000);
+        assert_eq!(v["title"], "dev \"main\"");
+    }
+
+    #[test]
+    fn event_lines() {
+        let line = event_line(1.23456789, "a\r\n\x1b[1mb\"");
+        assert_eq!(line.matches('\n').count(), 1, "escapes stay on one line");
+        let (t, kind, data): (f64, String, String) = serde_json::from_str(&line).unwrap();
+        assert_eq!(t, 1.234568);
+        assert_eq!(kind, "o");
+        assert_eq!(data, "a\r\n\x1b[1mb\"");
+    }
+
+    #[test]
+    fn ids_are_file_names() {
+        assert!(cast_path("dev_0_1-1700000000").is_ok());
+        for bad in ["", ".hidden", "../x", "a/b", "a b"] {
+            assert!(cast_path(bad).is_err(), "accepted {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn last_event_time_from_tail() {
+        let path = std::env::temp_dir().join(format!("tmux-mobile-test-{}.cast", uuid::Uuid::new_v4()));
+        let content = format!("{}{}{}", header_line(80, 24, 1, "t"), event_line(0.5, "x"), event_line(2.25, "y"));
+        std::fs::write(&path, &content).unwrap();
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(last_event_time(&mut file, content.len() as u64), 2.25);
+        std::fs::write(&path, header_line(80, 24, 1, "t")).unwrap();
+        let mut file = std::fs::File::open(&path).unwrap();
+        assert_eq!(last_event_time(&mut file, 10), 0.0, "no events yet");
+        let _ = std::fs::remove_file(&path);
+    }
+}
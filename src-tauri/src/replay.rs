@@ -0,0 +1,207 @@
+/// Recording replay: plays a `.cast` file back through `pane_output` as if it
+/// were a live pane. Subscribe to `recording:<id>` to start one.
+use crate::recording;
+use crate::terminal::Terminal;
+
+pub const TARGET_PREFIX: &str = "recording:";
+
+const MAX_IDLE_SECS: f64 = 2.0; // longer pauses in the recording are skipped
+const SPEED_RANGE: (f64, f64) = (0.25, 64.0);
+
+pub enum Control {
+    Pause,
+    Resume,
+    Seek(f64),
+    Speed(f64),
+}
+
+impl Control {
+    /// Parse `replay_control` params: `action` plus `position`/`speed`
+    pub fn from_params(params: &serde_json::Value) -> Result<Self, String> {
+        let action = params.get("action").and_then(|v| v.as_str()).unwrap_or("");
+        let number = |key: &str| params.get(key).and_then(|v| v.as_f64())
+            .ok_or_else(|| format!("missing required param: {}", key));
+        match action {
+            "pause" => Ok(Control::Pause),
+            "resume" => Ok(Control::Resume),
+            "seek" => Ok(Control::Seek(number("position")?)),
+            "speed" => Ok(Control::Speed(check_speed(number("speed")?)?)),
+            _ => Err(format!("unknown replay action: {:?} (pause, resume, seek, speed)", action)),
+        }
+    }
+}
+
+pub fn is_replay_target(target: &str) -> bool {
+    target.starts_with(TARGET_PREFIX)
+}
+
+pub fn check_speed(speed: f64) -> Result<f64, String> {
+    if speed.is_finite() && (SPEED_RANGE.0..=SPEED_RANGE.1).contains(&speed) {
+        Ok(speed)
+    } else {
+        Err(format!("speed must be between {} and {}", SPEED_RANGE.0, SPEED_RANGE.1))
+    }
+}
+
+pub struct Player {
+    events: Vec<(f64, String)>,
+    duration: f64,
+    position: f64, // seconds into the recording
+    next: usize,   // index of the first event not yet applied
+    speed: f64,
+    paused: bool,
+    size: (usize, usize),
+    term: Terminal,
+}
+
+impl Player {
+    pub fn load(target: &str, speed: f64) -> Result<Self, String> {
+        let id = target.strip_prefix(TARGET_PREFIX).ok_or_else(|| format!("not a recording target: {}", target))?;
+        let (header, events) = recording::load_output(id)?;
+        let dim = |key: &str, default: u64| header[key].as_u64().filter(|&n| n > 0).unwrap_or(default).min(1000) as usize;
+        Self::new(events, (dim("width", 80), dim("height", 24)), speed)
+    }
+
+    fn new(events: Vec<(f64, String)>, size: (usize, usize), speed: f64) -> Result<Self, String> {
+        let duration = events.last().map(|e| e.0).unwrap_or(0.0);
+        Ok(Self {
+            events,
+            duration,
+            position: 0.0,
+            next: 0,
+            speed: check_speed(speed)?,
+            paused: false,
+            size,
+            term: Terminal::new(size.0, size.1),
+        })
+    }
+
+    pub fn control(&mut self, c: Control) {
+        match c {
+            Control::Pause => self.paused = true,
+            Control::Resume => self.paused = false,
+            Control::Seek(pos) => self.seek(pos),
+            Control::Speed(speed) => self.speed = speed,
+        }
+    }
+
+    /// Advance by `elapsed` wall-clock seconds; true if any output was played
+    pub fn advance(&mut self, elapsed: f64) -> bool {
+        if self.paused || self.finished() {
+            return false;
+        }
+        if let Some((t, _)) = self.events.get(self.next) {
+            if t - self.position > MAX_IDLE_SECS {
+                self.position = t - MAX_IDLE_SECS;
+            }
+        }
+        self.position = (self.position + elapsed * self.speed).min(self.duration);
+        self.apply_until(self.position)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// The recording's terminal at the current position, rendered like a
+    /// live pane's `capture-pane` snapshot
+    pub fn screen(&self) -> String {
+        self.term.render()
+    }
+
+    pub fn state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "position": self.position,
+            "duration": self.duration,
+            "speed": self.speed,
+            "paused": self.paused,
+            "finished": self.finished(),
+        })
+    }
+
+    fn seek(&mut self, pos: f64) {
+        self.term = Terminal::new(self.size.0, self.size.1);
+        self.next = 0;
+        self.position = pos.clamp(0.0, self.duration);
+        self.apply_until(self.position);
+    }
+
+    fn apply_until(&mut self, pos: f64) -> bool {
+        let start = self.next;
+        while let Some((t, data)) = self.events.get(self.next) {
+            if *t > pos {
+                break;
+            }
+            self.term.feed(data);
+            self.next += 1;
+        }
+        self.next != start
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> Player {
+        Player::new(vec![
+            (0.5, "one\r\n".into()),
+            (1.0, "\x1b[32mtwo\x1b[0m\r\n".into()),
+            (2.0, "\x1b[H\x1b[2Jthree\r\n".into()),
+            (8.0, "four\r\n".into()),
+        ], (10, 3), 1.0).unwrap()
+    }
+
+    #[test]
+    fn seek_rebuilds_from_the_start() {
+        let mut p = player();
+        p.control(Control::Seek(1.5));
+        assert_eq!(p.screen(), "one\n\x1b[32mtwo\n\n");
+        // Past the clear, earlier output is history above the screen
+        p.control(Control::Seek(3.0));
+        assert_eq!(p.screen(), "one\n\x1b[32mtwo\n\x1b[39mthree\n\n\n");
+        // Backwards works too
+        p.control(Control::Seek(0.6));
+        assert_eq!(p.screen(), "one\n\n\n");
+        assert_eq!(p.state()["position"], 0.6);
+        // Out of range is clamped
+        p.control(Control::Seek(100.0));
+        assert!(p.finished());
+        assert_eq!(p.state()["position"], 8.0);
+        p.control(Control::Seek(-1.0));
+        assert_eq!(p.screen(), "\n\n\n");
+    }
+
+    #[test]
+    fn screen_is_a_plain_snapshot() {
+        // Cursor movement and terminal resets are applied, not passed on
+        let mut p = Player::new(vec![(0.0, "\x1bcprompt$ ls\x1b[2D\x1b[Kcd".into())], (20, 2), 1.0).unwrap();
+        p.control(Control::Seek(0.0));
+        assert_eq!(p.screen(), "prompt$ cd\n\n");
+    }
+
+    #[test]
+    fn advance_skips_long_pauses() {
+        let mut p = player();
+        assert!(p.advance(1.0));
+        assert_eq!(p.screen(), "one\n\x1b[32mtwo\n\n");
+        p.control(Control::Pause);
+        assert!(!p.advance(5.0), "paused");
+        p.control(Control::Resume);
+        assert!(p.advance(1.0));
+        // 6s of silence before "four" are cut to MAX_IDLE_SECS
+        assert!(!p.advance(1.0));
+        assert!(p.advance(1.1));
+        assert!(p.finished());
+    }
+
+    #[test]
+    fn controls_from_params() {
+        let c = Control::from_params(&serde_json::json!({ "action": "seek", "position": 2.5 })).unwrap();
+        assert!(matches!(c, Control::Seek(pos) if pos == 2.5));
+        assert!(Control::from_params(&serde_json::json!({ "action": "seek" })).is_err());
+        assert!(Control::from_params(&serde_json::json!({ "action": "speed", "speed": 100.0 })).is_err());
+        assert!(Control::from_params(&serde_json::json!({ "action": "rewind" })).is_err());
+    }
+}
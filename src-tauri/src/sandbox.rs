@@ -0,0 +1,293 @@
+/// Path policy for the fs_* methods: allowed roots, deny globs and
+/// per-operation permissions from the `[fs]` section of `config.toml`
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Prefix of every policy error, so callers can tell them from I/O errors
+pub const DENIED: &str = "denied by fs policy";
+
+fn default_roots() -> Vec<String> { vec!["~".into()] }
+fn default_deny() -> Vec<String> {
+    [
+        "**/.ssh", "**/.ssh/**", "**/.gnupg", "**/.gnupg/**", "**/.aws", "**/.aws/**",
+        "**/.env", "**/.env.*", "**/.config/tmux-mobile", "**/.config/tmux-mobile/**",
+    ]
+        .iter().map(|s| s.to_string()).collect()
+}
+fn default_true() -> bool { true }
+
+/// `[fs]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct FsPolicy {
+    /// Only paths under one of these are reachable
+    #[serde(default = "default_roots")]
+    pub roots: Vec<String>,
+    /// Globs matched against the resolved absolute path
+    #[serde(default = "default_deny")]
+    pub deny: Vec<String>,
+    #[serde(default = "default_true")]
+    pub read: bool,
+    #[serde(default = "default_true")]
+    pub write: bool,
+    #[serde(default = "default_true")]
+    pub delete: bool,
+}
+
+impl Default for FsPolicy {
+    fn default() -> Self {
+        Self { roots: default_roots(), deny: default_deny(), read: true, write: true, delete: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Read,
+    Write,
+    Delete,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Delete => "delete",
+        }
+    }
+}
+
+struct Compiled {
+    policy: FsPolicy,
+    roots: Vec<PathBuf>,
+    deny: GlobSet,
+}
+
+static POLICY: OnceLock<Compiled> = OnceLock::new();
+
+fn compile(policy: FsPolicy) -> Result<Compiled, String> {
+    let roots = policy.roots.iter()
+        .map(|r| {
+            let p = crate::config::expand_home(r);
+            p.canonicalize().unwrap_or(p)
+        })
+        .collect();
+    // A glob that doesn't compile is an error, not skipped: dropping it
+    // would silently lift the protection it was meant to give
+    let mut builder = GlobSetBuilder::new();
+    for pattern in &policy.deny {
+        let glob = Glob::new(pattern).map_err(|e| format!("invalid fs deny glob {:?}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    let deny = builder.build().map_err(|e| format!("invalid fs deny globs: {}", e))?;
+    Ok(Compiled { policy, roots, deny })
+}
+
+pub fn init(policy: FsPolicy) -> Result<(), String> {
+    let _ = POLICY.set(compile(policy)?);
+    Ok(())
+}
+
+fn compiled() -> &'static Compiled {
+    // Tests work in the temp dir rather than the home directory
+    #[cfg(test)]
+    let policy = FsPolicy { roots: vec![std::env::temp_dir().to_string_lossy().into_owned()], ..FsPolicy::default() };
+    #[cfg(not(test))]
+    let policy = FsPolicy::default();
+    POLICY.get_or_init(|| compile(policy).expect("default fs policy compiles"))
+}
+
+/// Resolve symlinks in the longest existing prefix of `p`; the rest (a path
+/// about to be created) must not climb back out with `..`
+fn canonical(p: &Path) -> Result<PathBuf, String> {
+    if let Ok(c) = p.canonicalize() {
+        return Ok(c);
+    }
+    let mut existing = p.to_path_buf();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        let Some(name) = existing.file_name() else { break };
+        rest.push(name.to_os_string());
+        existing.pop();
+    }
+    if p.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("{}: '..' in a path that does not exist: {}", DENIED, p.display()));
+    }
+    let mut out = existing.canonicalize().unwrap_or(existing);
+    out.extend(rest.iter().rev());
+    Ok(out)
+}
+
+/// Check `path` (already `~`-expanded and absolute) for `op`; returns the
+/// canonical path to operate on
+pub fn check(path: &Path, op: Op) -> Result<PathBuf, String> {
+    let real = canonical(path)?;
+    check_resolved(&real, op)?;
+    Ok(real)
+}
+
+/// `check` for a path that is canonical already
+pub(crate) fn check_resolved(real: &Path, op: Op) -> Result<(), String> {
+    let c = compiled();
+    let allowed = match op {
+        Op::Read => c.policy.read,
+        Op::Write => c.policy.write,
+        Op::Delete => c.policy.delete,
+    };
+    if !allowed {
+        return Err(format!("{}: {} is disabled", DENIED, op.name()));
+    }
+    if !c.roots.iter().any(|root| real.starts_with(root)) {
+        return Err(format!("{}: {} is outside the allowed roots", DENIED, real.display()));
+    }
+    if c.deny.is_match(real) {
+        return Err(format!("{}: {} matches a deny pattern", DENIED, real.display()));
+    }
+    if op == Op::Delete && c.roots.iter().any(|root| root == real) {
+        return Err(format!("{}: cannot delete a root ({})", DENIED, real.display()));
+    }
+    Ok(())
+}
+
+/// Like `check`, but a final symlink is not followed: for operations on
+/// the directory entry itself (delete, rename)
+pub fn check_entry(path: &Path, op: Op) -> Result<PathBuf, String> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let entry = canonical(parent)?.join(name);
+            check_resolved(&entry, op)?;
+            Ok(entry)
+        }
+        _ => check(path, op),
+    }
+}
+
+/// Whether `path` may be read, for hiding entries in listings
+pub fn readable(path: &Path) -> bool {
+    check(path, Op::Read).is_ok()
+}
+
+/// `readable` for a path known to be canonical already, such as an entry
+/// of a walk from a canonical root that doesn't follow links
+pub fn readable_resolved(real: &Path) -> bool {
+    check_resolved(real, Op::Read).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn denied(r: Result<PathBuf, String>) -> bool {
+        r.is_err_and(|e| e.starts_with(DENIED))
+    }
+
+    #[test]
+    fn default_deny_globs() {
+        let dir = scratch();
+        let cases = [
+            (".ssh", true),
+            (".ssh/id_ed25519", true),
+            (".gnupg/pubring.kbx", true),
+            (".aws/credentials", true),
+            (".env", true),
+            (".env.local", true),
+            ("app/.env.production", true),
+            (".config/tmux-mobile", true),
+            (".config/tmux-mobile/config.toml", true),
+            (".envrc", false),
+            (".environment", false),
+            ("env", false),
+            (".config/other/config.toml", false),
+            ("src/ssh.rs", false),
+        ];
+        for (rel, deny) in cases {
+            let p = dir.join(rel);
+            assert_eq!(denied(check(&p, Op::Read)), deny, "{}", rel);
+            assert_eq!(readable(&p), !deny, "{}", rel);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_deny_glob_is_an_error() {
+        let policy = FsPolicy { deny: vec!["**/.ssh/**".into(), "**/.aws/[".into()], ..FsPolicy::default() };
+        let err = compile(policy).err().expect("invalid glob accepted");
+        assert!(err.contains("**/.aws/["), "{}", err);
+        assert!(compile(FsPolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn roots_and_dot_dot() {
+        let dir = scratch();
+        std::fs::create_dir(dir.join("a")).unwrap();
+        assert_eq!(check(&dir.join("a/../a"), Op::Read).unwrap(), dir.join("a"));
+        assert!(denied(check(Path::new("/etc/hostname"), Op::Read)), "outside the roots");
+        // `..` through existing directories resolves, and still ends up outside
+        let up = "../".repeat(dir.components().count());
+        assert!(denied(check(&dir.join("a").join(&up).join("etc/hostname"), Op::Read)));
+        // A path to be created can't climb out through `..` at all
+        let err = check(&dir.join("new/../../x"), Op::Write).unwrap_err();
+        assert!(err.contains("'..' in a path that does not exist"), "{}", err);
+        // Roots can't be deleted, but what is inside them can
+        assert!(denied(check(&std::env::temp_dir(), Op::Delete)));
+        assert!(check(&dir, Op::Delete).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symlinks() {
+        let dir = scratch();
+        symlink("/etc", dir.join("etc")).unwrap();
+        std::fs::write(dir.join(".env"), "SECRET=1").unwrap();
+        symlink(dir.join(".env"), dir.join("config")).unwrap();
+
+        // Following the link leads outside the roots or to a denied file
+        assert!(denied(check(&dir.join("etc"), Op::Read)));
+        assert!(denied(check(&dir.join("etc/hostname"), Op::Read)));
+        assert!(denied(check(&dir.join("etc/new-file"), Op::Write)));
+        assert!(denied(check(&dir.join("config"), Op::Read)));
+        // The link entry itself lives inside the roots and may be removed
+        assert_eq!(check_entry(&dir.join("etc"), Op::Delete).unwrap(), dir.join("etc"));
+        assert_eq!(check_entry(&dir.join("config"), Op::Delete).unwrap(), dir.join("config"));
+        // ...but not anything reached through it
+        assert!(denied(check_entry(&dir.join("etc/hostname"), Op::Delete)));
+        assert!(denied(check_entry(&dir.join(".env"), Op::Delete)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_leaf_under_denied_dir() {
+        let dir = scratch();
+        std::fs::create_dir(dir.join(".ssh")).unwrap();
+        for rel in [".ssh/new_key", ".aws/not/there/yet", ".config/tmux-mobile/token"] {
+            assert!(denied(check(&dir.join(rel), Op::Write)), "{}", rel);
+            assert!(denied(check_entry(&dir.join(rel), Op::Write)), "{}", rel);
+        }
+        assert_eq!(check(&dir.join("new/leaf"), Op::Write).unwrap(), dir.join("new/leaf"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn readable_resolved_skips_resolution() {
+        let dir = scratch();
+        symlink("/etc", dir.join("etc")).unwrap();
+        assert!(readable_resolved(&dir));
+        assert!(readable_resolved(&dir.join("notes.txt")));
+        assert!(!readable_resolved(&dir.join(".env")));
+        assert!(!readable_resolved(&dir.join(".ssh/config")));
+        assert!(!readable_resolved(Path::new("/etc/hostname")));
+        // Taken as already canonical: the link isn't followed here, unlike
+        // `readable`, which is why walks must not follow links
+        assert!(readable_resolved(&dir.join("etc")));
+        assert!(!readable(&dir.join("etc")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+/// Scheduled commands against panes: one-shot (at a time / after a delay) and
+/// cron-style jobs. Pending jobs are persisted to `jobs.json` in the config dir.
+use crate::tmux;
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime, TimeZone, Timelike};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_HISTORY: usize = 100;
+const MISSED_GRACE_SECS: u64 = 600; // overdue one-shots older than this are dropped at startup
+const IF_MATCH_LINES: usize = 5;    // last non-empty pane lines checked by `if_match`
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobAction {
+    SendKeys { keys: String },
+    SendCommand { command: String },
+}
+
+/// `schedule_job` params
+#[derive(Debug, Deserialize)]
+pub struct JobRequest {
+    pub name: Option<String>,
+    pub target: String,
+    pub action: JobAction,
+    /// Unix timestamp (seconds) for a one-shot job
+    pub at: Option<u64>,
+    /// Run once, this many seconds from now
+    pub delay_secs: Option<u64>,
+    /// 5-field cron expression (minute hour day-of-month month day-of-week), local time
+    pub cron: Option<String>,
+    /// Only run if this regex matches one of the last lines of the pane
+    pub if_match: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub target: String,
+    pub action: JobAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_match: Option<String>,
+    pub next_run: u64,
+    pub created: u64,
+    #[serde(default)]
+    pub runs: u64,
+    #[serde(default)]
+    pub last_run: Option<u64>,
+}
+
+/// Outcome of one run, kept in memory for `list_jobs`
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRun {
+    pub job_id: String,
+    pub target: String,
+    pub time: u64,
+    pub status: &'static str, // "ok", "skipped", "error", "missed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    jobs: Vec<Job>,
+    history: VecDeque<JobRun>,
+}
+
+static SCHEDULER: OnceLock<Mutex<Scheduler>> = OnceLock::new();
+
+fn scheduler() -> &'static Mutex<Scheduler> {
+    SCHEDULER.get_or_init(|| Mutex::new(Scheduler::default()))
+}
+
+fn jobs_path() -> PathBuf {
+    crate::config::config_dir().join("jobs.json")
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn persist(jobs: &[Job]) {
+    let path = jobs_path();
+    let result = path.parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| std::fs::write(&path, serde_json::to_string_pretty(jobs).unwrap()));
+    if let Err(e) = result {
+        eprintln!("⚠️  Cannot save {}: {}", path.display(), e);
+    }
+}
+
+fn push_history(history: &mut VecDeque<JobRun>, run: JobRun) {
+    if history.len() >= MAX_HISTORY {
+        history.pop_front();
+    }
+    history.push_back(run);
+}
+
+/// Load persisted jobs. Cron jobs are rescheduled from now; one-shots that
+/// were missed by more than the grace period are dropped.
+pub fn init() {
+    let jobs: Vec<Job> = match std::fs::read_to_string(jobs_path()) {
+        Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+            eprintln!("⚠️  Ignoring invalid {}: {}", jobs_path().display(), e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    let now = unix_now();
+    let mut sched = scheduler().lock().unwrap();
+    sched.jobs.clear();
+    for mut job in jobs {
+        if let Some(expr) = &job.cron {
+            match Cron::parse(expr).and_then(|c| c.next_after(now)) {
+                Ok(next) => job.next_run = next,
+                Err(e) => {
+                    eprintln!("⚠️  Dropping job {}: {}", job.id, e);
+                    continue;
+                }
+            }
+        } else if job.next_run + MISSED_GRACE_SECS < now {
+            let run = JobRun { job_id: job.id, target: job.target, time: now, status: "missed", detail: None };
+            push_history(&mut sched.history, run);
+            continue;
+        }
+        sched.jobs.push(job);
+    }
+    persist(&sched.jobs);
+}
+
+pub fn schedule(req: JobRequest) -> Result<Job, String> {
+    if req.target.is_empty() {
+        return Err("job target must not be empty".into());
+    }
+    if let Some(pattern) = &req.if_match {
+        Regex::new(pattern).map_err(|e| format!("invalid if_match: {}", e))?;
+    }
+    let now = unix_now();
+    let next_run = match (req.at, req.delay_secs, &req.cron) {
+        (Some(at), None, None) => at,
+        (None, Some(delay), None) => now + delay,
+        (None, None, Some(expr)) => Cron::parse(expr)?.next_after(now)?,
+        _ => return Err("exactly one of at, delay_secs or cron is required".into()),
+    };
+    let job = Job {
+        id: uuid::Uuid::new_v4().to_string()[..8].to_string(),
+        name: req.name,
+        target: req.target,
+        action: req.action,
+        cron: req.cron,
+        if_match: req.if_match,
+        next_run,
+        created: now,
+        runs: 0,
+        last_run: None,
+    };
+    let mut sched = scheduler().lock().unwrap();
+    sched.jobs.push(job.clone());
+    persist(&sched.jobs);
+    Ok(job)
+}
+
+pub fn cancel(id: &str) -> Result<(), String> {
+    let mut sched = scheduler().lock().unwrap();
+    let before = sched.jobs.len();
+    sched.jobs.retain(|j| j.id != id);
+    if sched.jobs.len() == before {
+        return Err(format!("no such job: {}", id));
+    }
+    persist(&sched.jobs);
+    Ok(())
+}
+
+/// Pending jobs ordered by next run, plus recent runs (newest first)
+pub fn list() -> (Vec<Job>, Vec<JobRun>) {
+    let sched = scheduler().lock().unwrap();
+    let mut jobs = sched.jobs.clone();
+    jobs.sort_by_key(|j| j.next_run);
+    (jobs, sched.history.iter().rev().cloned().collect())
+}
+
+fn execute(job: &Job) -> (&'static str, Option<String>) {
+    if let Some(pattern) = &job.if_match {
+        let re = match Regex::new(pattern) {
+            Ok(re) => re,
+            Err(e) => return ("error", Some(e.to_string())),
+        };
+        let content = match tmux::capture_pane_plain(&job.target, Some(IF_MATCH_LINES * 4)) {
+            Ok(c) => c,
+            Err(e) => return ("error", Some(e)),
+        };
+        let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+        let tail = &lines[lines.len().saturating_sub(IF_MATCH_LINES)..];
+        if !tail.iter().any(|l| re.is_match(l)) {
+            return ("skipped", Some("if_match did not match".into()));
+        }
+    }
+    let result = match &job.action {
+        JobAction::SendKeys { keys } => tmux::send_keys(&job.target, keys, false),
+        JobAction::SendCommand { command } => tmux::send_command(&job.target, command),
+    };
+    match result {
+        Ok(()) => ("ok", None),
+        Err(e) => ("error", Some(e)),
+    }
+}
+
+/// Run every job that is due. One-shots are removed, cron jobs rescheduled.
+/// Blocking (shells out to tmux); returns the runs for notification.
+pub fn run_due() -> Vec<JobRun> {
+    let now = unix_now();
+    let due: Vec<Job> = {
+        let sched = scheduler().lock().unwrap();
+        sched.jobs.iter().filter(|j| j.next_run <= now).cloned().collect()
+    };
+    if due.is_empty() {
+        return Vec::new();
+    }
+
+    let runs: Vec<JobRun> = due.iter()
+        .map(|job| {
+            let (status, detail) = execute(job);
+            JobRun { job_id: job.id.clone(), target: job.target.clone(), time: now, status, detail }
+        })
+        .collect();
+
+    let mut sched = scheduler().lock().unwrap();
+    for job in &due {
+        let next = job.cron.as_deref().and_then(|c| Cron::parse(c).and_then(|c| c.next_after(now)).ok());
+        match (sched.jobs.iter_mut().find(|j| j.id == job.id), next) {
+            (Some(j), Some(next)) => {
+                j.next_run = next;
+                j.runs += 1;
+                j.last_run = Some(now);
+            }
+            _ => sched.jobs.retain(|j| j.id != job.id),
+        }
+    }
+    for run in &runs {
+        push_history(&mut sched.history, run.clone());
+    }
+    persist(&sched.jobs);
+    runs
+}
+
+/// Parsed 5-field cron expression; each field is a bitmask of allowed values
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month or weekday left open (`*`, `*/n` or the whole range),
+    /// which turns off cron's "either day field may match" rule
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+const ALL_DAYS: u64 = ((1 << 32) - 1) & !1; // 1-31
+const ALL_WEEKDAYS: u64 = (1 << 7) - 1;    // 0-6
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("cron needs 5 fields (minute hour day month weekday): {}", expr));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1; // 7 is Sunday too
+        }
+        let days = parse_field(fields[2], 1, 31)?;
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_any: fields[2].starts_with('*') || days == ALL_DAYS,
+            weekdays_any: fields[4].starts_with('*') || weekdays & ALL_WEEKDAYS == ALL_WEEKDAYS,
+        })
+    }
+
+    fn day_matches(&self, t: &NaiveDateTime) -> bool {
+        let dom = self.days & (1 << t.day()) != 0;
+        let dow = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        // Standard cron: when both are restricted, either one may match;
+        // otherwise both have to (an open field like `*/2` still filters)
+        if self.days_any || self.weekdays_any {
+            dom && dow
+        } else {
+            dom || dow
+        }
+    }
+
+    /// First matching minute strictly after `after` (unix seconds), in local time
+    fn next_after(&self, after: u64) -> Result<u64, String> {
+        let start = Local.timestamp_opt(after as i64, 0).single()
+            .ok_or("invalid timestamp")?
+            .naive_local();
+        let mut t = start.with_second(0).unwrap() + ChronoDuration::minutes(1);
+        let limit = start + ChronoDuration::days(366 * 4);
+        while t < limit {
+            if self.months & (1 << t.month()) == 0 {
+                t = (t.date().with_day(1).unwrap() + ChronoDuration::days(32)).with_day(1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+                continue;
+            }
+            if !self.day_matches(&t) {
+                t = (t.date() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0).unwrap();
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0).unwrap() + ChronoDuration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += ChronoDuration::minutes(1);
+                continue;
+            }
+            // Times skipped by a DST change do not exist locally; keep looking
+            if let Some(local) = Local.from_local_datetime(&t).earliest() {
+                if local.timestamp() as u64 > after {
+                    return Ok(local.timestamp() as u64);
+                }
+            }
+            t += ChronoDuration::minutes(1);
+        }
+        Err("cron expression never matches".into())
+    }
+}
+
+/// One cron field: `*`, `5`, `1-5`, `*/15`, `1-30/2`, or a comma list of those
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().map_err(|_| format!("invalid cron step: {}", part))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("invalid cron step: {}", part));
+        }
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a.parse().map_err(|_| format!("invalid cron value: {}", part))?;
+            let b = b.parse().map_err(|_| format!("invalid cron value: {}", part))?;
+            (a, b)
+        } else {
+            let v = range.parse().map_err(|_| format!("invalid cron value: {}", part))?;
+            // `5/15` means "from 5 to max, every 15"
+            (v, if step > 1 { max } else { v })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!("cron value out of range {}-{}: {}", min, max, part));
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(values: &[u32]) -> u64 {
+        values.iter().fold(0, |m, v| m | 1 << v)
+    }
+
+    /// Next run after a local time, as a local time
+    fn next(expr: &str, from: &str) -> Result<String, String> {
+        let from = NaiveDateTime::parse_from_str(from, "%Y-%m-%d %H:%M").unwrap();
+        let from = Local.from_local_datetime(&from).single().unwrap().timestamp() as u64;
+        let t = Cron::parse(expr)?.next_after(from)?;
+        Ok(Local.timestamp_opt(t as i64, 0).unwrap().format("%Y-%m-%d %H:%M").to_string())
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(parse_field("*", 0, 6).unwrap(), ALL_WEEKDAYS);
+        assert_eq!(parse_field("5", 0, 59).unwrap(), bits(&[5]));
+        assert_eq!(parse_field("1-3", 0, 59).unwrap(), bits(&[1, 2, 3]));
+        assert_eq!(parse_field("*/15", 0, 59).unwrap(), bits(&[0, 15, 30, 45]));
+        assert_eq!(parse_field("10-20/5", 0, 59).unwrap(), bits(&[10, 15, 20]));
+        assert_eq!(parse_field("50/5", 0, 59).unwrap(), bits(&[50, 55]));
+        assert_eq!(parse_field("1,5,9-10", 0, 59).unwrap(), bits(&[1, 5, 9, 10]));
+        assert_eq!(parse_field("*/2", 1, 31).unwrap().count_ones(), 16);
+        for bad in ["60", "5-1", "*/0", "x", "1-", "-1", "", "1,,2"] {
+            assert!(parse_field(bad, 0, 59).is_err(), "accepted {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert!(Cron::parse("* * * *").is_err(), "4 fields");
+        assert!(Cron::parse("* * * * * *").is_err(), "6 fields");
+        let c = Cron::parse("0 0 * * 7").unwrap();
+        assert_eq!(c.weekdays & 1, 1, "7 is Sunday");
+        let c = Cron::parse("0 0 */2 * 0-7").unwrap();
+        assert!(c.days_any && c.weekdays_any);
+        let c = Cron::parse("0 0 1-31 * 1,3").unwrap();
+        assert!(c.days_any && !c.weekdays_any, "whole range is open");
+        let c = Cron::parse("0 0 1-30 * 1").unwrap();
+        assert!(!c.days_any);
+    }
+
+    #[test]
+    fn next_runs() {
+        // 2025-01-01 is a Wednesday
+        let cases = [
+            ("*/15 * * * *", "2025-01-06 08:31", "2025-01-06 08:45"),
+            ("0 9 * * *", "2025-01-06 09:00", "2025-01-07 09:00"), // strictly after
+            ("0 9 * * 1-5", "2025-01-04 10:00", "2025-01-06 09:00"), // Saturday -> Monday
+            ("30 2 1 * *", "2025-01-06 00:00", "2025-02-01 02:30"),
+            ("0 0 29 2 *", "2025-01-01 00:00", "2028-02-29 00:00"),
+            ("5 8-10/2 * 3,6 *", "2025-01-01 00:00", "2025-03-01 08:05"),
+            // Both day fields restricted: either may match
+            ("0 9 15 * 5", "2025-01-01 00:00", "2025-01-03 09:00"),
+            // An open day field still filters: odd days that are Mondays
+            ("0 9 */2 * 1", "2025-01-01 00:00", "2025-01-13 09:00"),
+            // Weekdays `*/2` (Sun, Tue, Thu, Sat) together with the 10th
+            ("0 9 10 * */2", "2025-01-01 00:00", "2025-04-10 09:00"),
+            ("0 9 1-31 * 1", "2025-01-01 00:00", "2025-01-06 09:00"),
+        ];
+        for (expr, from, want) in cases {
+            assert_eq!(next(expr, from).unwrap(), want, "{} after {}", expr, from);
+        }
+        assert!(next("0 0 31 2 *", "2025-01-01 00:00").is_err(), "Feb 31");
+    }
+}
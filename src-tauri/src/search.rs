@@ -0,0 +1,472 @@
+/// Recursive search under a directory: `fs_find` matches names (glob or
+/// fuzzy), `fs_grep` matches file contents. Both skip what `.gitignore`
+/// ignores and stream hits back in batches.
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use globset::{Glob, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+pub const MAX_RESULTS: usize = 10_000;
+const DEFAULT_FIND_RESULTS: usize = 1000;
+const DEFAULT_GREP_RESULTS: usize = 500;
+const MAX_CONTEXT: usize = 10;
+/// Larger files are not searched by `fs_grep`
+const MAX_GREP_FILE: u64 = 10 * 1024 * 1024;
+const MAX_LINE_CHARS: usize = 500;
+const BATCH_SIZE: usize = 50;
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// `fs_find` params
+#[derive(Debug, Deserialize)]
+pub struct FindRequest {
+    pub path: String,
+    pub pattern: String,
+    /// Treat `pattern` as a glob; by default it is one if it has `*`, `?` or `[`
+    pub glob: Option<bool>,
+    #[serde(default)]
+    pub hidden: bool,
+    pub max_results: Option<usize>,
+}
+
+/// `fs_grep` params
+#[derive(Debug, Deserialize)]
+pub struct GrepRequest {
+    pub path: String,
+    pub pattern: String,
+    /// Only search files whose name (or relative path, with a `/`) matches
+    pub glob: Option<String>,
+    #[serde(default)]
+    pub ignore_case: bool,
+    #[serde(default)]
+    pub context: usize,
+    #[serde(default)]
+    pub hidden: bool,
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Hit {
+    Name {
+        path: String,
+        name: String,
+        #[serde(rename = "type")]
+        file_type: &'static str,
+        /// Fuzzy matches only; higher is better
+        #[serde(skip_serializing_if = "Option::is_none")]
+        score: Option<i64>,
+    },
+    Line {
+        path: String,
+        /// 1-based
+        line: usize,
+        text: String,
+        /// Matched `[start, end)` char ranges within `text`
+        ranges: Vec<(usize, usize)>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        before: Vec<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        after: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub count: usize,
+    /// Stopped at `max_results`
+    pub truncated: bool,
+    pub cancelled: bool,
+    pub files_searched: usize,
+    pub elapsed_ms: u64,
+}
+
+enum NameMatch {
+    Glob { matcher: GlobMatcher, full_path: bool },
+    Fuzzy(Vec<char>),
+}
+
+enum Matcher {
+    Name(NameMatch),
+    Content { regex: Regex, filter: Option<NameMatch>, context: usize },
+}
+
+/// A validated search, ready to run
+pub struct Search {
+    root: PathBuf,
+    matcher: Matcher,
+    hidden: bool,
+    max_results: usize,
+}
+
+fn glob(pattern: &str) -> Result<NameMatch, String> {
+    let matcher = Glob::new(pattern).map_err(|e| format!("invalid glob: {}", e))?.compile_matcher();
+    Ok(NameMatch::Glob { matcher, full_path: pattern.contains('/') })
+}
+
+fn search_root(path: &str) -> Result<PathBuf, String> {
+    let root = sandbox::check(&expand_path(path), Op::Read)?;
+    if !root.is_dir() {
+        return Err(format!("not a directory: {}", root.display()));
+    }
+    Ok(root)
+}
+
+fn limit(requested: Option<usize>, default: usize) -> usize {
+    requested.unwrap_or(default).clamp(1, MAX_RESULTS)
+}
+
+pub fn prepare_find(req: FindRequest) -> Result<Search, String> {
+    if req.pattern.is_empty() {
+        return Err("empty pattern".into());
+    }
+    let is_glob = req.glob.unwrap_or_else(|| req.pattern.contains(['*', '?', '[']));
+    let matcher = if is_glob {
+        glob(&req.pattern)?
+    } else {
+        NameMatch::Fuzzy(req.pattern.to_lowercase().chars().collect())
+    };
+    Ok(Search {
+        root: search_root(&req.path)?,
+        matcher: Matcher::Name(matcher),
+        hidden: req.hidden,
+        max_results: limit(req.max_results, DEFAULT_FIND_RESULTS),
+    })
+}
+
+pub fn prepare_grep(req: GrepRequest) -> Result<Search, String> {
+    let regex = RegexBuilder::new(&req.pattern)
+        .case_insensitive(req.ignore_case)
+        .build()
+        .map_err(|e| format!("invalid pattern: {}", e))?;
+    let filter = req.glob.as_deref().map(glob).transpose()?;
+    Ok(Search {
+        root: search_root(&req.path)?,
+        matcher: Matcher::Content { regex, filter, context: req.context.min(MAX_CONTEXT) },
+        hidden: req.hidden,
+        max_results: limit(req.max_results, DEFAULT_GREP_RESULTS),
+    })
+}
+
+/// Subsequence match of `needle` (lowercase) in `name`; rewards runs of
+/// consecutive characters and matches at word starts. None if not all of
+/// `needle` is found.
+fn fuzzy_score(needle: &[char], name: &str) -> Option<i64> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut score = 0i64;
+    let mut pos = 0;
+    let mut prev: Option<usize> = None;
+    for &c in needle {
+        let i = (pos..chars.len()).find(|&i| chars[i].to_lowercase().eq(std::iter::once(c)))?;
+        score += 1;
+        if prev == Some(i.wrapping_sub(1)) {
+            score += 5;
+        }
+        if i == 0 || matches!(chars[i - 1], '_' | '-' | '.' | ' ') || (chars[i].is_uppercase() && chars[i - 1].is_lowercase()) {
+            score += 3;
+        }
+        prev = Some(i);
+        pos = i + 1;
+    }
+    // Prefer shorter names for the same matches
+    Some(score * 100 - chars.len() as i64)
+}
+
+impl NameMatch {
+    fn score(&self, rel: &Path, name: &str) -> Option<Option<i64>> {
+        match self {
+            NameMatch::Glob { matcher, full_path } => {
+                let hit = if *full_path { matcher.is_match(rel) } else { matcher.is_match(name) };
+                hit.then_some(None)
+            }
+            NameMatch::Fuzzy(needle) => fuzzy_score(needle, name).map(Some),
+        }
+    }
+}
+
+fn clip(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((i, _)) => line[..i].to_string(),
+        None => line.to_string(),
+    }
+}
+
+fn char_ranges(line: &str, regex: &Regex) -> Vec<(usize, usize)> {
+    regex.find_iter(line)
+        .map(|m| (line[..m.start()].chars().count(), line[..m.end()].chars().count()))
+        .filter(|&(start, _)| start < MAX_LINE_CHARS)
+        .map(|(start, end)| (start, end.min(MAX_LINE_CHARS)))
+        .collect()
+}
+
+/// Matching lines of one file, or nothing for binary and oversized files
+fn grep_file(path: &Path, regex: &Regex, context: usize, room: usize) -> Vec<Hit> {
+    let Ok(meta) = std::fs::metadata(path) else { return Vec::new() };
+    if meta.len() > MAX_GREP_FILE {
+        return Vec::new();
+    }
+    let Ok(bytes) = std::fs::read(path) else { return Vec::new() };
+    if bytes[..bytes.len().min(8192)].contains(&0) {
+        return Vec::new();
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut hits = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if hits.len() >= room {
+            break;
+        }
+        if !regex.is_match(line) {
+            continue;
+        }
+        hits.push(Hit::Line {
+            path: path.to_string_lossy().to_string(),
+            line: i + 1,
+            text: clip(line),
+            ranges: char_ranges(line, regex),
+            before: lines[i.saturating_sub(context)..i].iter().map(|l| clip(l)).collect(),
+            after: lines[i + 1..(i + 1 + context).min(lines.len())].iter().map(|l| clip(l)).collect(),
+        });
+    }
+    hits
+}
+
+/// Best fuzzy hits first (ties by path), at most `max`
+fn rank(hits: &mut Vec<Hit>, max: usize) {
+    hits.sort_by(|a, b| match (a, b) {
+        (Hit::Name { score: sa, path: pa, .. }, Hit::Name { score: sb, path: pb, .. }) => sb.cmp(sa).then_with(|| pa.cmp(pb)),
+        _ => std::cmp::Ordering::Equal,
+    });
+    hits.truncate(max);
+}
+
+impl Search {
+    /// Walk the tree, sending hits in batches until done, cancelled, the
+    /// limit is reached or the receiver goes away. Fuzzy name hits are
+    /// ranked by score, so they are collected over the whole walk and sent
+    /// at the end. Blocking.
+    pub fn run(self, cancel: Arc<AtomicBool>, out: mpsc::UnboundedSender<Vec<Hit>>) -> Summary {
+        let started = Instant::now();
+        let walker = ignore::WalkBuilder::new(&self.root)
+            .hidden(!self.hidden)
+            .require_git(false)
+            // Don't descend into what the policy hides
+            .filter_entry(|e| sandbox::readable_resolved(e.path()))
+            .build();
+        let mut batch = Vec::new();
+        let mut last_flush = Instant::now();
+        let (mut count, mut files, mut truncated) = (0, 0, false);
+        let mut gone = false;
+        let fuzzy = matches!(self.matcher, Matcher::Name(NameMatch::Fuzzy(_)));
+        let mut ranked = Vec::new();
+
+        for entry in walker.flatten() {
+            if cancel.load(Ordering::Relaxed) || gone {
+                break;
+            }
+            if entry.depth() == 0 {
+                continue;
+            }
+            let path = entry.path();
+            let Some(ft) = entry.file_type() else { continue };
+            let name = entry.file_name().to_string_lossy();
+            let rel = path.strip_prefix(&self.root).unwrap_or(path);
+            let room = self.max_results.saturating_sub(count);
+            let hits = match &self.matcher {
+                Matcher::Name(m) => {
+                    files += 1;
+                    match m.score(rel, &name) {
+                        Some(score) => vec![Hit::Name {
+                            path: path.to_string_lossy().to_string(),
+                            name: name.to_string(),
+                            file_type: if ft.is_dir() { "dir" } else if ft.is_symlink() { "symlink" } else { "file" },
+                            score,
+                        }],
+                        None => Vec::new(),
+                    }
+                }
+                // Links are not followed: their target may be outside the roots
+                Matcher::Content { .. } if !ft.is_file() => continue,
+                Matcher::Content { filter: Some(f), .. } if f.score(rel, &name).is_none() => continue,
+                Matcher::Content { regex, context, .. } => {
+                    files += 1;
+                    grep_file(path, regex, *context, room)
+                }
+            };
+            if fuzzy {
+                // Pruned as it goes, so memory stays bounded
+                count += hits.len();
+                ranked.extend(hits);
+                if ranked.len() >= 2 * self.max_results {
+                    rank(&mut ranked, self.max_results);
+                }
+                continue;
+            }
+            count += hits.len();
+            batch.extend(hits);
+            if batch.len() >= BATCH_SIZE || (!batch.is_empty() && last_flush.elapsed() >= BATCH_INTERVAL) {
+                gone = out.send(std::mem::take(&mut batch)).is_err();
+                last_flush = Instant::now();
+            }
+            if count >= self.max_results {
+                truncated = true;
+                break;
+            }
+        }
+        if fuzzy {
+            truncated = count > self.max_results;
+            rank(&mut ranked, self.max_results);
+            count = ranked.len();
+            while !ranked.is_empty() && !gone {
+                let rest = ranked.split_off(BATCH_SIZE.min(ranked.len()));
+                gone = out.send(std::mem::replace(&mut ranked, rest)).is_err();
+            }
+        }
+        if !batch.is_empty() {
+            let _ = out.send(batch);
+        }
+        Summary {
+            count,
+            truncated,
+            cancelled: cancel.load(Ordering::Relaxed) || gone,
+            files_searched: files,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        for (rel, content) in files {
+            let p = dir.join(rel);
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(p, content).unwrap();
+        }
+        dir.canonicalize().unwrap()
+    }
+
+    /// Hits (relative paths, with the line for grep) and the summary
+    fn run(search: Search, root: &Path) -> (Vec<String>, Summary) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let summary = search.run(Arc::new(AtomicBool::new(false)), tx);
+        let mut hits = Vec::new();
+        while let Ok(batch) = rx.try_recv() {
+            for hit in batch {
+                hits.push(match hit {
+                    Hit::Name { path, .. } => path,
+                    Hit::Line { path, line, .. } => format!("{}:{}", path, line),
+                });
+            }
+        }
+        let root = format!("{}/", root.display());
+        (hits.iter().map(|h| h.strip_prefix(&root).unwrap_or(h).to_string()).collect(), summary)
+    }
+
+    fn find(root: &Path, pattern: &str, hidden: bool, max_results: Option<usize>) -> Search {
+        let path = root.to_string_lossy().into_owned();
+        prepare_find(FindRequest { path, pattern: pattern.into(), glob: None, hidden, max_results }).unwrap()
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        let score = |needle: &str, name: &str| fuzzy_score(&needle.chars().collect::<Vec<_>>(), name);
+        // needle, better name, worse name
+        let cases = [
+            ("main", "main.rs", "my_awesome_integration.rs"),   // one run beats scattered letters
+            ("sr", "search_results.rs", "user.rs"),             // word starts
+            ("fc", "FileCache.ts", "finance.ts"),              // camelCase humps
+            ("cfg", "cfg.toml", "config.toml"),
+            ("abc", "abc.txt", "abc_longer_name.txt"),          // same matches: shorter wins
+            ("readme", "README.md", "bread_meal.md"),           // case-insensitive
+        ];
+        for (needle, better, worse) in cases {
+            let (b, w) = (score(needle, better).unwrap(), score(needle, worse).unwrap());
+            assert!(b > w, "{}: {} ({}) vs {} ({})", needle, better, b, worse, w);
+        }
+        assert_eq!(score("xyz", "main.rs"), None);
+        assert_eq!(score("nm", "main"), None, "order matters");
+    }
+
+    #[test]
+    fn ranges_and_clipping_count_chars() {
+        let re = Regex::new("wö|字").unwrap();
+        assert_eq!(char_ranges("héllo wörld", &re), [(6, 8)]);
+        let long = format!("{}字{}", "é".repeat(MAX_LINE_CHARS - 1), "字".repeat(10));
+        assert_eq!(clip(&long).chars().count(), MAX_LINE_CHARS);
+        assert!(clip(&long).ends_with('字'));
+        // Matches past the clip are dropped, ones across it are cut short
+        let ranges = char_ranges(&long, &Regex::new("é字+").unwrap());
+        assert_eq!(ranges, [(MAX_LINE_CHARS - 2, MAX_LINE_CHARS)]);
+        assert_eq!(char_ranges(&long, &re).len(), 1);
+        assert_eq!(clip("short"), "short");
+    }
+
+    #[test]
+    fn name_and_path_globs() {
+        let rel = Path::new("src/server/main.rs");
+        let cases = [
+            ("*.rs", true),            // no slash: matched against the name
+            ("main.*", true),
+            ("server", false),
+            ("src/**/main.rs", true),  // with a slash: against the relative path
+            ("server/*.rs", false),    // anchored at the search root
+            ("src/*/main.rs", true),
+        ];
+        for (pattern, want) in cases {
+            assert_eq!(glob(pattern).unwrap().score(rel, "main.rs").is_some(), want, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn ignored_hidden_and_denied_entries() {
+        let dir = scratch(&[
+            (".gitignore", "target/\n*.log\n"),
+            ("src/main.rs", "fn main() {} // TODO\n"),
+            ("target/debug/out.rs", "// TODO\n"),
+            ("build.log", "TODO\n"),
+            (".hidden/h.rs", "// TODO\n"),
+            (".env", "TODO=secret\n"),
+            (".ssh/config", "# TODO\n"),
+        ]);
+        let (mut hits, _) = run(find(&dir, "*", false, None), &dir);
+        hits.sort();
+        assert_eq!(hits, ["src", "src/main.rs"]);
+        let (mut hits, _) = run(find(&dir, "*", true, None), &dir);
+        hits.sort();
+        assert_eq!(hits, [".gitignore", ".hidden", ".hidden/h.rs", "src", "src/main.rs"], "policy still applies");
+
+        let grep = |hidden| {
+            let path = dir.to_string_lossy().into_owned();
+            let req = GrepRequest { path, pattern: "TODO".into(), glob: None, ignore_case: false, context: 0, hidden, max_results: None };
+            let (mut hits, _) = run(prepare_grep(req).unwrap(), &dir);
+            hits.sort();
+            hits
+        };
+        assert_eq!(grep(false), ["src/main.rs:1"]);
+        assert_eq!(grep(true), [".hidden/h.rs:1", "src/main.rs:1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fuzzy_hits_are_ranked_before_the_limit() {
+        let mut files: Vec<(String, &str)> = (0..200).map(|i| (format!("d{:03}/m_a_i_n_{}.txt", i, i), "")).collect();
+        files.push(("zzz/main.rs".into(), ""));
+        let refs: Vec<(&str, &str)> = files.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+        let dir = scratch(&refs);
+        let (hits, summary) = run(find(&dir, "main", false, Some(3)), &dir);
+        assert_eq!(hits[0], "zzz/main.rs", "best match first, wherever the walk found it");
+        assert_eq!((hits.len(), summary.count, summary.truncated), (3, 3, true));
+        let (hits, summary) = run(find(&dir, "main.rs", false, Some(10)), &dir);
+        assert_eq!((hits, summary.truncated), (vec!["zzz/main.rs".to_string()], false));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
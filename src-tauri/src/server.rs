@@ -1,12 +1,35 @@
 use crate::tmux;
 use crate::fs as rfs;
+use crate::fswatch;
+use crate::search;
+use crate::tree;
+use crate::du;
+use crate::text;
+use crate::config::Config;
+use crate::triggers;
+use crate::watchers;
+use crate::recording;
+use crate::logs;
+use crate::replay;
+use crate::snippets;
+use crate::scheduler;
+use crate::wait;
+use crate::exec;
+use crate::git;
+use crate::sandbox;
+use crate::trash;
+use crate::transfer;
+use crate::thumbnail;
+use crate::archive;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::task::JoinSet;
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 
 // JSON-RPC style request/response
@@ -17,6 +40,9 @@ struct Request {
     method: String,
     #[serde(default)]
     params: serde_json::Value,
+    /// Peer address, filled in by the connection
+    #[serde(skip)]
+    client: String,
 }
 
 #[derive(Serialize, Clone)]
@@ -32,6 +58,8 @@ struct Response {
 struct ErrorInfo {
     code: i32,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
 }
 
 // Error codes
@@ -40,19 +68,89 @@ const ERR_METHOD_NOT_FOUND: i32 = -32601;
 const ERR_INVALID_PARAMS: i32 = -32602;
 const ERR_INTERNAL: i32 = -32603;
 const ERR_AUTH: i32 = -32000;
+const ERR_TIMEOUT: i32 = -32001;
+const ERR_FORBIDDEN: i32 = -32002;
+const ERR_CONFLICT: i32 = -32003;
 
 impl Response {
     fn ok(id: Option<u64>, result: serde_json::Value) -> Self {
         Self { id, result: Some(result), error: None }
     }
     fn err(id: Option<u64>, code: i32, message: String) -> Self {
-        Self { id, result: None, error: Some(ErrorInfo { code, message }) }
+        Self { id, result: None, error: Some(ErrorInfo { code, message, data: None }) }
+    }
+    fn err_data(id: Option<u64>, code: i32, message: String, data: serde_json::Value) -> Self {
+        Self { id, result: None, error: Some(ErrorInfo { code, message, data: Some(data) }) }
     }
 }
 
 // Per-connection subscription state: target -> last captured content
 type Subscriptions = Arc<Mutex<HashMap<String, String>>>;
 
+// Per-connection recording replays: target -> control channel (dropping it stops the replay)
+type Replays = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<replay::Control>>>>;
+
+// Per-connection `exec` processes: id -> kill switch
+type Execs = Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>;
+
+// Per-connection followed files: id -> polling task
+type Follows = Arc<Mutex<HashMap<String, tokio::task::AbortHandle>>>;
+
+// Per-connection running searches: id -> cancel flag
+type Searches = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+// Per-connection running copies: id -> cancel flag
+type Copies = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+/// Least time between two `fs_copy_progress` notifications
+const COPY_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+type WsSender = Arc<Mutex<futures_util::stream::SplitSink<
+    tokio_tungstenite::WebSocketStream<TcpStream>, Message
+>>>;
+
+// Server-wide notifications, fanned out to every authenticated connection
+static EVENTS: OnceLock<broadcast::Sender<String>> = OnceLock::new();
+
+fn events() -> &'static broadcast::Sender<String> {
+    EVENTS.get_or_init(|| broadcast::channel(256).0)
+}
+
+fn notification(method: &str, params: serde_json::Value) -> String {
+    serde_json::json!({ "id": null, "method": method, "params": params }).to_string()
+}
+
+/// Push a notification to all connected clients (dropped if nobody listens)
+fn notify_all(method: &str, params: serde_json::Value) {
+    let _ = events().send(notification(method, params));
+}
+
+// Policy denials get their own code so clients can tell them from I/O errors
+fn fs_error(id: Option<u64>, e: String) -> Response {
+    let code = if e.starts_with(sandbox::DENIED) { ERR_FORBIDDEN } else { ERR_INTERNAL };
+    Response::err(id, code, e)
+}
+
+fn git_response(id: Option<u64>, r: Result<serde_json::Value, git::GitError>) -> Response {
+    match r {
+        Ok(v) => Response::ok(id, v),
+        Err(git::GitError::Conflict(status)) => Response::err_data(
+            id,
+            ERR_CONFLICT,
+            "repository changed since it was last read".into(),
+            serde_json::to_value(status).unwrap(),
+        ),
+        Err(git::GitError::Failed(e)) => fs_error(id, e),
+    }
+}
+
+fn require_str_list(params: &serde_json::Value, key: &str) -> Result<Vec<String>, String> {
+    params.get(key)
+        .and_then(|v| v.as_array())
+        .and_then(|a| a.iter().map(|v| v.as_str().map(str::to_string)).collect())
+        .ok_or_else(|| format!("missing required param: {} (array of strings)", key))
+}
+
 fn require_str<'a>(params: &'a serde_json::Value, key: &str) -> Result<&'a str, String> {
     params.get(key)
         .and_then(|v| v.as_str())
@@ -177,9 +275,10 @@ fn handle_request(req: &Request) -> Response {
                 Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
             };
             let show_hidden = p.get("show_hidden").and_then(|v| v.as_bool()).unwrap_or(false);
-            match rfs::list_dir(path, show_hidden) {
+            let thumbnails = p.get("thumbnails").and_then(|v| v.as_bool()).unwrap_or(false);
+            match rfs::list_dir(path, show_hidden, thumbnails) {
                 Ok(entries) => Response::ok(id, serde_json::json!({ "entries": entries, "path": path })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -190,7 +289,56 @@ fn handle_request(req: &Request) -> Response {
             };
             match rfs::stat_file(path) {
                 Ok(stat) => Response::ok(id, serde_json::to_value(&stat).unwrap()),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_thumbnail" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let size = p.get("size").and_then(|v| v.as_u64()).map_or(thumbnail::DEFAULT_SIZE, |s| s.min(u32::MAX as u64) as u32);
+            let format = match p.get("format").and_then(|v| v.as_str()).map(thumbnail::Format::parse).transpose() {
+                Ok(f) => f,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match thumbnail::thumbnail(path, size, format) {
+                Ok(t) => Response::ok(id, serde_json::to_value(&t).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_archive_list" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match archive::list(path) {
+                Ok(listing) => Response::ok(id, serde_json::to_value(&listing).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_tree" => {
+            let walk = serde_json::from_value(p.clone())
+                .map_err(|e| format!("invalid fs_tree params: {}", e))
+                .and_then(tree::prepare);
+            match walk {
+                Ok(walk) => Response::ok(id, serde_json::to_value(walk.run()).unwrap()),
+                Err(e) if e.starts_with(sandbox::DENIED) => fs_error(id, e),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "fs_df" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match du::df(path) {
+                Ok(space) => Response::ok(id, serde_json::to_value(space).unwrap()),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -199,9 +347,59 @@ fn handle_request(req: &Request) -> Response {
                 Ok(s) => s,
                 Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
             };
-            match rfs::read_file(path) {
-                Ok(content) => Response::ok(id, serde_json::json!({ "content": content })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            let offset = p.get("offset").and_then(|v| v.as_u64());
+            let length = p.get("length").and_then(|v| v.as_u64());
+            let line = p.get("line").and_then(|v| v.as_u64());
+            let lines = p.get("lines").and_then(|v| v.as_u64()).map(|n| n as usize);
+            let chunk = match (line, offset) {
+                (Some(line), _) => text::read_lines(path, line as usize, lines),
+                (None, Some(offset)) => text::read_bytes(path, offset, length),
+                (None, None) => {
+                    return match rfs::read_file(path) {
+                        Ok((content, etag, encoding)) => Response::ok(id, serde_json::json!({
+                            "content": content, "etag": etag, "encoding": encoding,
+                        })),
+                        Err(e) => fs_error(id, e),
+                    };
+                }
+            };
+            match chunk {
+                Ok(chunk) => Response::ok(id, serde_json::to_value(chunk).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_tail" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let lines = p.get("lines").and_then(|v| v.as_u64()).unwrap_or(100) as usize;
+            match text::tail(path, lines) {
+                Ok(chunk) => Response::ok(id, serde_json::to_value(chunk).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_diff" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let base = match (p.get("other").and_then(|v| v.as_str()), p.get("content").and_then(|v| v.as_str())) {
+                (Some(other), None) => rfs::DiffBase::Path(other),
+                (None, Some(content)) => rfs::DiffBase::Content(content),
+                (None, None) => rfs::DiffBase::Snapshot,
+                _ => return Response::err(id, ERR_INVALID_PARAMS, "pass at most one of other and content".into()),
+            };
+            let context = p.get("context").and_then(|v| v.as_u64()).unwrap_or(3).min(1000) as usize;
+            match rfs::diff_file(path, base, context) {
+                Ok(c) => Response::ok(id, serde_json::json!({
+                    "diff": c.diff,
+                    "identical": c.identical,
+                    "snapshot_time": c.snapshot_time,
+                })),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -214,9 +412,17 @@ fn handle_request(req: &Request) -> Response {
                 Ok(s) => s,
                 Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
             };
-            match rfs::write_file(path, content) {
-                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            let if_match = p.get("if_match").and_then(|v| v.as_str());
+            let encoding = p.get("encoding").and_then(|v| v.as_str());
+            match rfs::write_file(path, content, if_match, encoding) {
+                Ok(etag) => Response::ok(id, serde_json::json!({ "ok": true, "etag": etag })),
+                Err(rfs::WriteError::Conflict(current)) => Response::err_data(
+                    id,
+                    ERR_CONFLICT,
+                    "file changed since it was read".into(),
+                    serde_json::to_value(current).unwrap(),
+                ),
+                Err(rfs::WriteError::Failed(e)) => fs_error(id, e),
             }
         }
 
@@ -227,7 +433,7 @@ fn handle_request(req: &Request) -> Response {
             };
             match rfs::create_dir(path) {
                 Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -236,8 +442,45 @@ fn handle_request(req: &Request) -> Response {
                 Ok(s) => s,
                 Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
             };
-            match rfs::delete_path(path) {
-                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+            if p.get("permanent").and_then(|v| v.as_bool()).unwrap_or(false) {
+                return match rfs::delete_path(path) {
+                    Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                    Err(e) => fs_error(id, e),
+                };
+            }
+            match trash::move_to_trash(path, &req.client) {
+                Ok(item) => Response::ok(id, serde_json::json!({ "ok": true, "trashed": item })),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_trash_list" => match trash::list() {
+            Ok(items) => Response::ok(id, serde_json::json!({ "items": items })),
+            Err(e) => Response::err(id, ERR_INTERNAL, e),
+        },
+
+        "fs_trash_restore" => {
+            let trash_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let to = p.get("to").and_then(|v| v.as_str());
+            match trash::restore(trash_id, to) {
+                Ok(path) => Response::ok(id, serde_json::json!({ "restored": path })),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_trash_empty" => {
+            let ids = match p.get("ids") {
+                Some(_) => match require_str_list(p, "ids") {
+                    Ok(ids) => Some(ids),
+                    Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+                },
+                None => None,
+            };
+            match trash::empty(ids.as_deref()) {
+                Ok(removed) => Response::ok(id, serde_json::json!({ "removed": removed })),
                 Err(e) => Response::err(id, ERR_INTERNAL, e),
             }
         }
@@ -253,7 +496,54 @@ fn handle_request(req: &Request) -> Response {
             };
             match rfs::rename_path(from, to) {
                 Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_chmod" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let mode = match require_str(p, "mode") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let recursive = p.get("recursive").and_then(|v| v.as_bool()).unwrap_or(false);
+            match rfs::chmod(path, mode, recursive) {
+                Ok((path, mode)) => Response::ok(id, serde_json::json!({
+                    "path": path,
+                    "mode": format!("{:o}", mode),
+                    "permissions": rfs::format_permissions(mode),
+                })),
+                Err(e) if e.starts_with("invalid mode") => Response::err(id, ERR_INVALID_PARAMS, e),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_symlink" => {
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match rfs::symlink(target, path) {
+                Ok(path) => Response::ok(id, serde_json::json!({ "path": path })),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "fs_readlink" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match rfs::read_link(path) {
+                Ok(info) => Response::ok(id, serde_json::to_value(info).unwrap()),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -264,7 +554,7 @@ fn handle_request(req: &Request) -> Response {
             };
             match rfs::download_file(path) {
                 Ok((name, data)) => Response::ok(id, serde_json::json!({ "name": name, "data": data })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
             }
         }
 
@@ -279,129 +569,1300 @@ fn handle_request(req: &Request) -> Response {
             };
             match rfs::upload_file(path, data) {
                 Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
-                Err(e) => Response::err(id, ERR_INTERNAL, e),
+                Err(e) => fs_error(id, e),
             }
         }
 
-        _ => Response::err(id, ERR_METHOD_NOT_FOUND, format!("unknown method: {}", req.method)),
-    }
-}
+        "add_trigger" => {
+            let spec = match serde_json::from_value::<triggers::TriggerSpec>(p.clone()) {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, format!("invalid trigger: {}", e)),
+            };
+            match triggers::add(spec) {
+                Ok(info) => Response::ok(id, serde_json::to_value(&info).unwrap()),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
 
-// Subscription polling task: captures pane content and sends diffs
-async fn subscription_loop(
-    sender: Arc<Mutex<futures_util::stream::SplitSink<
-        tokio_tungstenite::WebSocketStream<TcpStream>, Message
-    >>>,
-    subs: Subscriptions,
-) {
-    let mut interval = tokio::time::interval(std::time::Duration::from_millis(200));
-    loop {
-        interval.tick().await;
-        let targets: Vec<(String, String)> = {
-            let map = subs.lock().await;
-            map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
-        };
-        if targets.is_empty() {
-            continue;
+        "list_triggers" => Response::ok(id, serde_json::json!({ "triggers": triggers::list() })),
+
+        "remove_trigger" => {
+            let trigger_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match triggers::remove(trigger_id) {
+                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
         }
-        for (target, prev) in targets {
-            let t = target.clone();
-            let new_content = match tokio::task::spawn_blocking(move || {
-                tmux::capture_pane(&t, None)
-            }).await {
-                Ok(Ok(c)) => c,
-                _ => continue,
+
+        "trigger_log" => {
+            let limit = p.get("limit").and_then(|v| v.as_u64()).unwrap_or(100) as usize;
+            Response::ok(id, serde_json::json!({ "firings": triggers::log(limit) }))
+        }
+
+        "add_watch" => {
+            let spec = match serde_json::from_value::<watchers::WatchSpec>(p.clone()) {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, format!("invalid watch: {}", e)),
             };
-            if new_content == prev {
-                continue;
+            match watchers::add(spec) {
+                Ok(state) => Response::ok(id, serde_json::to_value(&state).unwrap()),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
             }
-            // Update stored content
-            subs.lock().await.insert(target.clone(), new_content.clone());
-            // Push update to client
-            let msg = serde_json::json!({
-                "id": null,
-                "method": "pane_output",
-                "params": { "target": target, "content": new_content }
-            });
-            let text = serde_json::to_string(&msg).unwrap();
-            let mut tx = sender.lock().await;
-            if tx.send(Message::Text(text.into())).await.is_err() {
-                return; // connection closed
+        }
+
+        "remove_watch" => {
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match watchers::remove(target) {
+                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
             }
         }
-    }
-}
 
-fn handle_subscribe(params: &serde_json::Value, subs: &mut HashMap<String, String>) -> Response {
-    let target = match require_str(params, "target") {
-        Ok(s) => s,
-        Err(e) => return Response::err(None, ERR_INVALID_PARAMS, e),
-    };
-    subs.insert(target.to_string(), String::new());
-    Response::ok(None, serde_json::json!({ "subscribed": target }))
-}
+        "list_watches" => Response::ok(id, serde_json::json!({ "watchers": watchers::list() })),
 
-fn handle_unsubscribe(params: &serde_json::Value, subs: &mut HashMap<String, String>) -> Response {
-    let target = match require_str(params, "target") {
-        Ok(s) => s,
-        Err(e) => return Response::err(None, ERR_INVALID_PARAMS, e),
-    };
-    subs.remove(target);
-    Response::ok(None, serde_json::json!({ "unsubscribed": target }))
-}
+        "watch_summary" => {
+            let since = p.get("since").and_then(|v| v.as_u64()).unwrap_or(0);
+            Response::ok(id, watchers::summary(since))
+        }
 
-async fn handle_connection(stream: TcpStream, addr: SocketAddr, token: Arc<String>) {
-    println!("📱 Client connected: {}", addr);
+        "start_recording" => {
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let name = p.get("name").and_then(|v| v.as_str());
+            match recording::start(target, name) {
+                Ok(info) => Response::ok(id, serde_json::to_value(&info).unwrap()),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            }
+        }
 
-    let ws_stream = match accept_async(stream).await {
-        Ok(ws) => ws,
-        Err(e) => {
-            eprintln!("❌ WebSocket handshake failed for {}: {}", addr, e);
-            return;
+        "stop_recording" => {
+            let rec_id = p.get("id").and_then(|v| v.as_str());
+            let target = p.get("target").and_then(|v| v.as_str());
+            match recording::stop(rec_id, target) {
+                Ok(stopped) => Response::ok(id, serde_json::json!({ "ok": true, "id": stopped })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
         }
-    };
 
-    let (ws_sender, mut receiver) = ws_stream.split();
-    let sender = Arc::new(Mutex::new(ws_sender));
-    let subs: Subscriptions = Arc::new(Mutex::new(HashMap::new()));
-    let mut authenticated = false;
+        "list_recordings" => match recording::list() {
+            Ok(recs) => Response::ok(id, serde_json::json!({ "recordings": recs })),
+            Err(e) => Response::err(id, ERR_INTERNAL, e),
+        },
 
-    // Start subscription polling task
-    let sub_handle = tokio::spawn(subscription_loop(sender.clone(), subs.clone()));
+        "get_recording" => {
+            let rec_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let start = p.get("start").and_then(|v| v.as_f64());
+            let end = p.get("end").and_then(|v| v.as_f64());
+            match recording::get(rec_id, start, end) {
+                Ok(rec) => Response::ok(id, rec),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            }
+        }
 
-    while let Some(msg) = receiver.next().await {
-        let msg = match msg {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("❌ Read error from {}: {}", addr, e);
-                break;
+        "start_log" => {
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let strip_ansi = p.get("strip_ansi").and_then(|v| v.as_bool());
+            match logs::start(target, strip_ansi) {
+                Ok(name) => Response::ok(id, serde_json::json!({ "ok": true, "name": name })),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
             }
-        };
+        }
 
-        match msg {
-            Message::Text(text) => {
-                let response = match serde_json::from_str::<Request>(&text) {
-                    Ok(req) => {
-                        // Auth gate: first message must be "auth"
-                        if !authenticated {
-                            if req.method == "auth" {
-                                let provided = req.params.get("token")
-                                    .and_then(|v| v.as_str())
-                                    .unwrap_or("");
-                                if provided == token.as_str() {
-                                    authenticated = true;
-                                    Response::ok(req.id, serde_json::json!({ "authenticated": true }))
-                                } else {
-                                    let r = Response::err(req.id, ERR_AUTH, "invalid token".into());
-                                    let json = serde_json::to_string(&r).unwrap();
-                                    let mut tx = sender.lock().await;
-                                    let _ = tx.send(Message::Text(json.into())).await;
-                                    let _ = tx.send(Message::Close(None)).await;
-                                    break;
-                                }
-                            } else {
-                                let r = Response::err(req.id, ERR_AUTH, "auth required — send {\"method\":\"auth\",\"params\":{\"token\":\"...\"}} first".into());
-                                let json = serde_json::to_string(&r).unwrap();
+        "stop_log" => {
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match logs::stop(target) {
+                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "list_logs" => match logs::list() {
+            Ok(list) => Response::ok(id, serde_json::json!({ "logs": list })),
+            Err(e) => Response::err(id, ERR_INTERNAL, e),
+        },
+
+        "tail_log" => {
+            let target = p.get("target").and_then(|v| v.as_str());
+            let name = p.get("name").and_then(|v| v.as_str());
+            let lines = p.get("lines").and_then(|v| v.as_u64()).unwrap_or(100) as usize;
+            match logs::tail(target, name, lines) {
+                Ok(lines) => Response::ok(id, serde_json::json!({ "lines": lines })),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            }
+        }
+
+        "search_log" => {
+            let pattern = match require_str(p, "pattern") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let target = p.get("target").and_then(|v| v.as_str());
+            let name = p.get("name").and_then(|v| v.as_str());
+            let max_results = p.get("max_results").and_then(|v| v.as_u64()).unwrap_or(200) as usize;
+            match logs::search(target, name, pattern, max_results) {
+                Ok((matches, truncated)) => Response::ok(id, serde_json::json!({ "matches": matches, "truncated": truncated })),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            }
+        }
+
+        "list_snippets" => {
+            let session = p.get("session").and_then(|v| v.as_str());
+            match snippets::list(session) {
+                Ok(list) => Response::ok(id, serde_json::json!({ "snippets": list })),
+                Err(e) => Response::err(id, ERR_INTERNAL, e),
+            }
+        }
+
+        "save_snippet" => {
+            let snippet = match serde_json::from_value::<snippets::Snippet>(p.clone()) {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, format!("invalid snippet: {}", e)),
+            };
+            match snippets::save_snippet(snippet) {
+                Ok(info) => Response::ok(id, serde_json::to_value(&info).unwrap()),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "delete_snippet" => {
+            let snippet_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match snippets::delete(snippet_id) {
+                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "run_snippet" => {
+            let snippet_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let target = match require_str(p, "target") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            // Placeholder values: strings as-is, other JSON scalars stringified
+            let values: HashMap<String, String> = p.get("params")
+                .and_then(|v| v.as_object())
+                .map(|o| o.iter().map(|(k, v)| {
+                    (k.clone(), v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()))
+                }).collect())
+                .unwrap_or_default();
+            match snippets::run(snippet_id, target, &values) {
+                Ok(command) => Response::ok(id, serde_json::json!({ "ok": true, "command": command })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "schedule_job" => {
+            let job = match serde_json::from_value::<scheduler::JobRequest>(p.clone()) {
+                Ok(j) => j,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, format!("invalid job: {}", e)),
+            };
+            match scheduler::schedule(job) {
+                Ok(job) => Response::ok(id, serde_json::to_value(&job).unwrap()),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "list_jobs" => {
+            let (jobs, history) = scheduler::list();
+            Response::ok(id, serde_json::json!({ "jobs": jobs, "history": history }))
+        }
+
+        "cancel_job" => {
+            let job_id = match require_str(p, "id") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match scheduler::cancel(job_id) {
+                Ok(()) => Response::ok(id, serde_json::json!({ "ok": true })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "git_status" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match git::status(path) {
+                Ok(status) => Response::ok(id, serde_json::to_value(status).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "git_diff" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let file = p.get("file").and_then(|v| v.as_str());
+            let context = p.get("context").and_then(|v| v.as_u64()).map(|n| n as usize);
+            let untracked = p.get("untracked").and_then(|v| v.as_bool()).unwrap_or(true);
+            match git::diff(path, file, context, untracked) {
+                Ok(diff) => Response::ok(id, serde_json::to_value(diff).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "git_log" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let limit = p.get("limit").and_then(|v| v.as_u64()).unwrap_or(50) as usize;
+            let skip = p.get("skip").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+            let file = p.get("file").and_then(|v| v.as_str());
+            match git::log(path, limit, skip, file) {
+                Ok(commits) => Response::ok(id, serde_json::json!({ "commits": commits })),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "git_show" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let rev = match require_str(p, "rev") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match git::show(path, rev) {
+                Ok(show) => Response::ok(id, serde_json::to_value(show).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "git_stage" | "git_unstage" | "git_discard" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let files = match require_str_list(p, "files") {
+                Ok(f) => f,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let expected = match require_str(p, "expected_state") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let r = match req.method.as_str() {
+                "git_stage" => git::stage(path, &files, expected),
+                "git_unstage" => git::unstage(path, &files, expected),
+                _ => git::discard(path, &files, expected),
+            };
+            git_response(id, r.map(|s| serde_json::to_value(s).unwrap()))
+        }
+
+        "git_stage_hunk" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let file = match require_str(p, "file") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let hunk = match p.get("hunk").and_then(|v| v.as_u64()) {
+                Some(n) => n as usize,
+                None => return Response::err(id, ERR_INVALID_PARAMS, "missing required param: hunk".into()),
+            };
+            let expected = match require_str(p, "expected_state") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let context = p.get("context").and_then(|v| v.as_u64()).map(|n| n as usize);
+            let unstage = p.get("unstage").and_then(|v| v.as_bool()).unwrap_or(false);
+            git_response(id, git::stage_hunk(path, file, hunk, context, unstage, expected).map(|s| serde_json::to_value(s).unwrap()))
+        }
+
+        "git_commit" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let message = match require_str(p, "message") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let expected = match require_str(p, "expected_state") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            git_response(id, git::commit(path, message, expected)
+                .map(|(hash, status)| serde_json::json!({ "commit": hash, "status": status })))
+        }
+
+        "git_stash" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let action = p.get("action").and_then(|v| v.as_str()).unwrap_or("push");
+            if action == "list" {
+                return match git::stash_list(path) {
+                    Ok(stashes) => Response::ok(id, serde_json::json!({ "stashes": stashes })),
+                    Err(e) => fs_error(id, e),
+                };
+            }
+            let expected = match require_str(p, "expected_state") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let message = p.get("message").and_then(|v| v.as_str());
+            let index = p.get("index").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+            let untracked = p.get("include_untracked").and_then(|v| v.as_bool()).unwrap_or(false);
+            git_response(id, git::stash(path, action, message, index, untracked, expected).map(|s| serde_json::to_value(s).unwrap()))
+        }
+
+        "transfer_upload" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let Some(size) = p.get("size").and_then(|v| v.as_u64()) else {
+                return Response::err(id, ERR_INVALID_PARAMS, "missing 'size'".into());
+            };
+            let sha256 = match require_str(p, "sha256") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let chunk_size = p.get("chunk_size").and_then(|v| v.as_u64()).map(|n| n as usize);
+            let overwrite = p.get("overwrite").and_then(|v| v.as_bool()).unwrap_or(false);
+            match transfer::start_upload(path, size, sha256, chunk_size, overwrite) {
+                Ok(info) => Response::ok(id, serde_json::to_value(info).unwrap()),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "transfer_list" => Response::ok(id, serde_json::json!({ "transfers": transfer::list() })),
+
+        "transfer_cancel" => {
+            let transfer_id = match require_str(p, "id").and_then(transfer::parse_id) {
+                Ok(t) => t,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match transfer::cancel(&transfer_id) {
+                Ok(info) => Response::ok(id, serde_json::json!({ "cancelled": info })),
+                Err(e) => Response::err(id, ERR_INVALID_PARAMS, e),
+            }
+        }
+
+        "git_branches" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            match git::branches(path) {
+                Ok(branches) => Response::ok(id, serde_json::json!({ "branches": branches })),
+                Err(e) => fs_error(id, e),
+            }
+        }
+
+        "git_checkout" => {
+            let path = match require_str(p, "path") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let branch = match require_str(p, "branch") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let expected = match require_str(p, "expected_state") {
+                Ok(s) => s,
+                Err(e) => return Response::err(id, ERR_INVALID_PARAMS, e),
+            };
+            let create = p.get("create").and_then(|v| v.as_bool()).unwrap_or(false);
+            git_response(id, git::checkout(path, branch, create, expected).map(|s| serde_json::to_value(s).unwrap()))
+        }
+
+        _ => Response::err(id, ERR_METHOD_NOT_FOUND, format!("unknown method: {}", req.method)),
+    }
+}
+
+// Server-wide polling task for watchers and triggers: runs regardless of
+// connected clients, each pane is captured once per tick
+async fn watch_loop() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(500));
+    loop {
+        interval.tick().await;
+        let watched = watchers::targets();
+        let mut targets = watched.clone();
+        targets.extend(triggers::targets());
+        targets.sort();
+        targets.dedup();
+        if targets.is_empty() {
+            continue;
+        }
+        let (events, fired) = tokio::task::spawn_blocking(move || {
+            let mut events = Vec::new();
+            let mut fired = Vec::new();
+            for t in &targets {
+                let capture = tmux::capture_pane_plain(t, None);
+                // Trigger-only targets just need the content
+                if watched.contains(t) {
+                    let command = tmux::pane_command(t).ok();
+                    events.extend(watchers::observe(t, capture.as_deref().map_err(|e| e.as_str()), command.as_deref()));
+                }
+                if let Ok(content) = &capture {
+                    for f in triggers::process(t, content) {
+                        events.push(watchers::record(t, "trigger", format!("{}: {}", f.trigger_id, f.line)));
+                        fired.push(f);
+                    }
+                }
+            }
+            (events, fired)
+        }).await.unwrap_or_default();
+        for ev in events {
+            notify_all("watch_event", serde_json::to_value(&ev).unwrap());
+        }
+        for f in fired {
+            notify_all("trigger_fired", serde_json::to_value(&f).unwrap());
+        }
+    }
+}
+
+// Runs scheduled jobs when they are due
+async fn scheduler_loop() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let runs = tokio::task::spawn_blocking(scheduler::run_due).await.unwrap_or_default();
+        for run in runs {
+            let detail = format!("{}: {}", run.job_id, run.status);
+            let ev = watchers::record(&run.target, "job", detail);
+            notify_all("watch_event", serde_json::to_value(&ev).unwrap());
+            notify_all("job_run", serde_json::to_value(&run).unwrap());
+        }
+    }
+}
+
+// Forward server-wide notifications to one client
+async fn forward_events(sender: WsSender, mut rx: broadcast::Receiver<String>) {
+    loop {
+        let text = match rx.recv().await {
+            Ok(t) => t,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let mut tx = sender.lock().await;
+        if tx.send(Message::Text(text.into())).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Subscription polling task: captures pane content and sends diffs
+async fn subscription_loop(sender: WsSender, subs: Subscriptions) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(200));
+    loop {
+        interval.tick().await;
+        let targets: Vec<(String, String)> = {
+            let map = subs.lock().await;
+            map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        };
+        if targets.is_empty() {
+            continue;
+        }
+        for (target, prev) in targets {
+            let t = target.clone();
+            let new_content = match tokio::task::spawn_blocking(move || {
+                tmux::capture_pane(&t, None)
+            }).await {
+                Ok(Ok(c)) => c,
+                _ => continue,
+            };
+            if new_content == prev {
+                continue;
+            }
+            // Update stored content
+            subs.lock().await.insert(target.clone(), new_content.clone());
+            // Push update to client
+            let text = notification("pane_output", serde_json::json!({ "target": target, "content": new_content }));
+            let mut tx = sender.lock().await;
+            if tx.send(Message::Text(text.into())).await.is_err() {
+                return; // connection closed
+            }
+        }
+    }
+}
+
+fn is_replay(params: &serde_json::Value) -> bool {
+    params.get("target").and_then(|v| v.as_str()).is_some_and(replay::is_replay_target)
+}
+
+fn handle_subscribe(params: &serde_json::Value, subs: &mut HashMap<String, String>) -> Response {
+    let target = match require_str(params, "target") {
+        Ok(s) => s,
+        Err(e) => return Response::err(None, ERR_INVALID_PARAMS, e),
+    };
+    subs.insert(target.to_string(), String::new());
+    Response::ok(None, serde_json::json!({ "subscribed": target }))
+}
+
+// Replay task: plays a recording back as `pane_output` snapshots, sent when
+// the rendered screen changes, playback finishes or a control changes the
+// replay's state
+async fn replay_loop(
+    sender: WsSender,
+    target: String,
+    mut player: replay::Player,
+    mut controls: mpsc::UnboundedReceiver<replay::Control>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(200));
+    let mut last = tokio::time::Instant::now();
+    let mut played = true;
+    let mut controlled = true;
+    let mut sent = (String::new(), false);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now = tokio::time::Instant::now();
+                played |= player.advance((now - last).as_secs_f64());
+                last = now;
+            }
+            c = controls.recv() => match c {
+                Some(c) => {
+                    player.control(c);
+                    controlled = true;
+                }
+                None => return, // unsubscribed or connection closed
+            },
+        }
+        if !played && !controlled {
+            continue;
+        }
+        played = false;
+        let current = (player.screen(), player.finished());
+        if current == sent && !controlled {
+            continue;
+        }
+        controlled = false;
+        let mut params = player.state();
+        params["target"] = serde_json::json!(target);
+        params["content"] = serde_json::json!(current.0);
+        sent = current;
+        let text = notification("pane_output", params);
+        let mut tx = sender.lock().await;
+        if tx.send(Message::Text(text.into())).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn handle_replay_subscribe(req: &Request, sender: &WsSender, replays: &Replays) -> Response {
+    let target = match require_str(&req.params, "target") {
+        Ok(s) => s.to_string(),
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let speed = req.params.get("speed").and_then(|v| v.as_f64()).unwrap_or(1.0);
+    let t = target.clone();
+    let player = match tokio::task::spawn_blocking(move || replay::Player::load(&t, speed)).await {
+        Ok(Ok(p)) => p,
+        Ok(Err(e)) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    let state = player.state();
+    let (tx, rx) = mpsc::unbounded_channel();
+    replays.lock().await.insert(target.clone(), tx);
+    tokio::spawn(replay_loop(sender.clone(), target.clone(), player, rx));
+    Response::ok(req.id, serde_json::json!({ "subscribed": target, "replay": state }))
+}
+
+async fn handle_replay_control(req: &Request, replays: &Replays) -> Response {
+    let target = match require_str(&req.params, "target") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let control = match replay::Control::from_params(&req.params) {
+        Ok(c) => c,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    match replays.lock().await.get(target) {
+        Some(tx) if tx.send(control).is_ok() => Response::ok(req.id, serde_json::json!({ "ok": true })),
+        _ => Response::err(req.id, ERR_INVALID_PARAMS, format!("no replay subscribed: {}", target)),
+    }
+}
+
+fn handle_unsubscribe(params: &serde_json::Value, subs: &mut HashMap<String, String>) -> Response {
+    let target = match require_str(params, "target") {
+        Ok(s) => s,
+        Err(e) => return Response::err(None, ERR_INVALID_PARAMS, e),
+    };
+    subs.remove(target);
+    Response::ok(None, serde_json::json!({ "unsubscribed": target }))
+}
+
+async fn handle_wait_for(req: Request) -> Response {
+    let target = match require_str(&req.params, "target") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let re = match require_str(&req.params, "pattern").and_then(|p| regex::Regex::new(p).map_err(|e| format!("invalid pattern: {}", e))) {
+        Ok(r) => r,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let timeout = req.params.get("timeout").and_then(|v| v.as_f64()).unwrap_or(30.0);
+    if !timeout.is_finite() || timeout <= 0.0 || timeout > wait::MAX_TIMEOUT.as_secs_f64() {
+        return Response::err(req.id, ERR_INVALID_PARAMS, format!("timeout must be between 0 and {} seconds", wait::MAX_TIMEOUT.as_secs()));
+    }
+    let context = req.params.get("context").and_then(|v| v.as_u64()).unwrap_or(3).min(100) as usize;
+    let since_now = req.params.get("since_now").and_then(|v| v.as_bool()).unwrap_or(true);
+    match wait::wait_for(target, &re, std::time::Duration::from_secs_f64(timeout), context, since_now).await {
+        Ok(Some(m)) => Response::ok(req.id, serde_json::to_value(m).unwrap()),
+        Ok(None) => Response::err(req.id, ERR_TIMEOUT, format!("timed out after {}s waiting for {:?}", timeout, re.as_str())),
+        Err(e) => Response::err(req.id, ERR_INTERNAL, e),
+    }
+}
+
+async fn handle_exec_in_pane(req: Request) -> Response {
+    let target = match require_str(&req.params, "target") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let command = match require_str(&req.params, "command") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let timeout = req.params.get("timeout").and_then(|v| v.as_f64()).unwrap_or(60.0);
+    if !timeout.is_finite() || timeout <= 0.0 || timeout > wait::MAX_TIMEOUT.as_secs_f64() {
+        return Response::err(req.id, ERR_INVALID_PARAMS, format!("timeout must be between 0 and {} seconds", wait::MAX_TIMEOUT.as_secs()));
+    }
+    let max_lines = req.params.get("max_lines").and_then(|v| v.as_u64()).unwrap_or(2000) as usize;
+    let interrupt = req.params.get("interrupt_on_timeout").and_then(|v| v.as_bool()).unwrap_or(false);
+    match wait::exec_in_pane(target, command, std::time::Duration::from_secs_f64(timeout), max_lines, interrupt).await {
+        Ok(r) => Response::ok(req.id, serde_json::to_value(r).unwrap()),
+        Err(wait::ExecError::Invalid(e)) => Response::err(req.id, ERR_INVALID_PARAMS, e),
+        Err(wait::ExecError::Failed(e)) => Response::err(req.id, ERR_INTERNAL, e),
+        Err(wait::ExecError::Timeout(partial)) => Response::err_data(
+            req.id,
+            ERR_TIMEOUT,
+            format!("command did not finish within {}s", timeout),
+            serde_json::to_value(partial).unwrap(),
+        ),
+    }
+}
+
+// Long-running requests reply from their own task so the connection keeps
+// serving others; the set is dropped (aborting them) on disconnect
+fn spawn_reply<F>(pending: &mut JoinSet<()>, sender: WsSender, fut: F)
+where
+    F: std::future::Future<Output = Response> + Send + 'static,
+{
+    while pending.try_join_next().is_some() {}
+    pending.spawn(async move {
+        let json = serde_json::to_string(&fut.await).unwrap();
+        let _ = sender.lock().await.send(Message::Text(json.into())).await;
+    });
+}
+
+// fs_du: the scan may take its whole time budget, so it runs beside the
+// connection's other requests
+async fn handle_du(req: Request) -> Response {
+    let params: du::DuRequest = match serde_json::from_value(req.params) {
+        Ok(p) => p,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, format!("invalid fs_du params: {}", e)),
+    };
+    match tokio::task::spawn_blocking(move || du::du(params)).await {
+        Ok(Ok(usage)) => Response::ok(req.id, serde_json::to_value(usage).unwrap()),
+        Ok(Err(e)) => fs_error(req.id, e),
+        Err(e) => Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    }
+}
+
+// Starts the process and replies before any of its output is forwarded
+async fn handle_exec(req: &Request, sender: &WsSender, execs: &Execs, pending: &mut JoinSet<()>) -> Response {
+    if !exec::enabled() {
+        return Response::err(req.id, ERR_FORBIDDEN, "exec is disabled (set [exec] enabled = true in config.toml)".into());
+    }
+    let spec: exec::ExecRequest = match serde_json::from_value(req.params.clone()) {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, format!("invalid exec params: {}", e)),
+    };
+    let process = match exec::spawn(spec) {
+        Ok(p) => p,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let id = process.id.clone();
+    let pid = process.pid;
+    let (kill_tx, kill_rx) = oneshot::channel();
+    execs.lock().await.insert(id.clone(), kill_tx);
+
+    let response = Response::ok(req.id, serde_json::json!({ "id": id, "pid": pid }));
+    let json = serde_json::to_string(&response).unwrap();
+    let _ = sender.lock().await.send(Message::Text(json.into())).await;
+
+    let sender = sender.clone();
+    let execs = execs.clone();
+    while pending.try_join_next().is_some() {}
+    pending.spawn(async move {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let forward = async {
+            while let Some((method, params)) = rx.recv().await {
+                let msg = notification(method, params);
+                if sender.lock().await.send(Message::Text(msg.into())).await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::join!(exec::run(process, kill_rx, tx), forward);
+        execs.lock().await.remove(&id);
+    });
+    response
+}
+
+async fn handle_exec_kill(req: &Request, execs: &Execs) -> Response {
+    let id = match require_str(&req.params, "id") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    match execs.lock().await.remove(id) {
+        Some(kill) => {
+            let _ = kill.send(());
+            Response::ok(req.id, serde_json::json!({ "killed": id }))
+        }
+        None => Response::err(req.id, ERR_INVALID_PARAMS, format!("no running process: {}", id)),
+    }
+}
+
+// Registers a download, or picks a transfer up again after a reconnect:
+// a download is streamed from `offset` as binary frames once the reply is
+// out, an upload just reports the offset to continue sending from
+async fn handle_transfer_stream(req: &Request, sender: &WsSender, pending: &mut JoinSet<()>) -> Response {
+    let p = req.params.clone();
+    let started = tokio::task::spawn_blocking(move || -> Result<(transfer::TransferInfo, Option<u64>), String> {
+        let offset = p.get("offset").and_then(|v| v.as_u64());
+        let transfer_id = match p.get("id").and_then(|v| v.as_str()) {
+            Some(t) => {
+                let transfer_id = transfer::parse_id(t)?;
+                let info = transfer::status(&transfer_id)?;
+                if info.direction == "upload" {
+                    return Ok((info, None));
+                }
+                transfer_id
+            }
+            None => {
+                let path = require_str(&p, "path")?;
+                let chunk_size = p.get("chunk_size").and_then(|v| v.as_u64()).map(|n| n as usize);
+                transfer::parse_id(&transfer::start_download(path, chunk_size)?.id)?
+            }
+        };
+        transfer::claim_download(&transfer_id, offset).map(|(info, stream)| (info, Some(stream)))
+    }).await;
+    let (info, stream) = match started {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return fs_error(req.id, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    let response = Response::ok(req.id, serde_json::to_value(&info).unwrap());
+    let Some(stream) = stream else { return response };
+    stream_download(&response, info, stream, sender, pending).await;
+    response
+}
+
+// Send `response`, then the download's chunks as binary frames from a task
+async fn stream_download(response: &Response, info: transfer::TransferInfo, stream: u64, sender: &WsSender, pending: &mut JoinSet<()>) {
+    let json = serde_json::to_string(response).unwrap();
+    let _ = sender.lock().await.send(Message::Text(json.into())).await;
+
+    let sender = sender.clone();
+    while pending.try_join_next().is_some() {}
+    pending.spawn(async move {
+        let transfer_id = transfer::parse_id(&info.id).unwrap();
+        loop {
+            let next = tokio::task::spawn_blocking(move || transfer::read_chunk(&transfer_id, stream)).await;
+            let chunk = match next {
+                Ok(Ok(Some(c))) => c,
+                Ok(Ok(None)) | Err(_) => return, // cancelled or superseded
+                Ok(Err(e)) => {
+                    let msg = notification("transfer_error", serde_json::json!({ "id": info.id, "message": e, "fatal": true }));
+                    let _ = sender.lock().await.send(Message::Text(msg.into())).await;
+                    return;
+                }
+            };
+            let frame = transfer::encode_frame(transfer::FRAME_DOWNLOAD, &transfer_id, chunk.offset, &chunk.data);
+            let mut tx = sender.lock().await;
+            if tx.send(Message::Binary(frame.into())).await.is_err() {
+                return; // the client resumes from its own offset
+            }
+            if chunk.done || chunk.progress {
+                let (method, params) = transfer_notice(&chunk.info, chunk.done);
+                let _ = tx.send(Message::Text(notification(method, params).into())).await;
+            }
+            if chunk.done {
+                return;
+            }
+        }
+    });
+}
+
+// fs_archive, and fs_archive_extract without a `dest`: the result is built
+// in a scratch file and streamed like `transfer_download`, so it can be
+// resumed with `transfer_resume`
+async fn handle_archive(req: &Request, sender: &WsSender, pending: &mut JoinSet<()>) -> Response {
+    let p = req.params.clone();
+    let format = match archive::Format::parse(p.get("format").and_then(|v| v.as_str()).unwrap_or("zip")) {
+        Ok(f) => f,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let archiving = req.method == "fs_archive";
+    let started = tokio::task::spawn_blocking(move || -> Result<(serde_json::Value, Option<(transfer::TransferInfo, u64)>), String> {
+        let path = require_str(&p, "path")?;
+        let chunk_size = p.get("chunk_size").and_then(|v| v.as_u64()).map(|n| n as usize);
+        let (info, extra) = if archiving {
+            let packed = archive::pack(path, format)?;
+            let info = transfer::start_scratch_download(packed.source, packed.file, packed.name, chunk_size)?;
+            (info, serde_json::json!({ "entries": packed.entries, "uncompressed_size": packed.size }))
+        } else {
+            let entry = require_str(&p, "entry")?;
+            if let Some(dest) = p.get("dest").and_then(|v| v.as_str()) {
+                let overwrite = p.get("overwrite").and_then(|v| v.as_bool()).unwrap_or(false);
+                let (dest, size) = archive::extract_to_path(path, entry, dest, overwrite)?;
+                return Ok((serde_json::json!({ "path": dest, "size": size }), None));
+            }
+            let (source, file, name) = archive::extract_for_download(path, entry)?;
+            (transfer::start_scratch_download(source, file, name, chunk_size)?, serde_json::json!({}))
+        };
+        let (info, stream) = transfer::claim_download(&transfer::parse_id(&info.id)?, None)?;
+        let mut result = serde_json::to_value(&info).unwrap();
+        if let (Some(r), Some(extra)) = (result.as_object_mut(), extra.as_object()) {
+            r.extend(extra.clone());
+        }
+        Ok((result, Some((info, stream))))
+    }).await;
+    let (result, download) = match started {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return fs_error(req.id, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    let response = Response::ok(req.id, result);
+    if let Some((info, stream)) = download {
+        stream_download(&response, info, stream, sender, pending).await;
+    }
+    response
+}
+
+fn transfer_notice(info: &transfer::TransferInfo, done: bool) -> (&'static str, serde_json::Value) {
+    if done {
+        ("transfer_complete", serde_json::json!({
+            "id": info.id, "direction": info.direction, "path": info.path, "size": info.size, "sha256": info.sha256,
+        }))
+    } else {
+        ("transfer_progress", serde_json::json!({
+            "id": info.id, "direction": info.direction, "offset": info.offset, "size": info.size,
+        }))
+    }
+}
+
+// Writes one upload frame; returns the notification to send back, if any
+fn handle_upload_frame(frame: &[u8]) -> Option<String> {
+    let frame = match transfer::decode_frame(frame) {
+        Ok(f) if f.kind == transfer::FRAME_UPLOAD => f,
+        Ok(f) => return Some(notification("transfer_error", serde_json::json!({
+            "id": f.id.to_string(), "message": format!("unexpected frame kind {}", f.kind), "fatal": false,
+        }))),
+        Err(e) => return Some(notification("transfer_error", serde_json::json!({ "message": e, "fatal": false }))),
+    };
+    let id = frame.id.to_string();
+    match transfer::write_chunk(&frame.id, frame.offset, frame.data) {
+        Ok(w) if w.done || w.progress => {
+            let (method, params) = transfer_notice(&w.info, w.done);
+            Some(notification(method, params))
+        }
+        Ok(_) => None,
+        Err(transfer::ChunkError::OutOfOrder { expected }) => Some(notification("transfer_error", serde_json::json!({
+            "id": id, "message": format!("expected offset {}", expected), "offset": expected, "fatal": false,
+        }))),
+        Err(transfer::ChunkError::Rejected(e)) => Some(notification("transfer_error", serde_json::json!({
+            "id": id, "message": e, "fatal": false,
+        }))),
+        Err(transfer::ChunkError::Failed(e)) => Some(notification("transfer_error", serde_json::json!({
+            "id": id, "message": e, "fatal": true,
+        }))),
+    }
+}
+
+// fs_watch / fs_unwatch / fs_watches: the watcher and its forwarding task
+// are created on first use and dropped with the connection
+async fn handle_fs_watch(req: &Request, watches: &mut Option<fswatch::Watches>, sender: &WsSender, pending: &mut JoinSet<()>) -> Response {
+    let p = &req.params;
+    if req.method == "fs_watches" {
+        let list = watches.as_ref().map(|w| w.list()).unwrap_or_default();
+        return Response::ok(req.id, serde_json::json!({ "watches": list }));
+    }
+    let path = match require_str(p, "path") {
+        Ok(s) => s.to_string(),
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    if req.method == "fs_unwatch" {
+        return match watches.as_mut().map(|w| w.unwatch(&path)) {
+            Some(Ok(path)) => Response::ok(req.id, serde_json::json!({ "unwatched": path })),
+            Some(Err(e)) => Response::err(req.id, ERR_INVALID_PARAMS, e),
+            None => Response::err(req.id, ERR_INVALID_PARAMS, format!("not watching {}", path)),
+        };
+    }
+    let recursive = p.get("recursive").and_then(|v| v.as_bool()).unwrap_or(false);
+    let existing = watches.take();
+    let created = existing.is_none();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // A recursive watch makes notify walk the whole tree
+    let done = tokio::task::spawn_blocking(move || -> Result<_, String> {
+        let mut w = match existing {
+            Some(w) => w,
+            None => fswatch::Watches::new(tx)?,
+        };
+        let watched = w.watch(&path, recursive);
+        Ok((w, watched))
+    }).await;
+    let watched = match done {
+        Ok(Ok((w, watched))) => {
+            *watches = Some(w);
+            watched
+        }
+        Ok(Err(e)) => return Response::err(req.id, ERR_INTERNAL, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    if created {
+        let sender = sender.clone();
+        while pending.try_join_next().is_some() {}
+        pending.spawn(async move {
+            while let Some(changes) = fswatch::next_batch(&mut rx).await {
+                let msg = notification("fs_changed", serde_json::json!({ "changes": changes }));
+                if sender.lock().await.send(Message::Text(msg.into())).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    match watched {
+        Ok(info) => Response::ok(req.id, serde_json::json!({ "watching": info })),
+        Err(e) => fs_error(req.id, e),
+    }
+}
+
+// fs_find / fs_grep: replies with the search id, then streams
+// `fs_search_results` batches and a final `fs_search_done`
+async fn handle_search(req: &Request, sender: &WsSender, searches: &Searches, pending: &mut JoinSet<()>) -> Response {
+    let params = req.params.clone();
+    let prepared = if req.method == "fs_find" {
+        serde_json::from_value(params).map_err(|e| format!("invalid fs_find params: {}", e))
+            .and_then(search::prepare_find)
+    } else {
+        serde_json::from_value(params).map_err(|e| format!("invalid fs_grep params: {}", e))
+            .and_then(search::prepare_grep)
+    };
+    let search = match prepared {
+        Ok(s) => s,
+        Err(e) if e.starts_with(sandbox::DENIED) => return fs_error(req.id, e),
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    let id = uuid::Uuid::new_v4().to_string();
+    let cancel = Arc::new(AtomicBool::new(false));
+    searches.lock().await.insert(id.clone(), cancel.clone());
+
+    let response = Response::ok(req.id, serde_json::json!({ "id": id }));
+    let json = serde_json::to_string(&response).unwrap();
+    let _ = sender.lock().await.send(Message::Text(json.into())).await;
+
+    let sender = sender.clone();
+    let searches = searches.clone();
+    while pending.try_join_next().is_some() {}
+    pending.spawn(async move {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let walk = tokio::task::spawn_blocking(move || search.run(cancel, tx));
+        while let Some(results) = rx.recv().await {
+            let msg = notification("fs_search_results", serde_json::json!({ "id": id, "results": results }));
+            if sender.lock().await.send(Message::Text(msg.into())).await.is_err() {
+                return;
+            }
+        }
+        searches.lock().await.remove(&id);
+        if let Ok(summary) = walk.await {
+            let mut params = serde_json::to_value(summary).unwrap();
+            params["id"] = serde_json::json!(id);
+            let _ = sender.lock().await.send(Message::Text(notification("fs_search_done", params).into())).await;
+        }
+    });
+    response
+}
+
+async fn handle_search_cancel(req: &Request, searches: &Searches) -> Response {
+    let id = match require_str(&req.params, "id") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    match searches.lock().await.remove(id) {
+        Some(cancel) => {
+            cancel.store(true, Ordering::Relaxed);
+            Response::ok(req.id, serde_json::json!({ "cancelled": id }))
+        }
+        None => Response::err(req.id, ERR_INVALID_PARAMS, format!("no running search: {}", id)),
+    }
+}
+
+#[derive(Deserialize)]
+struct CopyParams {
+    from: String,
+    to: String,
+    #[serde(default = "default_overwrite")]
+    overwrite: rfs::Overwrite,
+}
+
+fn default_overwrite() -> rfs::Overwrite { rfs::Overwrite::Error }
+
+// fs_copy: checks the whole copy up front and replies with its size, then
+// pushes `fs_copy_progress` while it runs and `fs_copy_done` at the end
+async fn handle_copy(req: &Request, sender: &WsSender, copies: &Copies, pending: &mut JoinSet<()>) -> Response {
+    let params: CopyParams = match serde_json::from_value(req.params.clone()) {
+        Ok(p) => p,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, format!("invalid fs_copy params: {}", e)),
+    };
+    let planned = tokio::task::spawn_blocking(move || rfs::plan_copy(&params.from, &params.to, params.overwrite)).await;
+    let plan = match planned {
+        Ok(Ok(plan)) => plan,
+        Ok(Err(e)) => return fs_error(req.id, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    let id = uuid::Uuid::new_v4().to_string();
+    let cancel = Arc::new(AtomicBool::new(false));
+    copies.lock().await.insert(id.clone(), cancel.clone());
+
+    let response = Response::ok(req.id, serde_json::json!({
+        "id": id,
+        "total_files": plan.total_files,
+        "total_bytes": plan.total_bytes,
+    }));
+    let json = serde_json::to_string(&response).unwrap();
+    let _ = sender.lock().await.send(Message::Text(json.into())).await;
+
+    let sender = sender.clone();
+    let copies = copies.clone();
+    while pending.try_join_next().is_some() {}
+    pending.spawn(async move {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let run = tokio::task::spawn_blocking(move || {
+            let mut last = std::time::Instant::now();
+            plan.run(&cancel, |p| {
+                if last.elapsed() >= COPY_PROGRESS_INTERVAL {
+                    last = std::time::Instant::now();
+                    let _ = tx.send(p.clone());
+                }
+            })
+        });
+        while let Some(progress) = rx.recv().await {
+            let mut params = serde_json::to_value(progress).unwrap();
+            params["id"] = serde_json::json!(id);
+            // Keep copying even if the client is gone
+            let _ = sender.lock().await.send(Message::Text(notification("fs_copy_progress", params).into())).await;
+        }
+        copies.lock().await.remove(&id);
+        let (done, error) = match run.await {
+            Ok(Ok(done)) => (done, None),
+            Ok(Err((e, done))) => (done, Some(e)),
+            Err(e) => (rfs::CopyProgress::default(), Some(format!("task panic: {}", e))),
+        };
+        let mut params = serde_json::to_value(done).unwrap();
+        params["id"] = serde_json::json!(id);
+        match error.as_deref() {
+            Some("cancelled") => params["cancelled"] = serde_json::json!(true),
+            Some(e) => params["error"] = serde_json::json!(e),
+            None => {}
+        }
+        let _ = sender.lock().await.send(Message::Text(notification("fs_copy_done", params).into())).await;
+    });
+    response
+}
+
+async fn handle_copy_cancel(req: &Request, copies: &Copies) -> Response {
+    let id = match require_str(&req.params, "id") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    match copies.lock().await.remove(id) {
+        Some(cancel) => {
+            cancel.store(true, Ordering::Relaxed);
+            Response::ok(req.id, serde_json::json!({ "cancelled": id }))
+        }
+        None => Response::err(req.id, ERR_INVALID_PARAMS, format!("no running copy: {}", id)),
+    }
+}
+
+// fs_follow: replies with the follow id (and an initial tail, if asked
+// for), then pushes `fs_follow_lines` as the file grows
+async fn handle_follow(req: &Request, sender: &WsSender, follows: &Follows, pending: &mut JoinSet<()>) -> Response {
+    let path = match require_str(&req.params, "path") {
+        Ok(s) => s.to_string(),
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    if follows.lock().await.len() >= text::MAX_FOLLOWS {
+        return Response::err(req.id, ERR_INVALID_PARAMS, format!("too many follows (max {} per connection)", text::MAX_FOLLOWS));
+    }
+    let lines = req.params.get("lines").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+    let opened = tokio::task::spawn_blocking(move || -> Result<_, String> {
+        let follower = text::Follower::open(&path)?;
+        let initial = if lines > 0 { Some(text::tail(&path, lines)?) } else { None };
+        Ok((follower, initial))
+    }).await;
+    let (mut follower, initial) = match opened {
+        Ok(Ok(o)) => o,
+        Ok(Err(e)) => return fs_error(req.id, e),
+        Err(e) => return Response::err(req.id, ERR_INTERNAL, format!("task panic: {}", e)),
+    };
+    let id = uuid::Uuid::new_v4().to_string();
+    let path = follower.path();
+    let response = Response::ok(req.id, serde_json::json!({
+        "id": id,
+        "path": path,
+        "encoding": follower.enc.name(),
+        "offset": follower.offset,
+        "content": initial.map(|c| c.content),
+    }));
+    let json = serde_json::to_string(&response).unwrap();
+    let _ = sender.lock().await.send(Message::Text(json.into())).await;
+
+    let sender = sender.clone();
+    let follows_task = follows.clone();
+    let task_id = id.clone();
+    let mut follows = follows.lock().await;
+    while pending.try_join_next().is_some() {}
+    let handle = pending.spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(250));
+        // Report a missing file once, then keep polling for it to come back
+        let mut failing = false;
+        loop {
+            interval.tick().await;
+            let polled = tokio::task::spawn_blocking(move || {
+                let r = follower.poll();
+                (follower, r)
+            }).await;
+            let Ok((f, result)) = polled else { break };
+            follower = f;
+            let msg = match result {
+                Ok(None) => {
+                    failing = false;
+                    continue;
+                }
+                Err(_) if failing => continue,
+                Ok(Some(appended)) => {
+                    failing = false;
+                    let mut params = serde_json::to_value(appended).unwrap();
+                    params["id"] = serde_json::json!(task_id);
+                    params["path"] = serde_json::json!(path);
+                    notification("fs_follow_lines", params)
+                }
+                Err(e) => {
+                    failing = true;
+                    notification("fs_follow_error", serde_json::json!({ "id": task_id, "path": path, "message": e }))
+                }
+            };
+            if sender.lock().await.send(Message::Text(msg.into())).await.is_err() {
+                break;
+            }
+        }
+        follows_task.lock().await.remove(&task_id);
+    });
+    follows.insert(id, handle);
+    response
+}
+
+async fn handle_unfollow(req: &Request, follows: &Follows) -> Response {
+    let id = match require_str(&req.params, "id") {
+        Ok(s) => s,
+        Err(e) => return Response::err(req.id, ERR_INVALID_PARAMS, e),
+    };
+    match follows.lock().await.remove(id) {
+        Some(handle) => {
+            handle.abort();
+            Response::ok(req.id, serde_json::json!({ "unfollowed": id }))
+        }
+        None => Response::err(req.id, ERR_INVALID_PARAMS, format!("not following: {}", id)),
+    }
+}
+
+async fn handle_connection(stream: TcpStream, addr: SocketAddr, token: Arc<String>) {
+    println!("📱 Client connected: {}", addr);
+
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("❌ WebSocket handshake failed for {}: {}", addr, e);
+            return;
+        }
+    };
+
+    let (ws_sender, mut receiver) = ws_stream.split();
+    let sender = Arc::new(Mutex::new(ws_sender));
+    let subs: Subscriptions = Arc::new(Mutex::new(HashMap::new()));
+    let replays: Replays = Arc::new(Mutex::new(HashMap::new()));
+    let execs: Execs = Arc::new(Mutex::new(HashMap::new()));
+    let searches: Searches = Arc::new(Mutex::new(HashMap::new()));
+    let copies: Copies = Arc::new(Mutex::new(HashMap::new()));
+    let follows: Follows = Arc::new(Mutex::new(HashMap::new()));
+    let mut fs_watches = None;
+    let mut authenticated = false;
+    let mut events_handle = None;
+    let mut pending = JoinSet::new();
+
+    // Start subscription polling task
+    let sub_handle = tokio::spawn(subscription_loop(sender.clone(), subs.clone()));
+
+    while let Some(msg) = receiver.next().await {
+        let msg = match msg {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("❌ Read error from {}: {}", addr, e);
+                break;
+            }
+        };
+
+        match msg {
+            Message::Text(text) => {
+                let response = match serde_json::from_str::<Request>(&text) {
+                    Ok(mut req) => {
+                        req.client = addr.to_string();
+                        // Auth gate: first message must be "auth"
+                        if !authenticated {
+                            if req.method == "auth" {
+                                let provided = req.params.get("token")
+                                    .and_then(|v| v.as_str())
+                                    .unwrap_or("");
+                                if provided == token.as_str() {
+                                    authenticated = true;
+                                    events_handle = Some(tokio::spawn(forward_events(sender.clone(), events().subscribe())));
+                                    Response::ok(req.id, serde_json::json!({ "authenticated": true, "time": watchers::now_ms() }))
+                                } else {
+                                    let r = Response::err(req.id, ERR_AUTH, "invalid token".into());
+                                    let json = serde_json::to_string(&r).unwrap();
+                                    let mut tx = sender.lock().await;
+                                    let _ = tx.send(Message::Text(json.into())).await;
+                                    let _ = tx.send(Message::Close(None)).await;
+                                    break;
+                                }
+                            } else {
+                                let r = Response::err(req.id, ERR_AUTH, "auth required — send {\"method\":\"auth\",\"params\":{\"token\":\"...\"}} first".into());
+                                let json = serde_json::to_string(&r).unwrap();
                                 let mut tx = sender.lock().await;
                                 let _ = tx.send(Message::Text(json.into())).await;
                                 let _ = tx.send(Message::Close(None)).await;
@@ -409,14 +1870,86 @@ async fn handle_connection(stream: TcpStream, addr: SocketAddr, token: Arc<Strin
                             }
                         } else {
                             match req.method.as_str() {
+                                "subscribe" if is_replay(&req.params) => {
+                                    handle_replay_subscribe(&req, &sender, &replays).await
+                                }
                                 "subscribe" => {
                                     let mut map = subs.lock().await;
                                     handle_subscribe(&req.params, &mut map)
                                 }
+                                "unsubscribe" if is_replay(&req.params) => {
+                                    let target = req.params.get("target").and_then(|v| v.as_str()).unwrap_or("");
+                                    replays.lock().await.remove(target);
+                                    Response::ok(req.id, serde_json::json!({ "unsubscribed": target }))
+                                }
                                 "unsubscribe" => {
                                     let mut map = subs.lock().await;
                                     handle_unsubscribe(&req.params, &mut map)
                                 }
+                                "replay_control" => handle_replay_control(&req, &replays).await,
+                                "wait_for" => {
+                                    spawn_reply(&mut pending, sender.clone(), handle_wait_for(req));
+                                    continue;
+                                }
+                                "exec" => {
+                                    let r = handle_exec(&req, &sender, &execs, &mut pending).await;
+                                    if r.error.is_none() {
+                                        continue; // already sent ahead of the output
+                                    }
+                                    r
+                                }
+                                "exec_kill" => handle_exec_kill(&req, &execs).await,
+                                "fs_find" | "fs_grep" => {
+                                    let r = handle_search(&req, &sender, &searches, &mut pending).await;
+                                    if r.error.is_none() {
+                                        continue; // already sent ahead of the results
+                                    }
+                                    r
+                                }
+                                "fs_search_cancel" => handle_search_cancel(&req, &searches).await,
+                                "fs_du" => {
+                                    spawn_reply(&mut pending, sender.clone(), handle_du(req));
+                                    continue;
+                                }
+                                "fs_copy" => {
+                                    let r = handle_copy(&req, &sender, &copies, &mut pending).await;
+                                    if r.error.is_none() {
+                                        continue; // already sent ahead of the progress
+                                    }
+                                    r
+                                }
+                                "fs_copy_cancel" => handle_copy_cancel(&req, &copies).await,
+                                "fs_follow" => {
+                                    let r = handle_follow(&req, &sender, &follows, &mut pending).await;
+                                    if r.error.is_none() {
+                                        continue; // already sent ahead of the lines
+                                    }
+                                    r
+                                }
+                                "fs_unfollow" => handle_unfollow(&req, &follows).await,
+                                "fs_watch" | "fs_unwatch" | "fs_watches" => {
+                                    handle_fs_watch(&req, &mut fs_watches, &sender, &mut pending).await
+                                }
+                                "transfer_download" | "transfer_resume" => {
+                                    let r = handle_transfer_stream(&req, &sender, &mut pending).await;
+                                    let streaming = r.result.as_ref()
+                                        .is_some_and(|info| info["direction"] == "download");
+                                    if streaming {
+                                        continue; // already sent ahead of the data
+                                    }
+                                    r
+                                }
+                                "fs_archive" | "fs_archive_extract" => {
+                                    let r = handle_archive(&req, &sender, &mut pending).await;
+                                    if r.result.as_ref().is_some_and(|info| info["direction"] == "download") {
+                                        continue; // already sent ahead of the data
+                                    }
+                                    r
+                                }
+                                "exec_in_pane" => {
+                                    spawn_reply(&mut pending, sender.clone(), handle_exec_in_pane(req));
+                                    continue;
+                                }
                                 _ => {
                                     tokio::task::spawn_blocking(move || handle_request(&req))
                                         .await
@@ -434,6 +1967,14 @@ async fn handle_connection(stream: TcpStream, addr: SocketAddr, token: Arc<Strin
                     break;
                 }
             }
+            Message::Binary(data) if authenticated => {
+                let notice = tokio::task::spawn_blocking(move || handle_upload_frame(&data)).await.ok().flatten();
+                if let Some(msg) = notice {
+                    if sender.lock().await.send(Message::Text(msg.into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
             Message::Close(_) => break,
             Message::Ping(data) => {
                 let mut tx = sender.lock().await;
@@ -444,6 +1985,12 @@ async fn handle_connection(stream: TcpStream, addr: SocketAddr, token: Arc<Strin
     }
 
     sub_handle.abort();
+    for cancel in searches.lock().await.values() {
+        cancel.store(true, Ordering::Relaxed);
+    }
+    if let Some(h) = events_handle {
+        h.abort();
+    }
     println!("👋 Client disconnected: {}", addr);
 }
 
@@ -451,6 +1998,25 @@ pub async fn start(host: &str, port: u16, token: &str) -> Result<(), Box<dyn std
     start_with_socket(host, port, token, None).await
 }
 
+pub async fn start_with_config(cfg: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // First, so a bad policy stops startup before anything else runs
+    sandbox::init(cfg.fs)?;
+    // Before any init: logs attach pipe-pane, and that has to reach the
+    // configured tmux server
+    tmux::set_socket(cfg.tmux_socket.clone());
+    triggers::init(cfg.triggers);
+    watchers::init(cfg.watchers);
+    recording::init(cfg.recordings_dir);
+    logs::init(cfg.logs);
+    exec::init(cfg.exec);
+    trash::init(cfg.trash);
+    transfer::init(cfg.transfers);
+    thumbnail::init(cfg.thumbnails);
+    archive::init(cfg.archives);
+    scheduler::init();
+    start_with_socket(&cfg.host, cfg.port, &cfg.token, cfg.tmux_socket).await
+}
+
 pub async fn start_with_socket(host: &str, port: u16, token: &str, socket: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
     tmux::set_socket(socket);
     let addr = format!("{}:{}", host, port);
@@ -461,6 +2027,9 @@ pub async fn start_with_socket(host: &str, port: u16, token: &str, socket: Optio
     println!("🔑 Token: {}", token);
     println!("   Methods: auth, list_sessions, list_panes, capture_pane, send_keys, send_command, new_session, kill_session, subscribe, unsubscribe");
 
+    tokio::spawn(watch_loop());
+    tokio::spawn(scheduler_loop());
+
     loop {
         let (stream, addr) = listener.accept().await?;
         let token = token.clone();
@@ -0,0 +1,233 @@
+/// Command snippets stored in `snippets.toml` next to the config file.
+/// Commands may contain `{name}` placeholders filled in when run; `{{`/`}}` are literal braces.
+use crate::tmux;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+fn default_enter() -> bool { true }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Only offered for (and runnable in) this session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Press Enter after typing the command (`send_command`), otherwise only type it
+    #[serde(default = "default_enter")]
+    pub enter: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SnippetFile {
+    #[serde(default)]
+    snippets: Vec<Snippet>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnippetInfo {
+    #[serde(flatten)]
+    pub snippet: Snippet,
+    pub placeholders: Vec<String>,
+}
+
+// Serializes read-modify-write cycles on the snippets file
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn snippets_path() -> PathBuf {
+    crate::config::config_dir().join("snippets.toml")
+}
+
+fn load() -> Result<SnippetFile, String> {
+    match std::fs::read_to_string(snippets_path()) {
+        Ok(s) => toml::from_str(&s).map_err(|e| format!("invalid snippets.toml: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SnippetFile::default()),
+        Err(e) => Err(format!("cannot read snippets: {}", e)),
+    }
+}
+
+fn save(file: &SnippetFile) -> Result<(), String> {
+    let path = snippets_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    }
+    let content = toml::to_string(file).map_err(|e| format!("cannot serialize snippets: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("cannot write snippets: {}", e))
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a command into literal text and `{name}` placeholders
+fn parse(command: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = command;
+    while let Some(i) = rest.find(['{', '}']) {
+        if i > 0 {
+            parts.push(Part::Text(&rest[..i]));
+        }
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            parts.push(Part::Text(&tail[..1]));
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err("unmatched '}' in snippet (use '}}' for a literal brace)".into());
+        }
+        let end = tail.find('}').ok_or("unclosed '{' in snippet (use '{{' for a literal brace)")?;
+        let name = &tail[1..end];
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("invalid placeholder name: {{{}}}", name));
+        }
+        parts.push(Part::Placeholder(name));
+        rest = &tail[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    Ok(parts)
+}
+
+fn placeholders(command: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for part in parse(command).unwrap_or_default() {
+        if let Part::Placeholder(n) = part {
+            if !names.iter().any(|x| x == n) {
+                names.push(n.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Fill in placeholders; every placeholder must have a value
+pub fn render(command: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let parts = parse(command)?;
+    let missing: Vec<String> = placeholders(command).into_iter().filter(|n| !values.contains_key(n)).collect();
+    if !missing.is_empty() {
+        return Err(format!("missing snippet params: {}", missing.join(", ")));
+    }
+    Ok(parts.iter().map(|p| match p {
+        Part::Text(t) => *t,
+        Part::Placeholder(n) => values[*n].as_str(),
+    }).collect())
+}
+
+fn info(snippet: Snippet) -> SnippetInfo {
+    SnippetInfo { placeholders: placeholders(&snippet.command), snippet }
+}
+
+/// Snippets usable in `session` (global ones plus those scoped to it), or all of them
+pub fn list(session: Option<&str>) -> Result<Vec<SnippetInfo>, String> {
+    let _guard = LOCK.lock().unwrap();
+    Ok(load()?.snippets.into_iter()
+        .filter(|s| session.is_none() || s.session.is_none() || s.session.as_deref() == session)
+        .map(info)
+        .collect())
+}
+
+/// Create a snippet, or replace the one with the same id
+pub fn save_snippet(mut snippet: Snippet) -> Result<SnippetInfo, String> {
+    if snippet.name.trim().is_empty() || snippet.command.is_empty() {
+        return Err("snippet name and command must not be empty".into());
+    }
+    parse(&snippet.command)?;
+    let _guard = LOCK.lock().unwrap();
+    let mut file = load()?;
+    if snippet.id.is_empty() {
+        snippet.id = uuid::Uuid::new_v4().to_string()[..8].to_string();
+    }
+    match file.snippets.iter_mut().find(|s| s.id == snippet.id) {
+        Some(existing) => *existing = snippet.clone(),
+        None => file.snippets.push(snippet.clone()),
+    }
+    save(&file)?;
+    Ok(info(snippet))
+}
+
+pub fn delete(id: &str) -> Result<(), String> {
+    let _guard = LOCK.lock().unwrap();
+    let mut file = load()?;
+    let before = file.snippets.len();
+    file.snippets.retain(|s| s.id != id);
+    if file.snippets.len() == before {
+        return Err(format!("no such snippet: {}", id));
+    }
+    save(&file)
+}
+
+/// Render a snippet and type it into `target`. Returns the command sent.
+pub fn run(id: &str, target: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let snippet = {
+        let _guard = LOCK.lock().unwrap();
+        load()?.snippets.into_iter().find(|s| s.id == id).ok_or_else(|| format!("no such snippet: {}", id))?
+    };
+    if let Some(session) = &snippet.session {
+        let target_session = target.split(':').next().unwrap_or(target);
+        if target_session != session {
+            return Err(format!("snippet {} is scoped to session {}", id, session));
+        }
+    }
+    let command = render(&snippet.command, values)?;
+    if snippet.enter {
+        tmux::send_command(target, &command)?;
+    } else {
+        tmux::send_text(target, &command)?;
+    }
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn render_substitutes() {
+        let v = values(&[("branch", "main"), ("n", "3")]);
+        assert_eq!(render("git checkout {branch}", &v).unwrap(), "git checkout main");
+        assert_eq!(render("{n}{n} {branch}", &v).unwrap(), "33 main");
+        assert_eq!(render("no placeholders", &v).unwrap(), "no placeholders");
+        // Values are inserted as they are, never parsed again
+        let v = values(&[("x", "{y}")]);
+        assert_eq!(render("echo {x}", &v).unwrap(), "echo {y}");
+    }
+
+    #[test]
+    fn render_escapes() {
+        let v = values(&[("f", "a.txt")]);
+        assert_eq!(render("awk '{{print $1}}' {f}", &v).unwrap(), "awk '{print $1}' a.txt");
+        assert_eq!(render("{{{f}}}", &v).unwrap(), "{a.txt}");
+        assert_eq!(render("{{f}}", &v).unwrap(), "{f}");
+    }
+
+    #[test]
+    fn render_errors() {
+        let v = values(&[("a", "1")]);
+        let err = render("{a} {b} {c} {b}", &v).unwrap_err();
+        assert_eq!(err, "missing snippet params: b, c");
+        for bad in ["echo }", "echo {a", "{1x}", "{a-b}", "{}"] {
+            assert!(render(bad, &v).is_err(), "accepted {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn lists_placeholders_once_in_order() {
+        assert_eq!(placeholders("{b} {a} {b} {{c}}"), ["b", "a"]);
+        assert!(placeholders("broken {").is_empty());
+    }
+}
@@ -0,0 +1,627 @@
+/// A small terminal emulator for rendering recorded output the way
+/// `capture-pane -p -e -J` shows a live pane: history plus screen, one line
+/// per row with wrapped rows joined, and SGR sequences for colors.
+/// Covers what shells and common full-screen programs use; anything else is
+/// parsed and ignored.
+use std::collections::VecDeque;
+
+/// Rows kept above the screen, as many as a live capture includes
+pub const HISTORY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// SGR code for this color; `base` is 30 for foreground, 40 for background
+    fn code(self, base: u16) -> String {
+        match self {
+            Color::Default => (base + 9).to_string(),
+            Color::Indexed(n) if n < 8 => (base + n as u16).to_string(),
+            Color::Indexed(n) if n < 16 => (base + 60 + n as u16 - 8).to_string(),
+            Color::Indexed(n) => format!("{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    /// Bit n set for SGR attribute n (1 bold … 9 strikethrough)
+    attrs: u16,
+}
+
+impl Style {
+    /// The SGR sequence that turns `self` into `to`
+    fn change_to(mut self, to: Style) -> String {
+        let mut codes = Vec::new();
+        if self.attrs & !to.attrs != 0 {
+            codes.push("0".to_string());
+            self = Style::default();
+        }
+        codes.extend((1..10).filter(|n| to.attrs & !self.attrs & (1 << n) != 0).map(|n| n.to_string()));
+        if to.fg != self.fg {
+            codes.push(to.fg.code(30));
+        }
+        if to.bg != self.bg {
+            codes.push(to.bg.code(40));
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+
+    fn apply_sgr(&mut self, params: &[u16]) {
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                n @ 1..=9 => self.attrs |= 1 << n,
+                21 => self.attrs &= !(1 << 1),
+                22 => self.attrs &= !(1 << 1 | 1 << 2),
+                n @ 23..=29 => self.attrs &= !(1 << (n - 20)),
+                n @ 30..=37 => self.fg = Color::Indexed((n - 30) as u8),
+                n @ 40..=47 => self.bg = Color::Indexed((n - 40) as u8),
+                n @ 90..=97 => self.fg = Color::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.bg = Color::Indexed((n - 100 + 8) as u8),
+                39 => self.fg = Color::Default,
+                49 => self.bg = Color::Default,
+                n @ (38 | 48) => {
+                    let color = match params.get(i + 1) {
+                        Some(5) => params.get(i + 2).map(|&c| {
+                            i += 2;
+                            Color::Indexed(c as u8)
+                        }),
+                        Some(2) if params.len() >= i + 5 => {
+                            let c = Color::Rgb(params[i + 2] as u8, params[i + 3] as u8, params[i + 4] as u8);
+                            i += 4;
+                            Some(c)
+                        }
+                        _ => None,
+                    };
+                    match (n, color) {
+                        (38, Some(c)) => self.fg = c,
+                        (48, Some(c)) => self.bg = c,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    /// '\0' for the second column of a wide character
+    ch: char,
+    style: Style,
+}
+
+const BLANK: Cell = Cell { ch: ' ', style: Style { fg: Color::Default, bg: Color::Default, attrs: 0 } };
+
+#[derive(Debug, Clone)]
+struct Row {
+    cells: Vec<Cell>,
+    /// Continues on the next row (auto-wrapped)
+    wrapped: bool,
+}
+
+impl Row {
+    fn blank(width: usize) -> Self {
+        Self { cells: vec![BLANK; width], wrapped: false }
+    }
+
+    fn is_blank(&self) -> bool {
+        self.cells.iter().all(|c| *c == BLANK)
+    }
+}
+
+enum State {
+    Ground,
+    Escape,
+    /// ESC followed by a byte that takes one more (charset selection)
+    EscapeArg,
+    Csi(String),
+    /// OSC, DCS and the like, up to BEL or ST
+    Str,
+    StrEscape,
+}
+
+#[derive(Clone, Copy)]
+struct Cursor {
+    x: usize,
+    y: usize,
+    style: Style,
+}
+
+pub struct Terminal {
+    width: usize,
+    height: usize,
+    rows: Vec<Row>,
+    history: VecDeque<Row>,
+    /// The main screen's rows while the alternate screen is up
+    main: Option<Vec<Row>>,
+    x: usize,
+    y: usize,
+    /// At the last column with a character just written: the next one wraps
+    pending_wrap: bool,
+    style: Style,
+    saved: Cursor,
+    /// Scroll region, inclusive
+    top: usize,
+    bottom: usize,
+    state: State,
+}
+
+/// Display width: 2 for East Asian wide characters and emoji, 0 for
+/// combining marks and other zero-width characters
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+impl Terminal {
+    pub fn new(width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self {
+            width,
+            height,
+            rows: vec![Row::blank(width); height],
+            history: VecDeque::new(),
+            main: None,
+            x: 0,
+            y: 0,
+            pending_wrap: false,
+            style: Style::default(),
+            saved: Cursor { x: 0, y: 0, style: Style::default() },
+            top: 0,
+            bottom: height - 1,
+            state: State::Ground,
+        }
+    }
+
+    pub fn feed(&mut self, data: &str) {
+        for c in data.chars() {
+            self.step(c);
+        }
+    }
+
+    /// History and screen as `capture-pane -p -e -J` prints them
+    pub fn render(&self) -> String {
+        let history = if self.main.is_some() { None } else { Some(&self.history) };
+        let mut out = String::new();
+        let mut style = Style::default();
+        let mut line: Vec<Cell> = Vec::new();
+        for row in history.into_iter().flatten().chain(&self.rows) {
+            line.extend(row.cells.iter().filter(|c| c.ch != '\0'));
+            if row.wrapped {
+                continue;
+            }
+            let end = line.iter().rposition(|c| *c != BLANK).map_or(0, |i| i + 1);
+            for cell in &line[..end] {
+                if cell.style != style {
+                    out.push_str(&style.change_to(cell.style));
+                    style = cell.style;
+                }
+                out.push(cell.ch);
+            }
+            out.push('\n');
+            line.clear();
+        }
+        out
+    }
+
+    fn step(&mut self, c: char) {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(c),
+            State::Escape => self.escape(c),
+            State::EscapeArg => {}
+            State::Csi(mut buf) => match c {
+                '\x1b' => self.state = State::Escape,
+                '\x40'..='\x7e' => self.csi(&buf, c),
+                _ if (c as u32) < 0x20 => {
+                    // Controls take effect in the middle of a sequence
+                    self.ground(c);
+                    self.state = State::Csi(buf);
+                }
+                _ => {
+                    buf.push(c);
+                    self.state = State::Csi(buf);
+                }
+            },
+            State::Str => match c {
+                '\x07' => {}
+                '\x1b' => self.state = State::StrEscape,
+                _ => self.state = State::Str,
+            },
+            State::StrEscape => {
+                if c != '\\' {
+                    self.state = State::Str;
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.state = State::Escape,
+            '\r' => self.carriage_return(),
+            '\n' | '\x0b' | '\x0c' => self.linefeed(),
+            '\x08' => {
+                self.x = self.x.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            '\t' => {
+                self.x = ((self.x / 8 + 1) * 8).min(self.width - 1);
+                self.pending_wrap = false;
+            }
+            c if (c as u32) < 0x20 || c == '\x7f' => {}
+            c => self.put(c),
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        match c {
+            '[' => self.state = State::Csi(String::new()),
+            ']' | 'P' | 'X' | '^' | '_' => self.state = State::Str,
+            '(' | ')' | '*' | '+' | '#' | '%' => self.state = State::EscapeArg,
+            'c' => *self = Terminal::new(self.width, self.height),
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.linefeed(),
+            'E' => {
+                self.carriage_return();
+                self.linefeed();
+            }
+            'M' => self.reverse_index(),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, buf: &str, action: char) {
+        let private = buf.starts_with(['?', '>', '<', '=']);
+        let params: Vec<u16> = buf.trim_start_matches(['?', '>', '<', '='])
+            .split([';', ':'])
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        // First parameter, with 0 or missing meaning 1
+        let n = params.first().copied().filter(|&n| n > 0).unwrap_or(1) as usize;
+        let arg = |i: usize| params.get(i).copied().unwrap_or(0) as usize;
+        if private {
+            match action {
+                'h' | 'l' => {
+                    for &mode in &params {
+                        self.private_mode(mode, action == 'h');
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        self.pending_wrap = false;
+        match action {
+            'A' => self.y = if self.y >= self.top { self.y.saturating_sub(n).max(self.top) } else { self.y.saturating_sub(n) },
+            'B' | 'e' => self.y = if self.y <= self.bottom { (self.y + n).min(self.bottom) } else { (self.y + n).min(self.height - 1) },
+            'C' | 'a' => self.x = (self.x + n).min(self.width - 1),
+            'D' => self.x = self.x.saturating_sub(n),
+            'E' => {
+                self.y = (self.y + n).min(self.height - 1);
+                self.x = 0;
+            }
+            'F' => {
+                self.y = self.y.saturating_sub(n);
+                self.x = 0;
+            }
+            'G' | '`' => self.x = (n - 1).min(self.width - 1),
+            'd' => self.y = (n - 1).min(self.height - 1),
+            'H' | 'f' => {
+                self.y = (arg(0).max(1) - 1).min(self.height - 1);
+                self.x = (arg(1).max(1) - 1).min(self.width - 1);
+            }
+            'J' if arg(0) == 3 => self.history.clear(),
+            'J' => self.erase_display(arg(0)),
+            'K' => self.erase_line(arg(0)),
+            'L' => self.insert_lines(n),
+            'M' => self.delete_lines(n),
+            'P' => {
+                let row = &mut self.rows[self.y].cells;
+                let n = n.min(self.width - self.x);
+                row.drain(self.x..self.x + n);
+                row.extend(std::iter::repeat_n(BLANK, n));
+            }
+            '@' => {
+                let row = &mut self.rows[self.y].cells;
+                let n = n.min(self.width - self.x);
+                row.truncate(self.width - n);
+                row.splice(self.x..self.x, std::iter::repeat_n(BLANK, n));
+            }
+            'X' => {
+                let end = (self.x + n).min(self.width);
+                self.rows[self.y].cells[self.x..end].fill(BLANK);
+            }
+            'S' => self.scroll_up(n),
+            'T' => self.scroll_down(n),
+            'r' => {
+                let top = arg(0).max(1) - 1;
+                let bottom = if arg(1) == 0 { self.height } else { arg(1).min(self.height) } - 1;
+                if top < bottom {
+                    (self.top, self.bottom) = (top, bottom);
+                    (self.x, self.y) = (0, 0);
+                }
+            }
+            'm' => self.style.apply_sgr(if params.is_empty() { &[0] } else { &params }),
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn private_mode(&mut self, mode: u16, on: bool) {
+        if !matches!(mode, 47 | 1047 | 1049) || on == self.main.is_some() {
+            return;
+        }
+        if on {
+            if mode == 1049 {
+                self.save_cursor();
+            }
+            let blank = vec![Row::blank(self.width); self.height];
+            self.main = Some(std::mem::replace(&mut self.rows, blank));
+        } else {
+            self.rows = self.main.take().unwrap_or_default();
+            if mode == 1049 {
+                self.restore_cursor();
+            }
+        }
+        self.pending_wrap = false;
+    }
+
+    fn put(&mut self, c: char) {
+        let w = char_width(c);
+        if w == 0 {
+            return;
+        }
+        if self.pending_wrap || self.x + w > self.width {
+            // A wide char that doesn't fit leaves a gap that isn't content
+            let row = &mut self.rows[self.y];
+            if !self.pending_wrap {
+                row.cells[self.x..].fill(Cell { ch: '\0', ..BLANK });
+            }
+            row.wrapped = true;
+            self.carriage_return();
+            self.linefeed();
+        }
+        let cell = Cell { ch: c, style: self.style };
+        let row = &mut self.rows[self.y].cells;
+        row[self.x] = cell;
+        if w == 2 {
+            if let Some(next) = row.get_mut(self.x + 1) {
+                *next = Cell { ch: '\0', ..cell };
+            }
+        }
+        self.x += w;
+        if self.x >= self.width {
+            self.x = self.width - 1;
+            self.pending_wrap = true;
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        self.x = 0;
+        self.pending_wrap = false;
+    }
+
+    fn linefeed(&mut self) {
+        self.pending_wrap = false;
+        if self.y == self.bottom {
+            self.scroll_up(1);
+        } else if self.y < self.height - 1 {
+            self.y += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        if self.y == self.top {
+            self.scroll_down(1);
+        } else {
+            self.y = self.y.saturating_sub(1);
+        }
+    }
+
+    fn push_history(&mut self, row: Row) {
+        if self.main.is_some() {
+            return;
+        }
+        self.history.push_back(row);
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        for _ in 0..n.min(self.bottom - self.top + 1) {
+            let row = self.rows.remove(self.top);
+            if self.top == 0 {
+                self.push_history(row);
+            }
+            self.rows.insert(self.bottom, Row::blank(self.width));
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        for _ in 0..n.min(self.bottom - self.top + 1) {
+            self.rows.remove(self.bottom);
+            self.rows.insert(self.top, Row::blank(self.width));
+        }
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        if (self.top..=self.bottom).contains(&self.y) {
+            for _ in 0..n.min(self.bottom - self.y + 1) {
+                self.rows.remove(self.bottom);
+                self.rows.insert(self.y, Row::blank(self.width));
+            }
+            self.x = 0;
+        }
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        if (self.top..=self.bottom).contains(&self.y) {
+            for _ in 0..n.min(self.bottom - self.y + 1) {
+                self.rows.remove(self.y);
+                self.rows.insert(self.bottom, Row::blank(self.width));
+            }
+            self.x = 0;
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for row in &mut self.rows[self.y + 1..] {
+                    *row = Row::blank(self.width);
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for row in &mut self.rows[..self.y] {
+                    *row = Row::blank(self.width);
+                }
+            }
+            _ => {
+                // Like tmux, a full clear moves what was on screen into history
+                let used = self.rows.iter().rposition(|r| !r.is_blank()).map_or(0, |i| i + 1);
+                let old = std::mem::replace(&mut self.rows, vec![Row::blank(self.width); self.height]);
+                for row in old.into_iter().take(used) {
+                    self.push_history(row);
+                }
+            }
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let row = &mut self.rows[self.y];
+        let range = match mode {
+            0 => self.x..self.width,
+            1 => 0..self.x + 1,
+            _ => 0..self.width,
+        };
+        row.cells[range].fill(BLANK);
+        if mode != 1 {
+            row.wrapped = false;
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Cursor { x: self.x, y: self.y, style: self.style };
+    }
+
+    fn restore_cursor(&mut self) {
+        let Cursor { x, y, style } = self.saved;
+        (self.x, self.y, self.style) = (x.min(self.width - 1), y.min(self.height - 1), style);
+        self.pending_wrap = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(width: usize, height: usize, data: &str) -> String {
+        let mut t = Terminal::new(width, height);
+        t.feed(data);
+        t.render()
+    }
+
+    #[test]
+    fn text_wrapping_and_cursor_moves() {
+        let cases = [
+            ("plain lines", 10, 3, "one\r\ntwo\r\n", "one\ntwo\n\n"),
+            ("wrapped rows are joined", 5, 3, "abcdefgh\r\nz", "abcdefgh\nz\n"),
+            ("exactly full rows don't wrap early", 5, 3, "abcde\r\nf", "abcde\nf\n\n"),
+            ("carriage return overwrites", 10, 1, "hello\rJ", "Jello\n"),
+            ("backspace and erase to end", 10, 1, "abc\x08\x08\x1b[K", "a\n"),
+            ("absolute position", 6, 3, "\x1b[2;3Hx\x1b[1;1Hy", "y\n  x\n\n"),
+            ("relative moves", 6, 2, "ab\x1b[Bc\x1b[A\x1b[2Dd", "ad\n  c\n"),
+            ("tabs", 20, 1, "a\tb", "a       b\n"),
+            ("insert and delete chars", 8, 1, "abcdef\x1b[1;2H\x1b[2P\x1b[1@", "a def\n"),
+            ("erase chars", 8, 1, "abcdef\x1b[1;2H\x1b[3X", "a   ef\n"),
+            ("wide chars take two columns", 5, 2, "中文字x", "中文字x\n"),
+            ("a wide char at the edge wraps early", 5, 2, "ab中文", "ab中文\n"),
+            ("combining marks are dropped", 5, 1, "e\u{301}!", "e!\n"),
+            ("osc and charset sequences are skipped", 10, 1, "\x1b]0;title\x07\x1b(Bok\x1b]2;t\x1b\\!", "ok!\n"),
+        ];
+        for (name, w, h, input, want) in cases {
+            assert_eq!(screen(w, h, input), want, "{}", name);
+        }
+    }
+
+    #[test]
+    fn colors_render_like_tmux() {
+        let cases = [
+            ("fg and reset of one attribute", "hello\x1b[31mred\x1b[0m plain", "hello\x1b[31mred\x1b[39m plain\n"),
+            ("bold then normal", "\x1b[1;32mok\x1b[22m!", "\x1b[1;32mok\x1b[0;32m!\n"),
+            ("bright, 256 and rgb", "\x1b[91ma\x1b[38;5;200mb\x1b[48;2;1;2;3mc", "\x1b[91ma\x1b[38;5;200mb\x1b[48;2;1;2;3mc\n"),
+            ("colon subparams", "\x1b[38:5:42mx", "\x1b[38;5;42mx\n"),
+            ("style carries over lines", "\x1b[33ma\r\nb", "\x1b[33ma\nb\n"),
+            ("trailing blanks are trimmed", "a   \x1b[7m \x1b[m   ", "a   \x1b[7m \n"),
+        ];
+        for (name, input, want) in cases {
+            assert_eq!(screen(20, 2, input).trim_end_matches('\n').to_string() + "\n", want, "{}", name);
+        }
+    }
+
+    #[test]
+    fn scrolling_and_history() {
+        // Lines scrolled off the top are kept, up to HISTORY
+        let input: String = (0..HISTORY + 10).map(|i| format!("{}\r\n", i)).collect();
+        let out = screen(10, 3, &input);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), HISTORY + 3);
+        assert_eq!(lines[0], "8");
+        assert_eq!(&lines[HISTORY..], ["208", "209", ""]);
+
+        // A scroll region keeps the rows outside it in place
+        let out = screen(10, 4, "head\x1b[2;3r\x1b[2;1Ha\r\nb\r\nc\x1b[r\x1b[4;1Hfoot");
+        assert_eq!(out, "head\nb\nc\nfoot\n", "a scrolled out of the region, not into history");
+        // Reverse index at the top scrolls down
+        assert_eq!(screen(10, 3, "a\r\nb\x1b[H\x1bMz"), "z\na\nb\n");
+        // Insert and delete lines
+        assert_eq!(screen(10, 3, "a\r\nb\r\nc\x1b[2H\x1b[L"), "a\n\nb\n");
+        assert_eq!(screen(10, 3, "a\r\nb\r\nc\x1b[1H\x1b[M"), "b\nc\n\n");
+    }
+
+    #[test]
+    fn clearing() {
+        // A full clear pushes the screen into history, like tmux does
+        assert_eq!(screen(10, 2, "old\x1b[H\x1b[2Jnew"), "old\nnew\n\n");
+        assert_eq!(screen(10, 2, "old\x1b[H\x1b[2J\x1b[3Jnew"), "new\n\n", "3J drops history");
+        assert_eq!(screen(10, 3, "a\r\nbb\r\nc\x1b[2;2H\x1b[J"), "a\nb\n\n");
+        assert_eq!(screen(10, 3, "a\r\nbb\r\nc\x1b[2;1H\x1b[1J"), "\n b\nc\n");
+        // ESC c starts over
+        assert_eq!(screen(10, 2, "\x1b[31mold\x1bcnew"), "new\n\n");
+    }
+
+    #[test]
+    fn alternate_screen() {
+        let mut t = Terminal::new(20, 2);
+        t.feed("prompt$ vim\r\n");
+        t.feed("\x1b[?1049h\x1b[Hediting");
+        assert_eq!(t.render(), "editing\n\n", "alternate screen shows without history");
+        t.feed("\x1b[?1049l");
+        t.feed("back");
+        assert_eq!(t.render(), "prompt$ vim\nback\n", "main screen and cursor come back");
+    }
+}
@@ -0,0 +1,698 @@
+/// Text access to files of any size: byte and line ranges, tails, and
+/// following appended lines, in whatever encoding the file uses
+use crate::fs::allowed_path;
+use crate::sandbox::Op;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+/// Most text returned by one range read
+pub const MAX_CHUNK: u64 = 512 * 1024;
+pub const MAX_LINES: usize = 10_000;
+/// Follows per connection
+pub const MAX_FOLLOWS: usize = 16;
+/// How far back `tail` looks for line starts
+const MAX_TAIL_BYTES: u64 = 4 * 1024 * 1024;
+/// Bytes looked at to guess the encoding
+pub(crate) const SAMPLE: usize = 8192;
+/// Longest unterminated line a follow holds back before sending it anyway
+const MAX_PARTIAL: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Fallback for anything that isn't valid UTF-8: every byte maps to a char
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "iso-8859-1",
+        }
+    }
+
+    /// Accepts the names `name` returns, plus `utf8` and `latin1`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" => Some(Encoding::Utf16Be),
+            "iso-8859-1" | "latin1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            Encoding::Latin1 => &[],
+        }
+    }
+
+    fn unit(self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            _ => 1,
+        }
+    }
+
+    fn is_newline(self, unit: &[u8]) -> bool {
+        match self {
+            Encoding::Utf16Le => unit == [b'\n', 0],
+            Encoding::Utf16Be => unit == [0, b'\n'],
+            _ => unit[0] == b'\n',
+        }
+    }
+}
+
+/// Guess the encoding from the start of a file; also returns the BOM length
+pub fn detect(sample: &[u8]) -> (Encoding, usize) {
+    if sample.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return (Encoding::Utf8, 3);
+    }
+    if sample.starts_with(&[0xFF, 0xFE]) {
+        return (Encoding::Utf16Le, 2);
+    }
+    if sample.starts_with(&[0xFE, 0xFF]) {
+        return (Encoding::Utf16Be, 2);
+    }
+    // BOM-less UTF-16: mostly ASCII text has a zero in every other byte.
+    // Checked first, since NULs are valid UTF-8.
+    let pairs = sample.len() / 2;
+    if pairs >= 8 {
+        let zeros = |odd: usize| sample.chunks_exact(2).filter(|p| p[odd] == 0).count();
+        if zeros(1) * 10 >= pairs * 4 && zeros(0) * 10 < pairs {
+            return (Encoding::Utf16Le, 0);
+        }
+        if zeros(0) * 10 >= pairs * 4 && zeros(1) * 10 < pairs {
+            return (Encoding::Utf16Be, 0);
+        }
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => return (Encoding::Utf8, 0),
+        // Cut off mid-character by the sample size
+        Err(e) if e.error_len().is_none() => return (Encoding::Utf8, 0),
+        Err(_) => {}
+    }
+    (Encoding::Latin1, 0)
+}
+
+pub fn decode(bytes: &[u8], enc: Encoding) -> String {
+    match enc {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let units = bytes.chunks_exact(2).map(|p| match enc {
+                Encoding::Utf16Le => u16::from_le_bytes([p[0], p[1]]),
+                _ => u16::from_be_bytes([p[0], p[1]]),
+            });
+            char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+        }
+    }
+}
+
+/// The reverse of `decode`; fails on a char Latin-1 has no byte for
+pub fn encode(text: &str, enc: Encoding) -> Result<Vec<u8>, String> {
+    match enc {
+        Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        Encoding::Latin1 => text.chars()
+            .map(|c| u8::try_from(c).map_err(|_| format!("{:?} can't be encoded as {}", c, enc.name())))
+            .collect(),
+        Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+        Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+    }
+}
+
+/// Length of the longest prefix of `bytes` that doesn't end mid-character
+fn complete_len(bytes: &[u8], enc: Encoding) -> usize {
+    match enc {
+        Encoding::Latin1 => bytes.len(),
+        Encoding::Utf8 => match std::str::from_utf8(bytes) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => bytes.len(),
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let n = bytes.len() & !1;
+            // Don't split a surrogate pair
+            let last = &bytes[n.saturating_sub(2)..n];
+            let high = |u: u16| (0xD800..0xDC00).contains(&u);
+            match (enc, last) {
+                (Encoding::Utf16Le, [a, b]) if high(u16::from_le_bytes([*a, *b])) => n - 2,
+                (Encoding::Utf16Be, [a, b]) if high(u16::from_be_bytes([*a, *b])) => n - 2,
+                _ => n,
+            }
+        }
+    }
+}
+
+/// Bytes to skip at `buf`'s start so it begins on a character boundary
+fn boundary_skip(buf: &[u8], enc: Encoding) -> usize {
+    // The second half of a surrogate pair
+    let low = |u: u16| (0xDC00..0xE000).contains(&u);
+    match (enc, buf) {
+        (Encoding::Utf8, _) => buf.iter().take(3).take_while(|&&b| b & 0xC0 == 0x80).count(),
+        (Encoding::Utf16Le, [a, b, ..]) if low(u16::from_le_bytes([*a, *b])) => 2,
+        (Encoding::Utf16Be, [a, b, ..]) if low(u16::from_be_bytes([*a, *b])) => 2,
+        _ => 0,
+    }
+}
+
+/// Offsets of the newline units in `buf`, which starts unit-aligned
+fn newlines(buf: &[u8], enc: Encoding) -> Vec<usize> {
+    let unit = enc.unit();
+    buf.chunks_exact(unit).enumerate()
+        .filter(|(_, u)| enc.is_newline(u))
+        .map(|(i, _)| i * unit)
+        .collect()
+}
+
+/// Lines in `buf`, counting an unterminated last one
+fn line_count(buf: &[u8], enc: Encoding) -> usize {
+    let ends = newlines(buf, enc);
+    let closed = ends.last().map_or(0, |&nl| nl + enc.unit());
+    ends.len() + usize::from(buf.len() > closed)
+}
+
+/// Line count and line-ending style of a whole file
+#[derive(Debug, Clone, Copy)]
+pub struct LineStats {
+    pub lines: usize,
+    /// `lf`, `crlf`, `cr`, `mixed`, or `none` without any line break
+    pub ending: &'static str,
+}
+
+/// Scan `reader` (positioned after the BOM) to the end. Lines are counted
+/// like `read_lines` does: by `\n`, plus an unterminated last line.
+pub(crate) fn line_stats(mut reader: impl Read, enc: Encoding) -> std::io::Result<LineStats> {
+    let unit = enc.unit();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut carry = Vec::new();
+    let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+    let (mut after_cr, mut open_line) = (false, false);
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        carry.extend_from_slice(&buf[..n]);
+        let whole = carry.len() - carry.len() % unit;
+        for u in carry[..whole].chunks_exact(unit) {
+            let c = match enc {
+                Encoding::Utf16Le => u16::from_le_bytes([u[0], u[1]]),
+                Encoding::Utf16Be => u16::from_be_bytes([u[0], u[1]]),
+                _ => u[0] as u16,
+            };
+            if c == b'\n' as u16 {
+                if after_cr { crlf += 1 } else { lf += 1 }
+                open_line = false;
+            } else {
+                if after_cr {
+                    cr += 1;
+                }
+                open_line = true;
+            }
+            after_cr = c == b'\r' as u16;
+        }
+        carry.drain(..whole);
+    }
+    if after_cr {
+        cr += 1;
+    }
+    let ending = match (lf > 0, crlf > 0, cr > 0) {
+        (false, false, false) => "none",
+        (true, false, false) => "lf",
+        (false, true, false) => "crlf",
+        (false, false, true) => "cr",
+        _ => "mixed",
+    };
+    Ok(LineStats { lines: lf + crlf + usize::from(open_line), ending })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TextChunk {
+    pub content: String,
+    pub encoding: &'static str,
+    /// Byte range of the file this text came from
+    pub offset: u64,
+    pub length: u64,
+    /// File size
+    pub size: u64,
+    /// The range reaches the end of the file
+    pub eof: bool,
+    /// First line number, for line reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// Lines returned, for line reads and tails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+}
+
+struct Opened {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    enc: Encoding,
+    bom: u64,
+}
+
+fn open(path: &str) -> Result<Opened, String> {
+    let p = allowed_path(path, Op::Read)?;
+    let mut file = File::open(&p).map_err(|e| format!("read error: {}", e))?;
+    let meta = file.metadata().map_err(|e| format!("read error: {}", e))?;
+    if !meta.is_file() {
+        return Err(format!("not a regular file: {}", p.display()));
+    }
+    let mut sample = Vec::with_capacity(SAMPLE);
+    (&mut file).take(SAMPLE as u64).read_to_end(&mut sample).map_err(|e| format!("read error: {}", e))?;
+    let (enc, bom) = detect(&sample);
+    Ok(Opened { path: p, file, size: meta.len(), enc, bom: bom as u64 })
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::with_capacity(len as usize);
+    file.seek(SeekFrom::Start(offset)).map_err(|e| format!("read error: {}", e))?;
+    file.take(len).read_to_end(&mut buf).map_err(|e| format!("read error: {}", e))?;
+    Ok(buf)
+}
+
+impl Opened {
+    fn chunk(&self, buf: &[u8], offset: u64) -> TextChunk {
+        TextChunk {
+            content: decode(buf, self.enc),
+            encoding: self.enc.name(),
+            offset,
+            length: buf.len() as u64,
+            size: self.size,
+            eof: offset + buf.len() as u64 >= self.size,
+            line: None,
+            lines: None,
+        }
+    }
+}
+
+/// Up to `length` bytes (at most MAX_CHUNK) from `offset`, moved onto
+/// character boundaries
+pub fn read_bytes(path: &str, offset: u64, length: Option<u64>) -> Result<TextChunk, String> {
+    let mut f = open(path)?;
+    let unit = f.enc.unit() as u64;
+    let mut start = offset.max(f.bom).min(f.size);
+    start -= (start - f.bom) % unit;
+    let len = length.unwrap_or(MAX_CHUNK).min(MAX_CHUNK);
+    let buf = read_at(&mut f.file, start, len)?;
+    let skip = boundary_skip(&buf, f.enc);
+    let end = if start + buf.len() as u64 >= f.size { buf.len() } else { skip + complete_len(&buf[skip..], f.enc) };
+    Ok(f.chunk(&buf[skip..end], start + skip as u64))
+}
+
+/// `count` lines (default: as many as fit in MAX_CHUNK) from 1-based `line`
+pub fn read_lines(path: &str, line: usize, count: Option<usize>) -> Result<TextChunk, String> {
+    if line == 0 {
+        return Err("line numbers start at 1".into());
+    }
+    let mut f = open(path)?;
+    let count = count.unwrap_or(MAX_LINES).clamp(1, MAX_LINES);
+    let block = 64 * 1024;
+
+    // Find where `line` starts
+    let mut start = f.bom;
+    let mut seen = 1;
+    let mut pos = f.bom;
+    while seen < line && pos < f.size {
+        let buf = read_at(&mut f.file, pos, block)?;
+        if buf.is_empty() {
+            break;
+        }
+        for nl in newlines(&buf, f.enc) {
+            seen += 1;
+            if seen == line {
+                start = pos + (nl + f.enc.unit()) as u64;
+                break;
+            }
+        }
+        pos += buf.len() as u64;
+    }
+    if seen < line {
+        let mut chunk = f.chunk(&[], f.size);
+        chunk.line = Some(line);
+        chunk.lines = Some(0);
+        return Ok(chunk);
+    }
+
+    // Take up to `count` lines, cut at a line end if MAX_CHUNK runs out first
+    let buf = read_at(&mut f.file, start, MAX_CHUNK)?;
+    let ends = newlines(&buf, f.enc);
+    let unit = f.enc.unit();
+    let end = match ends.get(count - 1) {
+        Some(&nl) => nl + unit,
+        None if start + buf.len() as u64 >= f.size => buf.len(),
+        // One line longer than MAX_CHUNK: return a piece of it
+        None => ends.last().map(|&nl| nl + unit).unwrap_or_else(|| complete_len(&buf, f.enc)),
+    };
+    let mut chunk = f.chunk(&buf[..end], start);
+    chunk.line = Some(line);
+    chunk.lines = Some(line_count(&buf[..end], f.enc));
+    Ok(chunk)
+}
+
+/// The last `lines` lines, looking back at most MAX_TAIL_BYTES
+pub fn tail(path: &str, lines: usize) -> Result<TextChunk, String> {
+    let mut f = open(path)?;
+    let lines = lines.clamp(1, MAX_LINES);
+    let unit = f.enc.unit();
+    let mut start = f.size;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut block = 64 * 1024u64;
+    loop {
+        let mut ends = newlines(&buf, f.enc);
+        // A newline at the very end closes the last line, it doesn't start one
+        if ends.last().map(|&nl| nl + unit) == Some(buf.len()) {
+            ends.pop();
+        }
+        if ends.len() >= lines {
+            let cut = ends[ends.len() - lines] + unit;
+            let mut chunk = f.chunk(&buf[cut..], start + cut as u64);
+            chunk.lines = Some(lines);
+            return Ok(chunk);
+        }
+        if start <= f.bom || f.size - start >= MAX_TAIL_BYTES {
+            // Whole file, or as far back as we look: a boundary is all we need
+            let skip = if start <= f.bom { 0 } else { boundary_skip(&buf, f.enc) };
+            let mut chunk = f.chunk(&buf[skip..], start + skip as u64);
+            chunk.lines = Some(line_count(&buf[skip..], f.enc));
+            return Ok(chunk);
+        }
+        let from = start.saturating_sub(block).max(f.bom).max(f.size.saturating_sub(MAX_TAIL_BYTES));
+        let from = from - (from - f.bom) % unit as u64;
+        let mut more = read_at(&mut f.file, from, start - from)?;
+        more.extend_from_slice(&buf);
+        buf = more;
+        start = from;
+        block *= 2;
+    }
+}
+
+/// Lines appended to a followed file since the last poll
+#[derive(Debug, Clone, Serialize)]
+pub struct Appended {
+    pub lines: Vec<String>,
+    /// Bytes of the file consumed so far
+    pub offset: u64,
+    /// The file was truncated or replaced; reading restarted at its beginning
+    pub reset: bool,
+}
+
+/// `tail -f`: remembers how far it has read and turns new bytes into lines.
+/// The path is reopened on every poll, so a rotated log is picked up.
+pub struct Follower {
+    path: PathBuf,
+    pub enc: Encoding,
+    pub offset: u64,
+    inode: u64,
+    /// Trailing bytes of an incomplete character
+    pending: Vec<u8>,
+    /// Text after the last newline
+    partial: String,
+}
+
+impl Follower {
+    /// Start following at the current end of the file
+    pub fn open(path: &str) -> Result<Self, String> {
+        let f = open(path)?;
+        let inode = f.file.metadata().map(|m| m.ino()).unwrap_or(0);
+        Ok(Self { path: f.path, enc: f.enc, offset: f.size.max(f.bom), inode, pending: Vec::new(), partial: String::new() })
+    }
+
+    pub fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    /// New complete lines, or None if nothing changed
+    pub fn poll(&mut self) -> Result<Option<Appended>, String> {
+        let meta = std::fs::metadata(&self.path).map_err(|e| format!("follow error: {}", e))?;
+        let mut reset = false;
+        if meta.ino() != self.inode || meta.len() < self.offset {
+            self.inode = meta.ino();
+            self.offset = 0;
+            self.pending.clear();
+            self.partial.clear();
+            reset = true;
+        }
+        if meta.len() == self.offset {
+            return Ok(reset.then(|| Appended { lines: Vec::new(), offset: 0, reset }));
+        }
+        let mut file = File::open(&self.path).map_err(|e| format!("follow error: {}", e))?;
+        let mut buf = read_at(&mut file, self.offset, MAX_CHUNK)?;
+        if self.offset == 0 {
+            // A new file: skip its BOM and guess its encoding again
+            let (enc, bom) = detect(&buf[..buf.len().min(SAMPLE)]);
+            self.enc = enc;
+            buf.drain(..bom);
+            self.offset = bom as u64;
+        }
+        self.offset += buf.len() as u64;
+        self.pending.extend_from_slice(&buf);
+        let n = complete_len(&self.pending, self.enc);
+        self.partial.push_str(&decode(&self.pending[..n], self.enc));
+        self.pending.drain(..n);
+
+        let mut lines: Vec<String> = self.partial.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l).to_string()).collect();
+        self.partial = lines.pop().unwrap_or_default();
+        if self.partial.len() > MAX_PARTIAL {
+            lines.push(std::mem::take(&mut self.partial));
+        }
+        if lines.is_empty() && !reset {
+            return Ok(None);
+        }
+        Ok(Some(Appended { lines, offset: self.offset, reset }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn utf16le(s: &str, bom: bool) -> Vec<u8> {
+        let mut out = if bom { vec![0xFF, 0xFE] } else { Vec::new() };
+        out.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+        out
+    }
+
+    fn utf16be(s: &str, bom: bool) -> Vec<u8> {
+        let mut out = if bom { vec![0xFE, 0xFF] } else { Vec::new() };
+        out.extend(s.encode_utf16().flat_map(u16::to_be_bytes));
+        out
+    }
+
+    /// A temp file holding `bytes`, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(bytes: &[u8]) -> Self {
+            let p = std::env::temp_dir().join(format!("tmux-mobile-test-{}.txt", uuid::Uuid::new_v4()));
+            std::fs::write(&p, bytes).unwrap();
+            Self(p)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        fn append(&self, bytes: &[u8]) {
+            std::fs::OpenOptions::new().append(true).open(&self.0).unwrap().write_all(bytes).unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn detect_encodings() {
+        let text = "plain ascii text here";
+        let cases: Vec<(&str, Vec<u8>, (Encoding, usize))> = vec![
+            ("utf-8 bom", b"\xEF\xBB\xBFhi".to_vec(), (Encoding::Utf8, 3)),
+            ("utf-16le bom", utf16le("hi", true), (Encoding::Utf16Le, 2)),
+            ("utf-16be bom", utf16be("hi", true), (Encoding::Utf16Be, 2)),
+            ("utf-16le no bom", utf16le(text, false), (Encoding::Utf16Le, 0)),
+            ("utf-16be no bom", utf16be(text, false), (Encoding::Utf16Be, 0)),
+            // Too short to tell by the zeros, and valid UTF-8
+            ("short utf-16le", utf16le("hi", false), (Encoding::Utf8, 0)),
+            ("utf-8", "café ☕".as_bytes().to_vec(), (Encoding::Utf8, 0)),
+            ("utf-8 cut mid-char", "café".as_bytes()[..4].to_vec(), (Encoding::Utf8, 0)),
+            ("latin-1", b"caf\xE9 au lait".to_vec(), (Encoding::Latin1, 0)),
+            ("empty", Vec::new(), (Encoding::Utf8, 0)),
+        ];
+        for (name, bytes, want) in cases {
+            assert_eq!(detect(&bytes), want, "{}", name);
+        }
+        assert_eq!(decode(b"caf\xE9", Encoding::Latin1), "café");
+        assert_eq!(decode(&utf16be("a😀", false), Encoding::Utf16Be), "a😀");
+        // A lone surrogate becomes a replacement character
+        assert_eq!(decode(&[0x3D, 0xD8, b'a', 0], Encoding::Utf16Le), "\u{FFFD}a");
+    }
+
+    #[test]
+    fn encode_reverses_decode() {
+        for enc in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Latin1] {
+            let text = if enc == Encoding::Latin1 { "café\r\n" } else { "café ☕ 😀\n" };
+            let bytes = encode(text, enc).unwrap();
+            assert_eq!(decode(&bytes, enc), text, "{}", enc.name());
+            assert_eq!(Encoding::from_name(enc.name()), Some(enc));
+            let mut with_bom = enc.bom().to_vec();
+            with_bom.extend(&bytes);
+            assert_eq!(detect(&with_bom).0, enc, "{}", enc.name());
+        }
+        assert_eq!(encode("café", Encoding::Latin1).unwrap(), b"caf\xE9");
+        assert_eq!(encode("1 €", Encoding::Latin1).unwrap_err(), "'€' can't be encoded as iso-8859-1");
+        assert_eq!(Encoding::from_name("Latin1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_name("shift_jis"), None);
+    }
+
+    #[test]
+    fn read_bytes_boundaries() {
+        // a é € 😀 b: 1 + 2 + 3 + 4 + 1 bytes
+        let utf8 = TempFile::new("aé€😀b".as_bytes());
+        // BOM, a, 😀 as a surrogate pair, b: 2 + 2 + 4 + 2 bytes
+        let le = TempFile::new(&utf16le("a😀b", true));
+        let be = TempFile::new(&utf16be("a😀b", true));
+        // file, offset, length, content, where it starts, bytes, eof
+        type Case<'a> = (&'a TempFile, u64, Option<u64>, &'a str, u64, u64, bool);
+        let cases: &[Case] = &[
+            (&utf8, 0, Some(2), "a", 0, 1, false), // é would be cut in half
+            (&utf8, 1, Some(10), "é€😀b", 1, 10, true),
+            (&utf8, 2, Some(3), "", 3, 0, false), // starts inside é, € doesn't fit
+            (&utf8, 4, None, "😀b", 6, 5, true),  // starts inside €
+            (&utf8, 99, None, "", 11, 0, true),
+            (&le, 0, None, "a😀b", 2, 8, true), // BOM skipped
+            (&le, 3, Some(4), "a", 2, 2, false), // aligned down; the pair doesn't fit
+            (&le, 6, None, "b", 8, 2, true),     // starts on the low surrogate
+            (&be, 4, Some(4), "😀", 4, 4, false),
+            (&be, 7, None, "b", 8, 2, true),
+        ];
+        for (file, offset, length, content, at, len, eof) in cases {
+            let c = read_bytes(file.path(), *offset, *length).unwrap();
+            let what = format!("offset {} of {:?}", offset, file.0);
+            assert_eq!(c.content, *content, "{}", what);
+            assert_eq!((c.offset, c.length, c.eof), (*at, *len, *eof), "{}", what);
+        }
+        let c = read_bytes(le.path(), 0, None).unwrap();
+        assert_eq!((c.encoding, c.length, c.size), ("utf-16le", 8, 10));
+    }
+
+    #[test]
+    fn read_lines_ranges() {
+        let text = "one\ntwo\r\nthree\nfour";
+        let utf8 = TempFile::new(text.as_bytes());
+        let be = TempFile::new(&utf16be(text, true));
+        for file in [&utf8, &be] {
+            let cases: &[(usize, Option<usize>, &str, usize, bool)] = &[
+                (1, Some(1), "one\n", 1, false),
+                (2, Some(2), "two\r\nthree\n", 2, false),
+                (3, None, "three\nfour", 2, true),
+                (4, Some(5), "four", 1, true),
+                (5, None, "", 0, true),
+            ];
+            for (line, count, content, lines, eof) in cases {
+                let c = read_lines(file.path(), *line, *count).unwrap();
+                assert_eq!(c.content, *content, "line {} of {:?}", line, file.0);
+                assert_eq!((c.line, c.lines, c.eof), (Some(*line), Some(*lines), *eof), "line {} of {:?}", line, file.0);
+            }
+        }
+        assert!(read_lines(utf8.path(), 0, None).is_err());
+        // Line starts past the first 64KB block
+        let long: String = (1..=20_000).map(|i| format!("line {}\n", i)).collect();
+        let big = TempFile::new(long.as_bytes());
+        let c = read_lines(big.path(), 15_000, Some(2)).unwrap();
+        assert_eq!(c.content, "line 15000\nline 15001\n");
+    }
+
+    #[test]
+    fn tail_lines() {
+        let cases: &[(&str, usize, &str, usize)] = &[
+            ("a\nb\nc\n", 2, "b\nc\n", 2),
+            ("a\nb\nc", 2, "b\nc", 2),
+            ("a\nb\nc\n", 10, "a\nb\nc\n", 3),
+            ("\n\n\n", 2, "\n\n", 2),
+            ("solo", 1, "solo", 1),
+            ("", 3, "", 0),
+        ];
+        for (text, n, content, lines) in cases {
+            for bytes in [text.as_bytes().to_vec(), utf16le(text, true)] {
+                let file = TempFile::new(&bytes);
+                let c = tail(file.path(), *n).unwrap();
+                assert_eq!((c.content.as_str(), c.lines), (*content, Some(*lines)), "tail {} of {:?}", n, text);
+                assert!(c.eof);
+            }
+        }
+        // Reads back block by block
+        let long: String = (1..=50_000).map(|i| format!("line {}\n", i)).collect();
+        let big = TempFile::new(long.as_bytes());
+        let c = tail(big.path(), 3).unwrap();
+        assert_eq!(c.content, "line 49998\nline 49999\nline 50000\n");
+        assert_eq!(c.offset + c.length, c.size);
+        // One line longer than the look-back: the end of it, from a char boundary
+        let huge = TempFile::new("é".repeat(MAX_TAIL_BYTES as usize).as_bytes());
+        let c = tail(huge.path(), 1).unwrap();
+        assert!(c.length <= MAX_TAIL_BYTES && c.length > 0);
+        assert!(c.content.chars().all(|ch| ch == 'é'));
+    }
+
+    #[test]
+    fn follower_polls() {
+        let file = TempFile::new(b"old\n");
+        let mut f = Follower::open(file.path()).unwrap();
+        assert_eq!(f.offset, 4);
+        assert!(f.poll().unwrap().is_none(), "nothing new");
+
+        // A partial line is held back until its newline arrives
+        file.append(b"new li");
+        assert!(f.poll().unwrap().is_none());
+        file.append(b"ne\r\nsecond\n");
+        let a = f.poll().unwrap().unwrap();
+        assert_eq!((a.lines, a.offset, a.reset), (vec!["new line".to_string(), "second".into()], 21, false));
+
+        // So is a character split between writes
+        let e = "é\n".as_bytes();
+        file.append(&e[..1]);
+        assert!(f.poll().unwrap().is_none());
+        file.append(&e[1..]);
+        assert_eq!(f.poll().unwrap().unwrap().lines, ["é"]);
+
+        // An overlong partial line is sent anyway
+        file.append(&vec![b'x'; MAX_PARTIAL + 1]);
+        assert_eq!(f.poll().unwrap().unwrap().lines[0].len(), MAX_PARTIAL + 1);
+        file.append(b"\n");
+        assert_eq!(f.poll().unwrap().unwrap().lines, [""]);
+
+        // Truncated: start over from the beginning
+        std::fs::write(&file.0, b"z\n").unwrap();
+        let a = f.poll().unwrap().unwrap();
+        assert_eq!((a.lines, a.offset, a.reset), (vec!["z".to_string()], 2, true));
+        std::fs::write(&file.0, b"").unwrap();
+        let a = f.poll().unwrap().unwrap();
+        assert!(a.reset && a.lines.is_empty() && a.offset == 0);
+
+        // Replaced by a new file in another encoding (a rotated log)
+        let next = TempFile::new(&utf16le("rotated\nhalf", true));
+        std::fs::rename(&next.0, &file.0).unwrap();
+        let a = f.poll().unwrap().unwrap();
+        assert_eq!((a.lines, a.reset, f.enc), (vec!["rotated".to_string()], true, Encoding::Utf16Le));
+        file.append(&utf16le("😀\n", false)[..2]);
+        assert!(f.poll().unwrap().is_none(), "half a surrogate pair");
+        file.append(&utf16le("😀\n", false)[2..]);
+        assert_eq!(f.poll().unwrap().unwrap().lines, ["half😀"]);
+    }
+}
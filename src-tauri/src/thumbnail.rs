@@ -0,0 +1,392 @@
+/// Downscaled previews of images, cached on disk by path, mtime and size
+use crate::fs::allowed_path;
+use crate::sandbox::Op;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 1024;
+/// Thumbnails embedded in `fs_list` entries
+pub const LIST_SIZE: u32 = 64;
+/// Time one `fs_list` may spend making new thumbnails
+pub const LIST_BUDGET: Duration = Duration::from_secs(2);
+const JPEG_QUALITY: u8 = 80;
+
+fn default_max_cache_mb() -> u64 { 200 }
+fn default_max_source_mb() -> u64 { 64 }
+
+/// `[thumbnails]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThumbnailConfig {
+    /// Where generated thumbnails are cached
+    pub dir: Option<String>,
+    /// The least recently used thumbnails are removed past this size
+    #[serde(default = "default_max_cache_mb")]
+    pub max_cache_mb: u64,
+    /// Larger images are not decoded
+    #[serde(default = "default_max_source_mb")]
+    pub max_source_mb: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_cache_mb: default_max_cache_mb(),
+            max_source_mb: default_max_source_mb(),
+        }
+    }
+}
+
+struct Settings {
+    dir: PathBuf,
+    max_cache: u64,
+    max_source: u64,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+fn settings() -> &'static Settings {
+    // Tests cache in the temp dir rather than the home directory
+    #[cfg(test)]
+    let cfg = ThumbnailConfig {
+        dir: Some(std::env::temp_dir().join("tmux-mobile-test-thumbnails").to_string_lossy().into_owned()),
+        ..ThumbnailConfig::default()
+    };
+    #[cfg(not(test))]
+    let cfg = ThumbnailConfig::default();
+    SETTINGS.get_or_init(|| settings_from(cfg))
+}
+
+fn settings_from(cfg: ThumbnailConfig) -> Settings {
+    Settings {
+        dir: cfg.dir.map(|d| crate::config::expand_home(&d))
+            .unwrap_or_else(|| crate::config::config_dir().join("thumbnails")),
+        max_cache: cfg.max_cache_mb * 1024 * 1024,
+        max_source: cfg.max_source_mb * 1024 * 1024,
+    }
+}
+
+pub fn init(cfg: ThumbnailConfig) {
+    let _ = SETTINGS.set(settings_from(cfg));
+}
+
+/// Bytes in the cache: a full scan at the first store, then a running
+/// total, so the directory is only walked again when it passes the cap
+static CACHE_SIZE: Mutex<Option<u64>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    /// `png`, `jpeg` or `webp`; None picks PNG for images with transparency
+    /// and JPEG otherwise
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "png" => Ok(Format::Png),
+            "jpeg" | "jpg" => Ok(Format::Jpeg),
+            "webp" => Ok(Format::Webp),
+            _ => Err(format!("unsupported thumbnail format: {} (png, jpeg or webp)", s)),
+        }
+    }
+
+    fn ext(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+            Format::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Thumbnail {
+    pub mime: &'static str,
+    /// Base64-encoded image
+    pub data: String,
+    pub width: u32,
+    pub height: u32,
+    /// Served from the cache
+    pub cached: bool,
+}
+
+/// Image types that can be decoded, by name
+pub fn supported(name: &str) -> bool {
+    matches!(
+        crate::mime::by_name(name),
+        Some("image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp")
+    )
+}
+
+/// Cache file for one rendition of a source file; a new mtime or size
+/// makes a new key, so stale entries are simply never hit again
+fn cache_path(src: &Path, meta: &fs::Metadata, size: u32, format: Option<Format>) -> PathBuf {
+    let mut h = Sha256::new();
+    h.update(src.as_os_str().as_encoded_bytes());
+    h.update(format!("\0{}.{}\0{}\0{}", meta.mtime(), meta.mtime_nsec(), meta.len(), size));
+    let hash: String = h.finalize().iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    // With no format asked for, the extension is only known after decoding
+    let ext = format.map_or("auto", |f| f.ext());
+    settings().dir.join(format!("{}-{}.{}", hash, size, ext))
+}
+
+fn from_cache(path: &Path) -> Option<Thumbnail> {
+    let bytes = fs::read(path).ok()?;
+    let format = image::guess_format(&bytes).ok()?;
+    let mime = match format {
+        image::ImageFormat::Png => Format::Png,
+        image::ImageFormat::Jpeg => Format::Jpeg,
+        image::ImageFormat::WebP => Format::Webp,
+        _ => return None,
+    }.mime();
+    let (width, height) = ImageReader::with_format(std::io::Cursor::new(&bytes), format).into_dimensions().ok()?;
+    // Mark it recently used
+    if let Ok(f) = fs::File::options().write(true).open(path) {
+        let _ = f.set_modified(SystemTime::now());
+    }
+    Some(Thumbnail {
+        mime,
+        data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+        width,
+        height,
+        cached: true,
+    })
+}
+
+fn decode(src: &Path) -> Result<DynamicImage, String> {
+    let err = |e: image::ImageError| format!("cannot decode {}: {}", src.display(), e);
+    let reader = ImageReader::open(src)
+        .map_err(|e| format!("read error: {}", e))?
+        .with_guessed_format()
+        .map_err(|e| format!("read error: {}", e))?;
+    if reader.format().is_none() {
+        return Err(format!("not a supported image: {}", src.display()));
+    }
+    let mut decoder = reader.into_decoder().map_err(err)?;
+    let orientation = decoder.orientation().map_err(err)?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(err)?;
+    // Camera photos are often stored sideways with an EXIF rotation
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn encode(img: &DynamicImage, format: Format) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let result = match format {
+        Format::Png => img.write_with_encoder(PngEncoder::new(&mut out)),
+        Format::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)),
+        Format::Webp => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut out)),
+    };
+    result.map_err(|e| format!("cannot encode thumbnail: {}", e))?;
+    Ok(out)
+}
+
+/// Write through a temp file so a concurrent reader never sees half of it
+fn store(path: &Path, data: &[u8]) {
+    let s = settings();
+    if fs::create_dir_all(&s.dir).is_err() {
+        return;
+    }
+    let tmp = s.dir.join(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    if fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, path)).is_err() {
+        let _ = fs::remove_file(&tmp);
+        return;
+    }
+    let mut size = CACHE_SIZE.lock().unwrap();
+    let total = match *size {
+        Some(n) => n + data.len() as u64,
+        None => prune(&s.dir, u64::MAX),
+    };
+    *size = Some(if total > s.max_cache { prune(&s.dir, s.max_cache) } else { total });
+}
+
+/// Remove the least recently used thumbnails in `dir` until it is under
+/// nine tenths of `max`, if it is over `max`; returns the bytes left
+fn prune(dir: &Path, max: u64) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else { return 0 };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries.flatten()
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+    if total <= max {
+        return total;
+    }
+    files.sort();
+    for (_, len, path) in files {
+        if total <= max / 10 * 9 {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+    total
+}
+
+/// A thumbnail fitting in `size`×`size`, keeping the aspect ratio. Small
+/// images are re-encoded but not enlarged.
+pub fn thumbnail(path: &str, size: u32, format: Option<Format>) -> Result<Thumbnail, String> {
+    let src = allowed_path(path, Op::Read)?;
+    let meta = fs::metadata(&src).map_err(|e| format!("read error: {}", e))?;
+    if !meta.is_file() {
+        return Err(format!("not a regular file: {}", src.display()));
+    }
+    let size = size.clamp(MIN_SIZE, MAX_SIZE);
+    let cached = cache_path(&src, &meta, size, format);
+    if let Some(t) = from_cache(&cached) {
+        return Ok(t);
+    }
+    if meta.len() > settings().max_source {
+        return Err(format!("image too large: {} bytes (max {})", meta.len(), settings().max_source));
+    }
+    let img = decode(&src)?;
+    let img = if img.width() > size || img.height() > size { img.thumbnail(size, size) } else { img };
+    let format = format.unwrap_or(if img.color().has_alpha() { Format::Png } else { Format::Jpeg });
+    let data = encode(&img, format)?;
+    store(&cached, &data);
+    Ok(Thumbnail {
+        mime: format.mime(),
+        data: base64::engine::general_purpose::STANDARD.encode(&data),
+        width: img.width(),
+        height: img.height(),
+        cached: false,
+    })
+}
+
+/// Small thumbnail for a directory listing as a `data:` URL. Cached ones
+/// are always returned; new ones are only made until `deadline`.
+pub fn list_thumbnail(path: &Path, deadline: Instant) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let meta = fs::metadata(&path).ok()?;
+    let cached = from_cache(&cache_path(&path, &meta, LIST_SIZE, None));
+    let t = match cached {
+        Some(t) => t,
+        None if Instant::now() < deadline => thumbnail(&path.to_string_lossy(), LIST_SIZE, None).ok()?,
+        None => return None,
+    };
+    Some(format!("data:{};base64,{}", t.mime, t.data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn s(p: &Path) -> &str {
+        p.to_str().unwrap()
+    }
+
+    #[test]
+    fn cache_is_keyed_by_mtime() {
+        let dir = scratch();
+        let img = dir.join("a.png");
+        RgbImage::from_pixel(400, 200, Rgb([200, 10, 10])).save(&img).unwrap();
+        let first = thumbnail(s(&img), 100, None).unwrap();
+        assert!(!first.cached);
+        assert_eq!((first.width, first.height), (100, 50));
+        let again = thumbnail(s(&img), 100, None).unwrap();
+        assert!(again.cached);
+        assert_eq!((again.data, again.mime), (first.data, first.mime));
+        assert!(!thumbnail(s(&img), 120, None).unwrap().cached, "another size is another entry");
+
+        fs::File::options().write(true).open(&img).unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+        assert!(!thumbnail(s(&img), 100, None).unwrap().cached, "a new mtime misses");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn format_follows_transparency() {
+        let dir = scratch();
+        let opaque = dir.join("opaque.png");
+        let alpha = dir.join("alpha.png");
+        RgbImage::from_pixel(40, 40, Rgb([0, 0, 255])).save(&opaque).unwrap();
+        RgbaImage::from_pixel(40, 40, Rgba([0, 0, 255, 128])).save(&alpha).unwrap();
+        let cases = [
+            (&opaque, None, "image/jpeg"),
+            (&alpha, None, "image/png"),
+            (&alpha, Some(Format::Jpeg), "image/jpeg"),
+            (&opaque, Some(Format::Webp), "image/webp"),
+        ];
+        for (path, format, mime) in cases {
+            let t = thumbnail(s(path), 32, format).unwrap();
+            assert_eq!(t.mime, mime, "{} as {:?}", path.display(), format);
+            let bytes = base64::engine::general_purpose::STANDARD.decode(&t.data).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap().to_mime_type(), mime);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn small_images_are_not_enlarged() {
+        let dir = scratch();
+        let img = dir.join("small.png");
+        RgbImage::new(30, 20).save(&img).unwrap();
+        let t = thumbnail(s(&img), 256, None).unwrap();
+        assert_eq!((t.width, t.height), (30, 20));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn large_sources_are_refused() {
+        let dir = scratch();
+        let img = dir.join("huge.png");
+        RgbImage::new(4, 4).save(&img).unwrap();
+        // Sparse, so it costs no disk; the size check comes before decoding
+        fs::File::options().write(true).open(&img).unwrap().set_len(settings().max_source + 1).unwrap();
+        let err = thumbnail(s(&img), 64, None).unwrap_err();
+        assert!(err.starts_with("image too large"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prune_drops_least_recently_used() {
+        let dir = scratch();
+        let now = SystemTime::now();
+        for (i, name) in ["old", "mid", "new"].iter().enumerate() {
+            let f = fs::File::create(dir.join(name)).unwrap();
+            f.set_len(100).unwrap();
+            f.set_modified(now - Duration::from_secs(100 - i as u64 * 10)).unwrap();
+        }
+        assert_eq!(prune(&dir, 300), 300, "fits: nothing removed");
+        assert_eq!(prune(&dir, 250), 200);
+        assert!(!dir.join("old").exists() && dir.join("mid").exists());
+        assert_eq!(prune(&dir, 150), 100);
+        assert!(dir.join("new").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
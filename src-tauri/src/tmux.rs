@@ -132,6 +132,42 @@ pub fn capture_pane(target: &str, lines: Option<usize>) -> Result<String, String
     ])
 }
 
+/// 捕获 pane 纯文本内容（不含 ANSI 转义序列），用于匹配输出
+pub fn capture_pane_plain(target: &str, lines: Option<usize>) -> Result<String, String> {
+    let start_line = lines.map(|n| format!("-{}", n)).unwrap_or("-200".to_string());
+    run_tmux(&["capture-pane", "-t", target, "-p", "-J", "-S", &start_line])
+}
+
+/// `next` 中 `prev` 里还没有的行。
+/// 以 `prev` 的末尾几行为锚点；最后一行被原地修改（如正在输入的提示符）时会再次返回。
+/// 找不到重叠（清屏）时返回全部内容。
+pub fn new_lines(prev: &str, next: &str) -> Vec<String> {
+    fn trimmed(s: &str) -> Vec<&str> {
+        let mut lines: Vec<&str> = s.lines().collect();
+        while lines.last().is_some_and(|l| l.trim().is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+    let prev = trimmed(prev);
+    let next = trimmed(next);
+    if prev == next {
+        return Vec::new();
+    }
+    // 先用完整的末尾几行匹配，再去掉（可能被修改的）最后一行重试
+    for skip in 0..2.min(prev.len()) {
+        let end = prev.len() - skip;
+        let anchor = &prev[end.saturating_sub(3)..end];
+        if anchor.is_empty() {
+            break;
+        }
+        if let Some(pos) = (anchor.len()..=next.len()).rev().find(|&i| &next[i - anchor.len()..i] == anchor) {
+            return next[pos..].iter().map(|l| l.to_string()).collect();
+        }
+    }
+    next.iter().map(|l| l.to_string()).collect()
+}
+
 /// 向 pane 发送按键
 pub fn send_keys(target: &str, keys: &str, literal: bool) -> Result<(), String> {
     let mut args = vec!["send-keys", "-t", target];
@@ -143,6 +179,11 @@ pub fn send_keys(target: &str, keys: &str, literal: bool) -> Result<(), String>
     Ok(())
 }
 
+/// 向 pane 输入文本（不回车）
+pub fn send_text(target: &str, text: &str) -> Result<(), String> {
+    send_keys(target, text, true)
+}
+
 /// 向 pane 发送文本 + Enter
 pub fn send_command(target: &str, command: &str) -> Result<(), String> {
     send_keys(target, command, true)?;
@@ -150,6 +191,22 @@ pub fn send_command(target: &str, command: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// pane 尺寸 (宽, 高)
+pub fn pane_size(target: &str) -> Result<(usize, usize), String> {
+    let out = run_tmux(&["display-message", "-t", target, "-p", "#{pane_width} #{pane_height}"])?;
+    let mut parts = out.split_whitespace().map(|n| n.parse::<usize>().unwrap_or(0));
+    Ok((parts.next().unwrap_or(80), parts.next().unwrap_or(24)))
+}
+
+/// 把 pane 输出通过管道交给 shell 命令；`command` 为 None 时停止
+pub fn pipe_pane(target: &str, command: Option<&str>) -> Result<(), String> {
+    match command {
+        Some(cmd) => run_tmux(&["pipe-pane", "-O", "-t", target, cmd])?,
+        None => run_tmux(&["pipe-pane", "-t", target])?,
+    };
+    Ok(())
+}
+
 /// 创建新 session
 pub fn new_session(name: &str) -> Result<(), String> {
     run_tmux(&["new-session", "-d", "-s", name])?;
@@ -0,0 +1,620 @@
+/// Chunked, resumable file transfers over binary WebSocket frames.
+///
+/// Every data frame is `[kind: u8][transfer id: 16 bytes][offset: u64 BE][payload]`.
+/// Transfers are kept here rather than on the connection, so a client that
+/// drops off can reconnect and carry on from the last offset it has.
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+/// Client → server: a chunk of an upload
+pub const FRAME_UPLOAD: u8 = 1;
+/// Server → client: a chunk of a download
+pub const FRAME_DOWNLOAD: u8 = 2;
+const HEADER_LEN: usize = 1 + 16 + 8;
+const MIN_CHUNK: usize = 16 * 1024;
+const MAX_CHUNK: usize = 4 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+fn default_chunk_size() -> usize { 256 * 1024 }
+fn default_max_size() -> u64 { 4 * 1024 * 1024 * 1024 }
+fn default_expire_hours() -> u64 { 24 }
+
+/// `[transfers]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferConfig {
+    /// Where partial uploads are kept
+    pub dir: Option<String>,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Largest upload accepted
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+    /// Transfers untouched for this long are dropped with their partial data
+    #[serde(default = "default_expire_hours")]
+    pub expire_hours: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            chunk_size: default_chunk_size(),
+            max_size: default_max_size(),
+            expire_hours: default_expire_hours(),
+        }
+    }
+}
+
+struct Settings {
+    dir: PathBuf,
+    chunk_size: usize,
+    max_size: u64,
+    expire: Duration,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+static TRANSFERS: Mutex<Option<HashMap<Uuid, Arc<Mutex<Transfer>>>>> = Mutex::new(None);
+
+fn settings() -> &'static Settings {
+    // Tests keep scratch files in the temp dir, in small chunks
+    #[cfg(test)]
+    let cfg = TransferConfig {
+        dir: Some(std::env::temp_dir().join("tmux-mobile-test-transfers").to_string_lossy().into_owned()),
+        chunk_size: MIN_CHUNK,
+        ..TransferConfig::default()
+    };
+    #[cfg(not(test))]
+    let cfg = TransferConfig::default();
+    SETTINGS.get_or_init(|| settings_from(cfg))
+}
+
+fn settings_from(cfg: TransferConfig) -> Settings {
+    Settings {
+        dir: cfg.dir.map(|d| crate::config::expand_home(&d))
+            .unwrap_or_else(|| crate::config::config_dir().join("transfers")),
+        chunk_size: cfg.chunk_size.clamp(MIN_CHUNK, MAX_CHUNK),
+        max_size: cfg.max_size,
+        expire: Duration::from_secs(cfg.expire_hours * 3600),
+    }
+}
+
+pub fn init(cfg: TransferConfig) {
+    let _ = SETTINGS.set(settings_from(cfg));
+    // Partial uploads and generated files from a previous run can't be
+    // resumed: their state is gone
+    if let Ok(entries) = fs::read_dir(&settings().dir) {
+        for entry in entries.flatten() {
+            if entry.path().extension().is_some_and(|e| e == "part" || e == "out") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Upload,
+    Download,
+}
+
+struct Transfer {
+    id: Uuid,
+    direction: Direction,
+    /// Upload target or download source
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+    chunk_size: usize,
+    /// Bytes received (upload) or sent (download) so far
+    offset: u64,
+    overwrite: bool,
+    /// Download source modification time, to notice it changing underneath
+    mtime: Option<SystemTime>,
+    /// Upload: hash of the bytes received so far; chunks arrive in order
+    hasher: Sha256,
+    part: Option<File>,
+    /// Download: only the latest stream may send, older ones stop
+    stream: u64,
+    /// Download of a file the server made for it, deleted with the transfer
+    scratch: Option<Scratch>,
+    touched: Instant,
+    last_progress: Instant,
+}
+
+struct Scratch {
+    file: PathBuf,
+    name: String,
+}
+
+impl Transfer {
+    /// The file a download reads from
+    fn source(&self) -> &PathBuf {
+        self.scratch.as_ref().map_or(&self.path, |s| &s.file)
+    }
+
+    /// Delete the files that only exist for this transfer
+    fn discard(&mut self) {
+        self.part = None;
+        let _ = fs::remove_file(part_path(&self.id));
+        if let Some(s) = &self.scratch {
+            let _ = fs::remove_file(&s.file);
+        }
+    }
+
+    fn info(&self) -> TransferInfo {
+        TransferInfo {
+            id: self.id.to_string(),
+            direction: match self.direction {
+                Direction::Upload => "upload",
+                Direction::Download => "download",
+            },
+            path: self.path.to_string_lossy().to_string(),
+            name: match &self.scratch {
+                Some(s) => s.name.clone(),
+                None => self.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            },
+            size: self.size,
+            sha256: self.sha256.clone(),
+            chunk_size: self.chunk_size,
+            offset: self.offset,
+        }
+    }
+
+    fn progress_due(&mut self) -> bool {
+        if self.last_progress.elapsed() < PROGRESS_INTERVAL {
+            return false;
+        }
+        self.last_progress = Instant::now();
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: String,
+    /// `upload` or `download`
+    pub direction: &'static str,
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub chunk_size: usize,
+    pub offset: u64,
+}
+
+/// A decoded binary frame
+pub struct Frame<'a> {
+    pub kind: u8,
+    pub id: Uuid,
+    pub offset: u64,
+    pub data: &'a [u8],
+}
+
+pub fn encode_frame(kind: u8, id: &Uuid, offset: u64, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + data.len());
+    frame.push(kind);
+    frame.extend_from_slice(id.as_bytes());
+    frame.extend_from_slice(&offset.to_be_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+pub fn decode_frame(frame: &[u8]) -> Result<Frame<'_>, String> {
+    if frame.len() < HEADER_LEN {
+        return Err(format!("binary frame too short: {} bytes", frame.len()));
+    }
+    let id = Uuid::from_slice(&frame[1..17]).map_err(|e| e.to_string())?;
+    let offset = u64::from_be_bytes(frame[17..25].try_into().unwrap());
+    Ok(Frame { kind: frame[0], id, offset, data: &frame[HEADER_LEN..] })
+}
+
+pub fn parse_id(id: &str) -> Result<Uuid, String> {
+    Uuid::parse_str(id).map_err(|_| format!("invalid transfer id: {}", id))
+}
+
+fn part_path(id: &Uuid) -> PathBuf {
+    settings().dir.join(format!("{}.part", id))
+}
+
+fn chunk_size(requested: Option<usize>) -> usize {
+    requested.map(|c| c.clamp(MIN_CHUNK, MAX_CHUNK)).unwrap_or(settings().chunk_size)
+}
+
+/// Drop transfers nobody has touched within the expiry window
+fn purge_expired(map: &mut HashMap<Uuid, Arc<Mutex<Transfer>>>) {
+    let expire = settings().expire;
+    map.retain(|_, t| {
+        let mut t = t.lock().unwrap();
+        let keep = t.touched.elapsed() < expire;
+        if !keep {
+            t.discard();
+        }
+        keep
+    });
+}
+
+fn insert(t: Transfer) {
+    let mut guard = TRANSFERS.lock().unwrap();
+    let map = guard.get_or_insert_with(HashMap::new);
+    purge_expired(map);
+    map.insert(t.id, Arc::new(Mutex::new(t)));
+}
+
+fn get(id: &Uuid) -> Option<Arc<Mutex<Transfer>>> {
+    TRANSFERS.lock().unwrap().as_ref()?.get(id).cloned()
+}
+
+fn remove(id: &Uuid) -> Option<Arc<Mutex<Transfer>>> {
+    TRANSFERS.lock().unwrap().as_mut()?.remove(id)
+}
+
+fn new_transfer(direction: Direction, path: PathBuf, size: u64, sha256: String, chunk_size: usize) -> Transfer {
+    Transfer {
+        id: Uuid::new_v4(),
+        direction,
+        path,
+        size,
+        sha256,
+        chunk_size,
+        offset: 0,
+        overwrite: false,
+        mtime: None,
+        hasher: Sha256::new(),
+        part: None,
+        stream: 0,
+        scratch: None,
+        touched: Instant::now(),
+        last_progress: Instant::now(),
+    }
+}
+
+/// Register a download; hashes the whole file up front so the client can
+/// verify what it got, even across reconnects
+pub fn start_download(path: &str, chunk: Option<usize>) -> Result<TransferInfo, String> {
+    let p = sandbox::check(&expand_path(path), Op::Read)?;
+    let meta = fs::metadata(&p).map_err(|e| format!("download error: {}", e))?;
+    if !meta.is_file() {
+        return Err(format!("not a regular file: {}", p.display()));
+    }
+    let sha256 = crate::fs::sha256_file(&p).map_err(|e| format!("download error: {}", e))?;
+    let mut t = new_transfer(Direction::Download, p, meta.len(), sha256, chunk_size(chunk));
+    t.mtime = meta.modified().ok();
+    let info = t.info();
+    insert(t);
+    Ok(info)
+}
+
+/// A new file in the transfer directory for the server to fill and then
+/// hand to `start_scratch_download`
+pub(crate) fn scratch_file() -> Result<(PathBuf, File), String> {
+    let dir = &settings().dir;
+    fs::create_dir_all(dir).map_err(|e| format!("cannot create transfer dir: {}", e))?;
+    let path = dir.join(format!("{}.out", Uuid::new_v4()));
+    let file = File::create(&path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+    Ok((path, file))
+}
+
+/// Register a download of a scratch file made from `path` (an archive of
+/// it, an entry pulled out of it), offered to the client as `name`. The
+/// file is deleted when the transfer finishes, fails or is dropped.
+pub(crate) fn start_scratch_download(path: PathBuf, file: PathBuf, name: String, chunk: Option<usize>) -> Result<TransferInfo, String> {
+    let result = fs::metadata(&file).and_then(|meta| Ok((meta, crate::fs::sha256_file(&file)?)));
+    let (meta, sha256) = match result {
+        Ok(r) => r,
+        Err(e) => {
+            let _ = fs::remove_file(&file);
+            return Err(format!("download error: {}", e));
+        }
+    };
+    let mut t = new_transfer(Direction::Download, path, meta.len(), sha256, chunk_size(chunk));
+    t.mtime = meta.modified().ok();
+    t.scratch = Some(Scratch { file, name });
+    let info = t.info();
+    insert(t);
+    Ok(info)
+}
+
+/// Register an upload of `size` bytes hashing to `sha256` into `path`
+pub fn start_upload(path: &str, size: u64, sha256: &str, chunk: Option<usize>, overwrite: bool) -> Result<TransferInfo, String> {
+    let p = sandbox::check(&expand_path(path), Op::Write)?;
+    let s = settings();
+    if size > s.max_size {
+        return Err(format!("upload too large: {} bytes (max {})", size, s.max_size));
+    }
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("sha256 must be 64 hex digits".into());
+    }
+    if !overwrite && fs::symlink_metadata(&p).is_ok() {
+        return Err(format!("{} already exists (pass overwrite: true to replace it)", p.display()));
+    }
+    if !p.parent().is_some_and(|d| d.is_dir()) {
+        return Err(format!("parent directory does not exist: {}", p.display()));
+    }
+    fs::create_dir_all(&s.dir).map_err(|e| format!("cannot create transfer dir: {}", e))?;
+    let mut t = new_transfer(Direction::Upload, p, size, sha256.to_ascii_lowercase(), chunk_size(chunk));
+    t.overwrite = overwrite;
+    let info = t.info();
+    insert(t);
+    Ok(info)
+}
+
+/// Current state of a transfer; `offset` is where an upload should resume
+pub fn status(id: &Uuid) -> Result<TransferInfo, String> {
+    let t = get(id).ok_or_else(|| format!("no such transfer: {}", id))?;
+    let mut t = t.lock().unwrap();
+    t.touched = Instant::now();
+    Ok(t.info())
+}
+
+pub fn list() -> Vec<TransferInfo> {
+    let mut guard = TRANSFERS.lock().unwrap();
+    let map = guard.get_or_insert_with(HashMap::new);
+    purge_expired(map);
+    map.values().map(|t| t.lock().unwrap().info()).collect()
+}
+
+/// Abort a transfer and throw away any partial upload
+pub fn cancel(id: &Uuid) -> Result<TransferInfo, String> {
+    let t = remove(id).ok_or_else(|| format!("no such transfer: {}", id))?;
+    let mut t = t.lock().unwrap();
+    t.discard();
+    Ok(t.info())
+}
+
+/// Start (or restart) sending a download from `offset`; returns a stream
+/// token for `read_chunk`. Any earlier stream of the same transfer stops.
+pub fn claim_download(id: &Uuid, offset: Option<u64>) -> Result<(TransferInfo, u64), String> {
+    let t = get(id).ok_or_else(|| format!("no such transfer: {}", id))?;
+    let mut t = t.lock().unwrap();
+    if t.direction != Direction::Download {
+        return Err(format!("{} is not a download", id));
+    }
+    let offset = offset.unwrap_or(t.offset);
+    if offset > t.size {
+        return Err(format!("offset {} is past the end ({} bytes)", offset, t.size));
+    }
+    t.offset = offset;
+    t.stream += 1;
+    t.touched = Instant::now();
+    Ok((t.info(), t.stream))
+}
+
+pub struct Chunk {
+    pub offset: u64,
+    pub data: Vec<u8>,
+    /// Last chunk of the file; the transfer is finished and removed
+    pub done: bool,
+    /// Time for a progress notification
+    pub progress: bool,
+    pub info: TransferInfo,
+}
+
+/// Next chunk of a download, or None once this stream has been cancelled
+/// or superseded by a newer `claim_download`
+pub fn read_chunk(id: &Uuid, stream: u64) -> Result<Option<Chunk>, String> {
+    let Some(t) = get(id) else { return Ok(None) };
+    let result = {
+        let mut t = t.lock().unwrap();
+        if t.stream != stream {
+            return Ok(None);
+        }
+        next_chunk(&mut t)
+    };
+    // Not under the transfer's lock: purge_expired takes the two the other way round
+    if !matches!(result, Ok(Chunk { done: false, .. })) {
+        if let Some(t) = remove(id) {
+            t.lock().unwrap().discard();
+        }
+    }
+    result.map(Some)
+}
+
+fn next_chunk(t: &mut Transfer) -> Result<Chunk, String> {
+    match fs::metadata(t.source()) {
+        Ok(m) if m.len() == t.size && m.modified().ok() == t.mtime => {}
+        Ok(_) => return Err(format!("{} changed during the download", t.path.display())),
+        Err(e) => return Err(format!("download error: {}", e)),
+    }
+    let offset = t.offset;
+    let len = (t.size - offset).min(t.chunk_size as u64) as usize;
+    let mut data = vec![0u8; len];
+    File::open(t.source())
+        .and_then(|mut f| {
+            f.seek(SeekFrom::Start(offset))?;
+            f.read_exact(&mut data)
+        })
+        .map_err(|e| format!("download error: {}", e))?;
+    t.offset += len as u64;
+    t.touched = Instant::now();
+    let done = t.offset == t.size;
+    let progress = !done && t.progress_due();
+    Ok(Chunk { offset, data, done, progress, info: t.info() })
+}
+
+pub enum ChunkError {
+    /// The chunk was not at the expected offset; resend from `expected`
+    OutOfOrder { expected: u64 },
+    /// The chunk was refused but the transfer is still there
+    Rejected(String),
+    /// The transfer is gone (unknown, or failed for good)
+    Failed(String),
+}
+
+pub struct Written {
+    pub progress: bool,
+    /// The upload is complete, verified and in place
+    pub done: bool,
+    pub info: TransferInfo,
+}
+
+/// Append an upload chunk. Chunks must arrive in order; the last one
+/// verifies the checksum and moves the file into place.
+pub fn write_chunk(id: &Uuid, offset: u64, data: &[u8]) -> Result<Written, ChunkError> {
+    let t = get(id).ok_or_else(|| ChunkError::Failed(format!("no such transfer: {}", id)))?;
+    let result = append(&mut t.lock().unwrap(), offset, data);
+    if matches!(result, Ok(Written { done: true, .. }) | Err(ChunkError::Failed(_))) {
+        if let Some(t) = remove(id) {
+            t.lock().unwrap().discard();
+        }
+    }
+    result
+}
+
+fn append(t: &mut Transfer, offset: u64, data: &[u8]) -> Result<Written, ChunkError> {
+    if t.direction != Direction::Upload {
+        return Err(ChunkError::Rejected(format!("{} is not an upload", t.id)));
+    }
+    if offset != t.offset {
+        return Err(ChunkError::OutOfOrder { expected: t.offset });
+    }
+    if offset + data.len() as u64 > t.size {
+        return Err(ChunkError::Rejected(format!("chunk runs past the declared size of {} bytes", t.size)));
+    }
+    let part = part_path(&t.id);
+    if t.part.is_none() {
+        let f = OpenOptions::new().create(true).write(true).truncate(true).open(&part)
+            .map_err(|e| ChunkError::Failed(format!("upload error: {}", e)))?;
+        t.part = Some(f);
+    }
+    let file = t.part.as_mut().unwrap();
+    if let Err(e) = file.write_all(data) {
+        // Roll back to the last whole chunk so the client can resend it
+        return match file.set_len(offset).and_then(|_| file.seek(SeekFrom::Start(offset))) {
+            Ok(_) => Err(ChunkError::Rejected(format!("upload error: {}", e))),
+            Err(_) => Err(ChunkError::Failed(format!("upload error: {}", e))),
+        };
+    }
+    t.hasher.update(data);
+    t.offset += data.len() as u64;
+    t.touched = Instant::now();
+    if t.offset < t.size {
+        return Ok(Written { progress: t.progress_due(), done: false, info: t.info() });
+    }
+
+    // Last chunk: verify and move into place
+    t.part = None;
+    let got = format!("{:x}", std::mem::take(&mut t.hasher).finalize());
+    if got != t.sha256 {
+        return Err(ChunkError::Failed(format!("checksum mismatch: expected {}, got {}", t.sha256, got)));
+    }
+    let target = sandbox::check(&t.path, Op::Write).map_err(ChunkError::Failed)?;
+    if !t.overwrite && fs::symlink_metadata(&target).is_ok() {
+        return Err(ChunkError::Failed(format!("{} already exists", target.display())));
+    }
+    let moved = match fs::rename(&part, &target) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => fs::copy(&part, &target).map(drop),
+        r => r,
+    };
+    moved.map_err(|e| ChunkError::Failed(format!("upload error: {}", e)))?;
+    Ok(Written { progress: false, done: true, info: t.info() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    #[test]
+    fn frames() {
+        let id = Uuid::new_v4();
+        let frame = encode_frame(FRAME_DOWNLOAD, &id, 0x0102_0304_0506_0708, b"payload");
+        assert_eq!(frame.len(), HEADER_LEN + 7);
+        assert_eq!(frame[0], FRAME_DOWNLOAD);
+        assert_eq!(&frame[17..25], &[1, 2, 3, 4, 5, 6, 7, 8], "offset is big-endian");
+        let f = decode_frame(&frame).unwrap();
+        assert_eq!((f.kind, f.id, f.offset, f.data), (FRAME_DOWNLOAD, id, 0x0102_0304_0506_0708, &b"payload"[..]));
+
+        let empty = encode_frame(FRAME_UPLOAD, &id, 0, &[]);
+        assert!(decode_frame(&empty).unwrap().data.is_empty());
+        assert!(decode_frame(&empty[..HEADER_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn upload_resumes_in_order() {
+        let dir = scratch();
+        let data: Vec<u8> = (0..MIN_CHUNK * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let target = dir.join("up.bin");
+        let info = start_upload(target.to_str().unwrap(), data.len() as u64, &sha256(&data), None, false).unwrap();
+        let id = parse_id(&info.id).unwrap();
+        let (a, rest) = data.split_at(MIN_CHUNK);
+        let (b, c) = rest.split_at(MIN_CHUNK);
+
+        assert!(matches!(write_chunk(&id, 0, a), Ok(Written { done: false, .. })));
+        // A resent or skipped chunk is told where to resume
+        assert!(matches!(write_chunk(&id, 0, a), Err(ChunkError::OutOfOrder { expected }) if expected == MIN_CHUNK as u64));
+        assert!(matches!(write_chunk(&id, (MIN_CHUNK * 2) as u64, c), Err(ChunkError::OutOfOrder { .. })));
+        assert_eq!(status(&id).unwrap().offset, MIN_CHUNK as u64);
+        // Too long for the declared size: refused, transfer kept
+        let long = vec![0u8; MIN_CHUNK * 2];
+        assert!(matches!(write_chunk(&id, MIN_CHUNK as u64, &long), Err(ChunkError::Rejected(_))));
+
+        assert!(matches!(write_chunk(&id, MIN_CHUNK as u64, b), Ok(Written { done: false, .. })));
+        assert!(matches!(write_chunk(&id, (MIN_CHUNK * 2) as u64, c), Ok(Written { done: true, .. })));
+        assert_eq!(fs::read(&target).unwrap(), data);
+        assert!(status(&id).is_err(), "finished transfers are dropped");
+        assert!(!part_path(&id).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn upload_checksum_mismatch() {
+        let dir = scratch();
+        let target = dir.join("bad.bin");
+        let info = start_upload(target.to_str().unwrap(), 3, &sha256(b"abc"), None, false).unwrap();
+        let id = parse_id(&info.id).unwrap();
+        assert!(matches!(write_chunk(&id, 0, b"abd"), Err(ChunkError::Failed(e)) if e.starts_with("checksum mismatch")));
+        assert!(!target.exists());
+        assert!(!part_path(&id).exists());
+        assert!(start_upload(target.to_str().unwrap(), 3, "xyz", None, false).is_err(), "bad sha256");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn download_resumes_from_offset() {
+        let dir = scratch();
+        let data: Vec<u8> = (0..MIN_CHUNK * 2 + 10).map(|i| (i % 7) as u8).collect();
+        let source = dir.join("down.bin");
+        fs::write(&source, &data).unwrap();
+        let info = start_download(source.to_str().unwrap(), None).unwrap();
+        assert_eq!((info.size, info.sha256.as_str(), info.chunk_size), (data.len() as u64, sha256(&data).as_str(), MIN_CHUNK));
+        let id = parse_id(&info.id).unwrap();
+
+        let (_, first) = claim_download(&id, None).unwrap();
+        let chunk = read_chunk(&id, first).unwrap().unwrap();
+        assert_eq!((chunk.offset, chunk.data.len(), chunk.done), (0, MIN_CHUNK, false));
+        // Reconnect and resume part way; the older stream stops
+        let (info, second) = claim_download(&id, Some(MIN_CHUNK as u64 + 5)).unwrap();
+        assert_eq!(info.offset, MIN_CHUNK as u64 + 5);
+        assert!(read_chunk(&id, first).unwrap().is_none());
+        let chunk = read_chunk(&id, second).unwrap().unwrap();
+        assert_eq!(chunk.offset, MIN_CHUNK as u64 + 5);
+        assert_eq!(chunk.data, data[MIN_CHUNK + 5..MIN_CHUNK * 2 + 5]);
+        let last = read_chunk(&id, second).unwrap().unwrap();
+        assert_eq!((last.offset, last.data.len(), last.done), ((MIN_CHUNK * 2 + 5) as u64, 5, true));
+        assert!(read_chunk(&id, second).unwrap().is_none(), "removed once done");
+
+        let info = start_download(source.to_str().unwrap(), None).unwrap();
+        let id = parse_id(&info.id).unwrap();
+        assert!(claim_download(&id, Some(data.len() as u64 + 1)).is_err(), "past the end");
+        cancel(&id).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+/// Server-managed trash: `fs_delete` moves items here instead of removing
+/// them. Each item lives in `<dir>/<id>/` next to a `meta.json`.
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Metadata of a move in progress, renamed to `meta.json` once it is done
+const PENDING_META: &str = "meta.json.part";
+
+fn default_retention_days() -> u64 { 30 }
+
+/// `[trash]` section of `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrashConfig {
+    pub dir: Option<String>,
+    /// Items older than this are removed for good
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { dir: None, retention_days: default_retention_days() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub name: String,
+    pub original_path: String,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub size: u64,
+    pub deleted_at: u64,
+    /// Address of the client that deleted it
+    pub client: String,
+}
+
+struct Settings {
+    dir: PathBuf,
+    retention: Duration,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+// Serializes moves in and out of the trash
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub fn init(cfg: TrashConfig) {
+    let dir = cfg.dir.map(|d| crate::config::expand_home(&d))
+        .unwrap_or_else(|| crate::config::config_dir().join("trash"));
+    let _ = SETTINGS.set(Settings { dir, retention: Duration::from_secs(cfg.retention_days * 86400) });
+    let _guard = LOCK.lock().unwrap();
+    purge_expired();
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        dir: crate::config::config_dir().join("trash"),
+        retention: Duration::from_secs(default_retention_days() * 86400),
+    })
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn item_dir(id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("invalid trash id: {}", id));
+    }
+    Ok(settings().dir.join(id))
+}
+
+/// Total size of a file or directory tree (symlinks not followed)
+fn tree_size(p: &Path) -> u64 {
+    match fs::symlink_metadata(p) {
+        Ok(m) if m.is_dir() => fs::read_dir(p)
+            .map(|entries| entries.flatten().map(|e| tree_size(&e.path())).sum())
+            .unwrap_or(0),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }
+}
+
+fn copy_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)
+    } else if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, meta.permissions())
+    } else {
+        fs::copy(from, to).map(drop)
+    }
+}
+
+fn remove_tree(p: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(p)?.is_dir() {
+        fs::remove_dir_all(p)
+    } else {
+        fs::remove_file(p)
+    }
+}
+
+/// How far a failed move got
+#[derive(Debug)]
+enum MoveError {
+    /// Nothing moved: `from` is as it was and no copy is left at `to`
+    NotMoved(std::io::Error),
+    /// `to` holds a complete copy, but only part of `from` could be removed
+    Partial(std::io::Error),
+}
+
+impl std::fmt::Display for MoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::NotMoved(e) => write!(f, "{}", e),
+            MoveError::Partial(e) => write!(f, "copied, but the original could not be fully removed: {}", e),
+        }
+    }
+}
+
+/// Rename, falling back to copy + delete across filesystems
+fn move_tree(from: &Path, to: &Path) -> Result<(), MoveError> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_then_remove(from, to),
+        r => r.map_err(MoveError::NotMoved),
+    }
+}
+
+fn copy_then_remove(from: &Path, to: &Path) -> Result<(), MoveError> {
+    if let Err(e) = copy_tree(from, to) {
+        let _ = remove_tree(to);
+        return Err(MoveError::NotMoved(e));
+    }
+    // From here on `to` is the only complete copy and must be kept
+    remove_tree(from).map_err(MoveError::Partial)
+}
+
+fn read_meta(dir: &Path) -> Option<TrashItem> {
+    let s = fs::read_to_string(dir.join("meta.json")).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+/// Finish or undo a move that was cut short: with the item in place its
+/// pending metadata is committed, without it the entry is dropped
+fn recover(dir: &Path) -> Option<TrashItem> {
+    let pending = dir.join(PENDING_META);
+    if !pending.exists() {
+        return None;
+    }
+    if fs::symlink_metadata(dir.join("item")).is_ok() {
+        fs::rename(&pending, dir.join("meta.json")).ok()?;
+        read_meta(dir)
+    } else {
+        let _ = fs::remove_dir_all(dir);
+        None
+    }
+}
+
+/// Drop items past the retention period and recover interrupted moves.
+/// Caller holds LOCK.
+fn purge_expired() {
+    let s = settings();
+    let cutoff = now_secs().saturating_sub(s.retention.as_secs());
+    let Ok(entries) = fs::read_dir(&s.dir) else { return };
+    for entry in entries.flatten() {
+        let expired = match read_meta(&entry.path()).or_else(|| recover(&entry.path())) {
+            Some(item) => item.deleted_at < cutoff,
+            None => false, // not ours
+        };
+        if expired {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// Move `path` into the trash
+pub fn move_to_trash(path: &str, client: &str) -> Result<TrashItem, String> {
+    let p = sandbox::check_entry(&expand_path(path), Op::Delete)?;
+    let meta = fs::symlink_metadata(&p).map_err(|e| format!("delete error: {}", e))?;
+    let _guard = LOCK.lock().unwrap();
+    purge_expired();
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let dir = item_dir(&id)?;
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create trash entry: {}", e))?;
+    let item = TrashItem {
+        id,
+        name: p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        original_path: p.to_string_lossy().to_string(),
+        file_type: if meta.is_dir() { "dir" } else if meta.file_type().is_symlink() { "symlink" } else { "file" }.into(),
+        size: tree_size(&p),
+        deleted_at: now_secs(),
+        client: client.to_string(),
+    };
+    // Metadata goes down before the move and is renamed into place after
+    // it, so a failure at any step leaves the item where `recover` or the
+    // caller can find it
+    let pending = dir.join(PENDING_META);
+    if let Err(e) = fs::write(&pending, serde_json::to_string_pretty(&item).unwrap()) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(format!("cannot write trash metadata: {}", e));
+    }
+    let moved = move_tree(&p, &dir.join("item"));
+    if let Err(MoveError::NotMoved(e)) = moved {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(format!("delete error: {}", e));
+    }
+    if let Err(e) = fs::rename(&pending, dir.join("meta.json")) {
+        // Only put it back when the original was left untouched
+        if moved.is_ok() && move_tree(&dir.join("item"), &p).is_ok() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        return Err(format!("cannot write trash metadata: {}", e));
+    }
+    if let Err(e) = moved {
+        return Err(format!("delete error: {} (a full copy is in the trash as {})", e, item.id));
+    }
+    Ok(item)
+}
+
+/// Trashed items, most recently deleted first
+pub fn list() -> Result<Vec<TrashItem>, String> {
+    let _guard = LOCK.lock().unwrap();
+    purge_expired();
+    let mut items: Vec<TrashItem> = match fs::read_dir(&settings().dir) {
+        Ok(entries) => entries.flatten().filter_map(|e| read_meta(&e.path())).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("cannot read trash: {}", e)),
+    };
+    items.sort_by_key(|i| std::cmp::Reverse(i.deleted_at));
+    Ok(items)
+}
+
+/// Put an item back at its original path, or at `to`; never overwrites
+pub fn restore(id: &str, to: Option<&str>) -> Result<String, String> {
+    let _guard = LOCK.lock().unwrap();
+    let dir = item_dir(id)?;
+    let item = read_meta(&dir).ok_or_else(|| format!("no such trash item: {}", id))?;
+    let target = sandbox::check_entry(&expand_path(to.unwrap_or(&item.original_path)), Op::Write)?;
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(format!("{} already exists; restore it somewhere else with `to`", target.display()));
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("restore error: {}", e))?;
+    }
+    // A partial move still put a full copy back; the rest of the entry goes below
+    if let Err(MoveError::NotMoved(e)) = move_tree(&dir.join("item"), &target) {
+        return Err(format!("restore error: {}", e));
+    }
+    let _ = fs::remove_dir_all(&dir);
+    Ok(target.to_string_lossy().to_string())
+}
+
+/// Permanently remove the given items, or everything. Returns how many went.
+pub fn empty(ids: Option<&[String]>) -> Result<usize, String> {
+    let _guard = LOCK.lock().unwrap();
+    let dirs: Vec<PathBuf> = match ids {
+        Some(ids) => ids.iter().map(|id| item_dir(id)).collect::<Result<_, _>>()?,
+        None => match fs::read_dir(&settings().dir) {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(_) => Vec::new(),
+        },
+    };
+    let mut removed = 0;
+    for dir in dirs {
+        if read_meta(&dir).is_some() {
+            fs::remove_dir_all(&dir).map_err(|e| format!("cannot empty trash: {}", e))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn init_temp() {
+        let _ = SETTINGS.set(Settings {
+            dir: std::env::temp_dir().join("tmux-mobile-test-trash"),
+            retention: Duration::from_secs(default_retention_days() * 86400),
+        });
+    }
+
+    #[test]
+    fn trash_and_restore() {
+        init_temp();
+        let dir = scratch();
+        let file = dir.join("notes.txt");
+        fs::write(&file, "hello").unwrap();
+        let item = move_to_trash(file.to_str().unwrap(), "test").unwrap();
+        assert!(!file.exists());
+        assert_eq!((item.name.as_str(), item.file_type.as_str(), item.size), ("notes.txt", "file", 5));
+        let entry = item_dir(&item.id).unwrap();
+        assert!(entry.join("meta.json").exists() && !entry.join(PENDING_META).exists());
+        assert!(list().unwrap().iter().any(|i| i.id == item.id));
+
+        assert_eq!(restore(&item.id, None).unwrap(), file.to_string_lossy());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello");
+        assert!(!entry.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_moves_are_recovered() {
+        init_temp();
+        let item = |id: &str| TrashItem {
+            id: id.to_string(),
+            name: "x".into(),
+            original_path: "/nowhere/x".into(),
+            file_type: "file".into(),
+            size: 1,
+            deleted_at: now_secs(),
+            client: "test".into(),
+        };
+        // Moved, but the metadata was never committed
+        let moved = uuid::Uuid::new_v4().to_string();
+        let dir = item_dir(&moved).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("item"), "x").unwrap();
+        fs::write(dir.join(PENDING_META), serde_json::to_string(&item(&moved)).unwrap()).unwrap();
+        // Metadata written, but the move never happened
+        let unmoved = uuid::Uuid::new_v4().to_string();
+        let other = item_dir(&unmoved).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join(PENDING_META), serde_json::to_string(&item(&unmoved)).unwrap()).unwrap();
+
+        let ids: Vec<String> = list().unwrap().into_iter().map(|i| i.id).collect();
+        assert!(ids.contains(&moved), "committed and listed");
+        assert!(dir.join("meta.json").exists());
+        assert!(!ids.contains(&unmoved));
+        assert!(!other.exists(), "empty entry dropped");
+        empty(Some(&[moved])).unwrap();
+    }
+
+    #[test]
+    fn cross_device_moves_keep_the_only_full_copy() {
+        let dir = scratch();
+        let from = dir.join("tree");
+        fs::create_dir_all(from.join("locked")).unwrap();
+        fs::write(from.join("a"), "a").unwrap();
+        fs::write(from.join("locked/f"), "f").unwrap();
+        fs::set_permissions(from.join("locked"), fs::Permissions::from_mode(0o555)).unwrap();
+        // Root can delete from it anyway, and then the move just succeeds
+        let removable = fs::write(from.join("locked/probe"), "").is_ok();
+        let _ = fs::remove_file(from.join("locked/probe"));
+
+        let to = dir.join("copy");
+        let result = copy_then_remove(&from, &to);
+        assert_eq!(fs::read_to_string(to.join("locked/f")).unwrap(), "f");
+        assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "a");
+        if removable {
+            assert!(result.is_ok() && !from.exists());
+        } else {
+            assert!(matches!(result, Err(MoveError::Partial(_))), "{:?}", result);
+            assert!(from.join("locked/f").exists());
+        }
+        for locked in [from.join("locked"), to.join("locked")] {
+            let _ = fs::set_permissions(locked, fs::Permissions::from_mode(0o755));
+        }
+
+        // A copy that fails leaves the original alone and nothing behind
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("secret"), "s").unwrap();
+        fs::set_permissions(src.join("secret"), fs::Permissions::from_mode(0o000)).unwrap();
+        let readable = fs::read(src.join("secret")).is_ok();
+        let result = copy_then_remove(&src, &dir.join("dst"));
+        if !readable {
+            assert!(matches!(result, Err(MoveError::NotMoved(_))), "{:?}", result);
+            assert!(src.join("secret").exists() && !dir.join("dst").exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
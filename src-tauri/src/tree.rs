@@ -0,0 +1,273 @@
+/// Nested listing of a directory down to a given depth, skipping what
+/// `.gitignore` files and extra exclude globs leave out
+use crate::fs::expand_path;
+use crate::sandbox::{self, Op};
+use ignore::overrides::OverrideBuilder;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const DEFAULT_DEPTH: usize = 3;
+const MAX_DEPTH: usize = 10;
+const DEFAULT_ENTRIES: usize = 2000;
+const MAX_ENTRIES: usize = 20_000;
+
+/// `fs_tree` params
+#[derive(Debug, Deserialize)]
+pub struct TreeRequest {
+    pub path: String,
+    pub depth: Option<usize>,
+    #[serde(default)]
+    pub hidden: bool,
+    /// Apply `.gitignore`, `.ignore` and git exclude files (default true)
+    pub gitignore: Option<bool>,
+    /// Globs of further names or relative paths to leave out
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: &'static str,
+    /// Files only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    /// Directories within the depth; None for those at the limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<Node>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tree {
+    pub root: Node,
+    /// Entries below the root
+    pub count: usize,
+    /// Stopped at `max_entries`
+    pub truncated: bool,
+}
+
+/// A validated tree walk, ready to run
+pub struct TreeWalk {
+    root: PathBuf,
+    depth: usize,
+    hidden: bool,
+    gitignore: bool,
+    exclude: ignore::overrides::Override,
+    max_entries: usize,
+}
+
+pub fn prepare(req: TreeRequest) -> Result<TreeWalk, String> {
+    let root = sandbox::check(&expand_path(&req.path), Op::Read)?;
+    if !root.is_dir() {
+        return Err(format!("not a directory: {}", root.display()));
+    }
+    let mut overrides = OverrideBuilder::new(&root);
+    for glob in &req.exclude {
+        overrides.add(&format!("!{}", glob)).map_err(|e| format!("invalid glob: {}", e))?;
+    }
+    Ok(TreeWalk {
+        root,
+        depth: req.depth.unwrap_or(DEFAULT_DEPTH).clamp(1, MAX_DEPTH),
+        hidden: req.hidden,
+        gitignore: req.gitignore.unwrap_or(true),
+        exclude: overrides.build().map_err(|e| format!("invalid glob: {}", e))?,
+        max_entries: req.max_entries.unwrap_or(DEFAULT_ENTRIES).clamp(1, MAX_ENTRIES),
+    })
+}
+
+/// Dirs first, then by name, like `fs_list`
+fn sort(nodes: &mut [Node]) {
+    nodes.sort_by(|a, b| {
+        (a.file_type != "dir").cmp(&(b.file_type != "dir"))
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+}
+
+impl TreeWalk {
+    /// Blocking
+    pub fn run(self) -> Tree {
+        let walker = ignore::WalkBuilder::new(&self.root)
+            .hidden(!self.hidden)
+            .git_ignore(self.gitignore)
+            .git_global(self.gitignore)
+            .git_exclude(self.gitignore)
+            .ignore(self.gitignore)
+            .parents(self.gitignore)
+            .require_git(false)
+            .overrides(self.exclude)
+            .max_depth(Some(self.depth))
+            .filter_entry(|e| sandbox::readable_resolved(e.path()))
+            .build();
+        // Directories still being filled, the root at the bottom; the walk
+        // is depth-first, so a node is complete once the walk leaves it
+        let mut open: Vec<(usize, Node)> = Vec::new();
+        let (mut count, mut truncated) = (0, false);
+        for entry in walker.flatten() {
+            let depth = entry.depth();
+            if depth > 0 {
+                if count == self.max_entries {
+                    truncated = true;
+                    break;
+                }
+                count += 1;
+            }
+            while open.last().is_some_and(|(d, _)| *d >= depth) {
+                close(&mut open);
+            }
+            let path = entry.path();
+            let meta = entry.metadata().ok();
+            let (file_type, link_target) = if entry.path_is_symlink() {
+                ("symlink", std::fs::read_link(path).ok().map(|t| t.to_string_lossy().to_string()))
+            } else if meta.as_ref().is_some_and(|m| m.is_dir()) {
+                ("dir", None)
+            } else {
+                ("file", None)
+            };
+            let node = Node {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: path.to_string_lossy().to_string(),
+                file_type,
+                size: meta.filter(|m| m.is_file()).map(|m| m.len()),
+                link_target,
+                children: (file_type == "dir" && depth < self.depth).then(Vec::new),
+            };
+            if file_type == "dir" {
+                open.push((depth, node));
+            } else if let Some((_, parent)) = open.last_mut() {
+                parent.children.get_or_insert_with(Vec::new).push(node);
+            }
+        }
+        while open.len() > 1 {
+            close(&mut open);
+        }
+        let mut root = open.pop().map(|(_, n)| n).unwrap_or_else(|| Node {
+            name: String::new(),
+            path: self.root.to_string_lossy().to_string(),
+            file_type: "dir",
+            size: None,
+            link_target: None,
+            children: Some(Vec::new()),
+        });
+        if let Some(children) = root.children.as_mut() {
+            sort(children);
+        }
+        Tree { root, count, truncated }
+    }
+}
+
+/// Finish the innermost open directory and add it to its parent
+fn close(open: &mut Vec<(usize, Node)>) {
+    if open.len() < 2 {
+        return;
+    }
+    let (_, mut node) = open.pop().unwrap();
+    if let Some(children) = node.children.as_mut() {
+        sort(children);
+    }
+    if let Some((_, parent)) = open.last_mut() {
+        parent.children.get_or_insert_with(Vec::new).push(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tmux-mobile-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn request(path: &std::path::Path) -> TreeRequest {
+        TreeRequest {
+            path: path.to_string_lossy().into_owned(),
+            depth: None,
+            hidden: false,
+            gitignore: None,
+            exclude: Vec::new(),
+            max_entries: None,
+        }
+    }
+
+    /// `name` for leaves, `name/` for directories at the depth limit and
+    /// `name[...]` for expanded ones, in order
+    fn shape(node: &Node) -> String {
+        match &node.children {
+            Some(children) => format!("{}[{}]", node.name, children.iter().map(shape).collect::<Vec<_>>().join(" ")),
+            None if node.file_type == "dir" => format!("{}/", node.name),
+            None => node.name.clone(),
+        }
+    }
+
+    fn project() -> PathBuf {
+        let dir = scratch();
+        fs::create_dir_all(dir.join("src/net/http")).unwrap();
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::create_dir_all(dir.join("Docs")).unwrap();
+        for f in ["README", "b.log", ".hidden", ".env", ".gitignore", "src/main.rs", "src/net/mod.rs", "src/net/http/get.rs", "Docs/a.md"] {
+            fs::write(dir.join(f), "x").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn depth_limits_expansion() {
+        let dir = project();
+        let tree = prepare(TreeRequest { depth: Some(2), ..request(&dir) }).unwrap().run();
+        assert_eq!(
+            shape(&tree.root).split_once('[').unwrap().1,
+            "Docs[a.md] src[net/ main.rs] b.log README]",
+        );
+        assert_eq!(tree.count, 7);
+        assert!(!tree.truncated);
+        let deep = prepare(TreeRequest { depth: Some(5), ..request(&dir) }).unwrap().run();
+        assert!(shape(&deep.root).contains("net[http[get.rs] mod.rs]"), "{}", shape(&deep.root));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn filters_and_excludes() {
+        let dir = project();
+        type Case = (fn(TreeRequest) -> TreeRequest, &'static [&'static str], &'static [&'static str]);
+        let cases: &[Case] = &[
+            (|r| r, &["README", "src"], &["target", ".hidden", ".gitignore"]),
+            (|r| TreeRequest { hidden: true, ..r }, &[".hidden", ".gitignore"], &["target", ".env"]),
+            (|r| TreeRequest { gitignore: Some(false), ..r }, &["target[debug[]]"], &[".hidden"]),
+            (|r| TreeRequest { exclude: vec!["*.log".into(), "src/net".into()], ..r }, &["src[main.rs]"], &["b.log", "net"]),
+        ];
+        for (i, (req, present, absent)) in cases.iter().enumerate() {
+            let tree = prepare(req(request(&dir))).unwrap().run();
+            let shape = shape(&tree.root);
+            for p in *present {
+                assert!(shape.contains(p), "case {}: {} missing from {}", i, p, shape);
+            }
+            for a in *absent {
+                assert!(!shape.contains(a), "case {}: {} in {}", i, a, shape);
+            }
+        }
+        let err = prepare(TreeRequest { exclude: vec!["a{".into()], ..request(&dir) }).err().unwrap();
+        assert!(err.starts_with("invalid glob"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stops_at_max_entries() {
+        let dir = project();
+        let tree = prepare(TreeRequest { max_entries: Some(3), depth: Some(10), ..request(&dir) }).unwrap().run();
+        assert_eq!(tree.count, 3);
+        assert!(tree.truncated);
+        fn count(n: &Node) -> usize {
+            n.children.iter().flatten().map(|c| 1 + count(c)).sum()
+        }
+        assert_eq!(count(&tree.root), 3, "{}", shape(&tree.root));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+/// Regex output triggers: run an action when new pane output matches a pattern
+use crate::tmux;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAX_LOG_ENTRIES: usize = 500;
+
+fn default_cooldown() -> u64 { 30 }
+fn default_max_per_hour() -> u32 { 20 }
+
+/// Trigger definition, as written in `config.toml` or passed to `add_trigger`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerSpec {
+    #[serde(default)]
+    pub id: String,
+    pub target: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub action: TriggerAction,
+    /// Minimum seconds between two firings
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+    /// Maximum firings in any sliding one-hour window
+    #[serde(default = "default_max_per_hour")]
+    pub max_per_hour: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// Only push a `trigger_fired` notification to connected clients
+    #[default]
+    Notify,
+    SendKeys { keys: String },
+    SendCommand { command: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TriggerInfo {
+    #[serde(flatten)]
+    pub spec: TriggerSpec,
+    pub source: &'static str, // "config" or "runtime"
+    pub fire_count: u64,
+    pub suppressed: u64,
+    pub last_fired: Option<u64>,
+}
+
+/// One entry in the firing log
+#[derive(Debug, Clone, Serialize)]
+pub struct Firing {
+    pub trigger_id: String,
+    pub target: String,
+    pub line: String,
+    pub action: TriggerAction,
+    pub time: u64, // unix timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Trigger {
+    spec: TriggerSpec,
+    regex: Regex,
+    source: &'static str,
+    recent: VecDeque<Instant>, // firings within the last hour
+    fire_count: u64,
+    suppressed: u64,
+    last_fired: Option<u64>,
+}
+
+impl Trigger {
+    fn new(mut spec: TriggerSpec, source: &'static str) -> Result<Self, String> {
+        if spec.target.is_empty() {
+            return Err("trigger target must not be empty".into());
+        }
+        let regex = Regex::new(&spec.pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+        if spec.id.is_empty() {
+            spec.id = uuid::Uuid::new_v4().to_string()[..8].to_string();
+        }
+        Ok(Self { spec, regex, source, recent: VecDeque::new(), fire_count: 0, suppressed: 0, last_fired: None })
+    }
+
+    fn info(&self) -> TriggerInfo {
+        TriggerInfo {
+            spec: self.spec.clone(),
+            source: self.source,
+            fire_count: self.fire_count,
+            suppressed: self.suppressed,
+            last_fired: self.last_fired,
+        }
+    }
+
+    /// Check cooldown and hourly budget; records the firing if allowed
+    fn try_fire(&mut self, now: Instant) -> bool {
+        while self.recent.front().is_some_and(|t| now.duration_since(*t) > Duration::from_secs(3600)) {
+            self.recent.pop_front();
+        }
+        let cooling = self.recent.back()
+            .is_some_and(|t| now.duration_since(*t) < Duration::from_secs(self.spec.cooldown_secs));
+        if cooling || self.recent.len() >= self.spec.max_per_hour as usize {
+            self.suppressed += 1;
+            return false;
+        }
+        self.recent.push_back(now);
+        self.fire_count += 1;
+        self.last_fired = Some(unix_now());
+        true
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    triggers: Vec<Trigger>,
+    log: VecDeque<Firing>,
+    last_capture: HashMap<String, String>, // target -> previous plain capture
+}
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+fn registry() -> &'static Mutex<Registry> {
+    REGISTRY.get_or_init(|| Mutex::new(Registry::default()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Load triggers from config. Invalid entries are reported and skipped.
+pub fn init(specs: Vec<TriggerSpec>) {
+    let mut reg = registry().lock().unwrap();
+    reg.triggers.retain(|t| t.source != "config");
+    for spec in specs {
+        if reg.triggers.iter().any(|t| t.spec.id == spec.id) {
+            eprintln!("⚠️  Skipping duplicate trigger from config: {}", spec.id);
+            continue;
+        }
+        match Trigger::new(spec, "config") {
+            Ok(t) => reg.triggers.push(t),
+            Err(e) => eprintln!("⚠️  Skipping trigger from config: {}", e),
+        }
+    }
+}
+
+pub fn add(spec: TriggerSpec) -> Result<TriggerInfo, String> {
+    let trigger = Trigger::new(spec, "runtime")?;
+    let mut reg = registry().lock().unwrap();
+    if reg.triggers.iter().any(|t| t.spec.id == trigger.spec.id) {
+        return Err(format!("trigger already exists: {}", trigger.spec.id));
+    }
+    let info = trigger.info();
+    reg.triggers.push(trigger);
+    Ok(info)
+}
+
+pub fn remove(id: &str) -> Result<(), String> {
+    let mut reg = registry().lock().unwrap();
+    let before = reg.triggers.len();
+    reg.triggers.retain(|t| t.spec.id != id);
+    if reg.triggers.len() == before {
+        return Err(format!("no such trigger: {}", id));
+    }
+    let targets: Vec<String> = reg.triggers.iter().map(|t| t.spec.target.clone()).collect();
+    reg.last_capture.retain(|k, _| targets.contains(k));
+    Ok(())
+}
+
+pub fn list() -> Vec<TriggerInfo> {
+    registry().lock().unwrap().triggers.iter().map(|t| t.info()).collect()
+}
+
+/// Most recent firings, newest first
+pub fn log(limit: usize) -> Vec<Firing> {
+    registry().lock().unwrap().log.iter().rev().take(limit).cloned().collect()
+}
+
+/// Distinct pane targets that have at least one trigger
+pub fn targets() -> Vec<String> {
+    let reg = registry().lock().unwrap();
+    let mut targets: Vec<String> = reg.triggers.iter().map(|t| t.spec.target.clone()).collect();
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+fn run_action(action: &TriggerAction, target: &str) -> Result<(), String> {
+    match action {
+        TriggerAction::Notify => Ok(()),
+        TriggerAction::SendKeys { keys } => tmux::send_keys(target, keys, false),
+        TriggerAction::SendCommand { command } => tmux::send_command(target, command),
+    }
+}
+
+/// Feed a fresh (ANSI-stripped) capture of `target`. Matches only new output
+/// since the previous capture, runs the actions and returns the firings.
+/// The first capture of a target only sets the baseline.
+pub fn process(target: &str, content: &str) -> Vec<Firing> {
+    let mut fired = Vec::new();
+    {
+        let mut reg = registry().lock().unwrap();
+        let prev = reg.last_capture.insert(target.to_string(), content.to_string());
+        let lines = match prev {
+            Some(prev) => tmux::new_lines(&prev, content),
+            None => return fired,
+        };
+        if lines.is_empty() {
+            return fired;
+        }
+        let now = Instant::now();
+        for trigger in reg.triggers.iter_mut().filter(|t| t.spec.target == target) {
+            // At most one firing per trigger per batch of new output
+            let Some(line) = lines.iter().find(|l| trigger.regex.is_match(l)) else { continue };
+            if !trigger.try_fire(now) {
+                continue;
+            }
+            fired.push(Firing {
+                trigger_id: trigger.spec.id.clone(),
+                target: target.to_string(),
+                line: line.clone(),
+                action: trigger.spec.action.clone(),
+                time: unix_now(),
+                error: None,
+            });
+        }
+    }
+
+    // Run actions outside the lock, they shell out to tmux
+    for f in fired.iter_mut() {
+        f.error = run_action(&f.action, &f.target).err();
+    }
+
+    let mut reg = registry().lock().unwrap();
+    for f in &fired {
+        if reg.log.len() >= MAX_LOG_ENTRIES {
+            reg.log.pop_front();
+        }
+        reg.log.push_back(f.clone());
+    }
+    fired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(target: &str, pattern: &str) -> TriggerSpec {
+        TriggerSpec {
+            id: String::new(),
+            target: target.into(),
+            pattern: pattern.into(),
+            action: TriggerAction::Notify,
+            cooldown_secs: 0,
+            max_per_hour: 3,
+        }
+    }
+
+    #[test]
+    fn new_validates_spec() {
+        assert!(Trigger::new(spec("", "x"), "runtime").is_err(), "empty target accepted");
+        let err = Trigger::new(spec("s:0.0", "(unclosed"), "runtime").err().unwrap();
+        assert!(err.starts_with("invalid pattern"), "{}", err);
+        let t = Trigger::new(spec("s:0.0", "ok"), "runtime").unwrap();
+        assert_eq!(t.spec.id.len(), 8, "generated id");
+    }
+
+    #[test]
+    fn cooldown_suppresses() {
+        let mut s = spec("s:0.0", "x");
+        s.cooldown_secs = 30;
+        let mut t = Trigger::new(s, "runtime").unwrap();
+        let now = Instant::now();
+        assert!(t.try_fire(now));
+        assert!(!t.try_fire(now + Duration::from_secs(10)));
+        assert!(t.try_fire(now + Duration::from_secs(31)));
+        assert_eq!((t.fire_count, t.suppressed), (2, 1));
+    }
+
+    #[test]
+    fn hourly_budget_slides() {
+        let mut t = Trigger::new(spec("s:0.0", "x"), "runtime").unwrap();
+        let now = Instant::now();
+        for i in 0..3 {
+            assert!(t.try_fire(now + Duration::from_secs(i)), "firing {}", i);
+        }
+        assert!(!t.try_fire(now + Duration::from_secs(60)), "over max_per_hour");
+        // The first firing leaves the window after an hour
+        assert!(t.try_fire(now + Duration::from_secs(3601)));
+    }
+
+    #[test]
+    fn action_from_toml() {
+        let s: TriggerSpec = toml::from_str(r#"
+            target = "dev:0.1"
+            pattern = "error"
+            action = { type = "send_command", command = "make" }
+        "#).unwrap();
+        assert!(matches!(s.action, TriggerAction::SendCommand { ref command } if command == "make"));
+        assert_eq!((s.cooldown_secs, s.max_per_hour), (30, 20), "defaults");
+        let s: TriggerSpec = toml::from_str("target = \"a\"\npattern = \"b\"").unwrap();
+        assert!(matches!(s.action, TriggerAction::Notify));
+    }
+
+    #[test]
+    fn process_matches_new_lines_only() {
+        let target = "_test_triggers:0.0";
+        add(TriggerSpec { id: "t-process".into(), ..spec(target, "^FAIL") }).unwrap();
+        assert!(process(target, "FAIL old\n$").is_empty(), "first capture is the baseline");
+        assert!(process(target, "FAIL old\n$").is_empty(), "nothing new");
+        let fired = process(target, "FAIL old\n$ make\nFAIL new\n$");
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].line, "FAIL new");
+        assert!(fired[0].error.is_none());
+        remove("t-process").unwrap();
+        assert!(remove("t-process").is_err());
+    }
+
+    #[test]
+    fn init_skips_duplicate_ids() {
+        let target = "test-init:0.0";
+        init(vec![
+            TriggerSpec { id: "t-dup".into(), ..spec(target, "first") },
+            TriggerSpec { id: "t-dup".into(), ..spec(target, "second") },
+            spec(target, "generated"),
+        ]);
+        let ours: Vec<_> = list().into_iter().filter(|t| t.spec.target == target).collect();
+        assert_eq!(ours.len(), 2);
+        let dup = ours.iter().find(|t| t.spec.id == "t-dup").unwrap();
+        assert_eq!(dup.spec.pattern, "first", "the first definition wins");
+        init(Vec::new());
+        assert!(!list().iter().any(|t| t.spec.target == target), "config triggers replaced");
+    }
+}
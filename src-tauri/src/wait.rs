@@ -0,0 +1,327 @@
+/// Waiting on pane output: `wait_for` (a regex showing up) and `exec_in_pane`
+/// (run a command between markers and collect its output and exit code)
+use crate::logs::AnsiStripper;
+use crate::{pipe, tmux};
+use regex::Regex;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+pub const MAX_EXEC_LINES: usize = 10_000;
+// Marker lines rely on `$?`, so only POSIX-style shells are accepted
+const SHELLS: &[&str] = &["bash", "zsh", "sh", "dash", "ksh", "mksh", "ash"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WaitMatch {
+    pub line: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    pub elapsed_ms: u64,
+}
+
+pub async fn capture(target: &str) -> Result<String, String> {
+    let t = target.to_string();
+    tokio::task::spawn_blocking(move || tmux::capture_pane_plain(&t, None))
+        .await
+        .map_err(|e| format!("task panic: {}", e))?
+}
+
+/// Build the match result for line `idx` of `lines`
+fn found(lines: &[&str], idx: usize, context: usize, started: Instant) -> WaitMatch {
+    let owned = |s: &[&str]| s.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+    WaitMatch {
+        line: lines[idx].to_string(),
+        before: owned(&lines[idx.saturating_sub(context)..idx]),
+        after: owned(&lines[idx + 1..(idx + 1 + context).min(lines.len())]),
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+/// A pane's raw output turned into plain lines as it arrives. Only the
+/// lines still needed for context are kept; the last one may be unfinished.
+struct Stream {
+    lines: Vec<String>,
+    pending: Vec<u8>,
+    stripper: AnsiStripper,
+    context: usize,
+}
+
+impl Stream {
+    /// `last` is the line output continues on, e.g. a half-printed prompt
+    fn new(context: usize, last: &str) -> Self {
+        Self { lines: vec![last.to_string()], pending: Vec::new(), stripper: AnsiStripper::default(), context }
+    }
+
+    /// Add a chunk of output; returns the index in `lines()` of the first
+    /// line it completed or extended that matches `re`
+    fn push(&mut self, data: &[u8], re: &Regex) -> Option<usize> {
+        self.pending.extend_from_slice(data);
+        let text = self.stripper.push(&pipe::take_utf8(&mut self.pending));
+        let mut parts = text.split('\n');
+        // The unfinished line is checked again if anything was added to it
+        let total = self.lines.len();
+        let extended = parts.next().unwrap_or_default();
+        self.lines[total - 1].push_str(extended);
+        self.lines.extend(parts.map(str::to_string));
+        let new = self.lines.len() - total + usize::from(!extended.is_empty());
+        let hit = self.lines[self.lines.len() - new..].iter().position(|l| re.is_match(l)).map(|i| self.lines.len() - new + i);
+        if hit.is_none() {
+            let keep = self.context + 1;
+            self.lines.drain(..self.lines.len().saturating_sub(keep));
+        }
+        hit
+    }
+
+    /// Lines so far, without an empty unfinished one
+    fn lines(&self) -> Vec<&str> {
+        let end = self.lines.len() - usize::from(self.lines.last().is_some_and(|l| l.is_empty()));
+        self.lines[..end].iter().map(String::as_str).collect()
+    }
+}
+
+/// Holds a pane's output tap for as long as a wait runs
+struct Tap(String);
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        pipe::release(&self.0);
+    }
+}
+
+/// Watch `target`'s output stream until `re` matches a line of new output.
+/// With `since_now` unset, the current pane content is checked first.
+/// `Ok(None)` on timeout.
+pub async fn wait_for(
+    target: &str,
+    re: &Regex,
+    timeout: Duration,
+    context: usize,
+    since_now: bool,
+) -> Result<Option<WaitMatch>, String> {
+    let started = Instant::now();
+    let deadline = started + timeout.min(MAX_TIMEOUT);
+    // Subscribed before the capture, so nothing falls between the two
+    let mut rx = pipe::subscribe(target)?;
+    let _tap = Tap(target.to_string());
+    let content = capture(target).await?;
+    let lines: Vec<&str> = content.lines().collect();
+    if !since_now {
+        if let Some(idx) = lines.iter().rposition(|l| re.is_match(l)) {
+            return Ok(Some(found(&lines, idx, context, started)));
+        }
+    }
+    let last = lines.iter().rev().find(|l| !l.trim().is_empty()).copied().unwrap_or_default();
+    let mut stream = Stream::new(context, last);
+    loop {
+        let chunk = match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Err(_) => return Ok(None),
+            Ok(Ok(c)) => c,
+            // Output was dropped; start over from what comes next
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => {
+                stream = Stream::new(context, "");
+                continue;
+            }
+            Ok(Err(broadcast::error::RecvError::Closed)) => return Err(format!("output of {} is no longer available", target)),
+        };
+        if let Some(idx) = stream.push(&chunk.data, re) {
+            return Ok(Some(found(&stream.lines(), idx, context, started)));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecResult {
+    pub output: String,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// The start marker scrolled out of the captured history
+    pub truncated: bool,
+}
+
+pub enum ExecError {
+    Invalid(String),
+    Failed(String),
+    /// Carries whatever output had appeared so far
+    Timeout(ExecResult),
+}
+
+/// Output lines between the start and end markers in `content`
+fn extract(content: &str, begin: &str, end: &str) -> (Vec<String>, Option<i32>, bool) {
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.iter().rposition(|l| l.trim_end() == begin);
+    let from = start.map(|i| i + 1).unwrap_or(0);
+    let end_at = lines[from..].iter().position(|l| l.starts_with(end)).map(|i| from + i);
+    let code = end_at.and_then(|i| lines[i][end.len()..].trim().parse().ok());
+    let mut out: Vec<String> = lines[from..end_at.unwrap_or(lines.len())].iter().map(|l| l.to_string()).collect();
+    if end_at.is_none() {
+        while out.last().is_some_and(|l| l.trim().is_empty()) {
+            out.pop();
+        }
+    }
+    (out, code, start.is_none())
+}
+
+/// The line typed into the shell for `exec_in_pane`. The command sits on its
+/// own line inside a group, so a trailing `&` or a `#` comment can't swallow
+/// the end marker; quotes split the markers so the echoed command line never
+/// matches them.
+fn wrap(id: &str, command: &str) -> String {
+    format!("echo __TMX\"\"_B_{id}; {{\n{command}\n}}; echo __TMX\"\"_E_{id}:$?", id = id, command = command.trim_end())
+}
+
+/// Run `command` in the shell in `target`, wrapped in unique start/end
+/// markers, and wait for the end marker. With `interrupt`, Ctrl-C is sent
+/// to the pane on timeout.
+pub async fn exec_in_pane(
+    target: &str,
+    command: &str,
+    timeout: Duration,
+    max_lines: usize,
+    interrupt: bool,
+) -> Result<ExecResult, ExecError> {
+    if command.contains('\n') {
+        return Err(ExecError::Invalid("command must be a single line".into()));
+    }
+    if command.trim_end().ends_with('\\') {
+        return Err(ExecError::Invalid("command must not end with a line continuation".into()));
+    }
+    let t = target.to_string();
+    let current = tokio::task::spawn_blocking(move || tmux::pane_command(&t))
+        .await
+        .map_err(|e| ExecError::Failed(format!("task panic: {}", e)))?
+        .map_err(ExecError::Failed)?;
+    if !SHELLS.contains(&current.trim_start_matches('-')) {
+        return Err(ExecError::Invalid(format!("pane is running {}, not a shell", current)));
+    }
+
+    let id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+    let begin = format!("__TMX_B_{}", id);
+    let end = format!("__TMX_E_{}:", id);
+    let wrapped = wrap(&id, command);
+    let started = Instant::now();
+    let deadline = started + timeout.min(MAX_TIMEOUT);
+    let t = target.to_string();
+    tokio::task::spawn_blocking(move || tmux::send_command(&t, &wrapped))
+        .await
+        .map_err(|e| ExecError::Failed(format!("task panic: {}", e)))?
+        .map_err(ExecError::Failed)?;
+
+    loop {
+        let now = Instant::now();
+        let finished = |content: &str| content.lines().any(|l| l.starts_with(end.as_str()));
+        if now >= deadline {
+            let content = capture_lines(target, max_lines).await.map_err(ExecError::Failed)?;
+            let (out, _, truncated) = extract(&content, &begin, &end);
+            if interrupt {
+                let t = target.to_string();
+                let _ = tokio::task::spawn_blocking(move || tmux::send_keys(&t, "C-c", false)).await;
+            }
+            return Err(ExecError::Timeout(ExecResult {
+                output: out.join("\n"),
+                exit_code: None,
+                duration_ms: started.elapsed().as_millis() as u64,
+                truncated,
+            }));
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+        if !finished(&capture(target).await.map_err(ExecError::Failed)?) {
+            continue;
+        }
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let content = capture_lines(target, max_lines).await.map_err(ExecError::Failed)?;
+        let (out, exit_code, truncated) = extract(&content, &begin, &end);
+        return Ok(ExecResult { output: out.join("\n"), exit_code, duration_ms, truncated });
+    }
+}
+
+async fn capture_lines(target: &str, lines: usize) -> Result<String, String> {
+    let t = target.to_string();
+    let lines = lines.min(MAX_EXEC_LINES);
+    tokio::task::spawn_blocking(move || tmux::capture_pane_plain(&t, Some(lines)))
+        .await
+        .map_err(|e| format!("task panic: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const B: &str = "__TMX_B_abc";
+    const E: &str = "__TMX_E_abc:";
+
+    #[test]
+    fn extract_output() {
+        let cases: &[(&str, &[&str], Option<i32>, bool)] = &[
+            // Echoed input lines come before the start marker
+            (
+                "$ echo __TMX\"\"_B_abc; {\n> ls\n> }; echo __TMX\"\"_E_abc:$?\n__TMX_B_abc\na\nb\n__TMX_E_abc:0\n$",
+                &["a", "b"],
+                Some(0),
+                false,
+            ),
+            ("__TMX_B_abc\n__TMX_E_abc:127\n", &[], Some(127), false),
+            ("__TMX_B_abc\nerr\n__TMX_E_abc: 2 \n", &["err"], Some(2), false),
+            // Still running: trailing blank lines are dropped
+            ("__TMX_B_abc\npartial\n\n\n", &["partial"], None, false),
+            // Start marker scrolled out of the capture
+            ("tail\n__TMX_E_abc:1\n", &["tail"], Some(1), true),
+            // An earlier run's markers are ignored
+            ("__TMX_B_abc\nold\n__TMX_E_abc:1\n__TMX_B_abc\nnew\n__TMX_E_abc:0", &["new"], Some(0), false),
+        ];
+        for (content, out, code, truncated) in cases {
+            let (o, c, t) = extract(content, B, E);
+            assert_eq!(o, *out, "output for {:?}", content);
+            assert_eq!(c, *code, "exit code for {:?}", content);
+            assert_eq!(t, *truncated, "truncated for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn wrap_keeps_markers_intact() {
+        for command in ["sleep 1 &", "echo hi # note", "true;", "ls  "] {
+            let w = wrap("abc", command);
+            assert!(!w.contains(B) && !w.contains(E), "marker typed literally in {:?}", w);
+            let lines: Vec<&str> = w.lines().collect();
+            assert_eq!(lines.len(), 3, "{:?}", w);
+            assert_eq!(lines[1], command.trim_end(), "command on its own line");
+            assert!(lines[2].starts_with("}; echo"), "{:?}", w);
+        }
+    }
+
+    #[test]
+    fn stream_maps_matches_to_lines() {
+        let re = Regex::new(r"^(ERROR|done)").unwrap();
+        let mut stream = Stream::new(2, "");
+        // Nothing complete matches yet; old lines are let go
+        assert_eq!(stream.push(b"one\ntwo\nthree\nfour\nERR", &re), None);
+        assert_eq!(stream.lines(), ["three", "four", "ERR"]);
+        // The unfinished line is checked again once it grows, colours and all
+        let idx = stream.push(b"\x1b[31mOR\x1b[0m: disk full\nfive\nsix\nseven\n", &re).unwrap();
+        let m = found(&stream.lines(), idx, 2, Instant::now());
+        assert_eq!(m.line, "ERROR: disk full");
+        assert_eq!((m.before, m.after), (vec!["three".to_string(), "four".into()], vec!["five".to_string(), "six".into()]));
+
+        // A burst of many lines in one chunk: the first match among them wins
+        let mut stream = Stream::new(1, "");
+        let burst: String = (0..500).map(|i| if i == 321 { "done 321\n".to_string() } else { format!("line {}\n", i) }).collect();
+        let idx = stream.push(burst.as_bytes(), &re).unwrap();
+        let m = found(&stream.lines(), idx, 1, Instant::now());
+        assert_eq!((m.line.as_str(), m.before, m.after), ("done 321", vec!["line 320".to_string()], vec!["line 322".to_string()]));
+
+        // Output carries on from the line the pane was on
+        let mut stream = Stream::new(0, "Pass");
+        assert_eq!(stream.push(b"word: ", &Regex::new("^Password: $").unwrap()), Some(0));
+        // but an untouched one isn't new output
+        let mut stream = Stream::new(0, "$ ");
+        assert_eq!(stream.push(b"\nok\n", &Regex::new(r"^\$ $").unwrap()), None);
+
+        // A match at the very end has no following lines, not an empty one
+        let mut stream = Stream::new(3, "");
+        let idx = stream.push("é\ndone\n".as_bytes(), &re).unwrap();
+        let m = found(&stream.lines(), idx, 3, Instant::now());
+        assert_eq!((m.before, m.after), (vec!["é".to_string()], Vec::<String>::new()));
+    }
+}
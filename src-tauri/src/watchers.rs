@@ -0,0 +1,309 @@
+/// Server-wide pane watchers: observe panes regardless of connected clients,
+/// keep their latest state and an event log for "while you were away" summaries
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_EVENTS: usize = 1000;
+const TAIL_LINES: usize = 10;
+
+fn default_idle_secs() -> u64 { 10 }
+
+/// Watcher definition, as written in `config.toml` or passed to `add_watch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchSpec {
+    pub target: String,
+    /// Seconds without output before the pane is reported idle
+    #[serde(default = "default_idle_secs")]
+    pub idle_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchState {
+    #[serde(flatten)]
+    pub spec: WatchSpec,
+    pub source: &'static str, // "config" or "runtime"
+    pub alive: bool,
+    pub active: bool,
+    pub command: String,
+    pub last_activity: u64, // unix ms
+    pub changes: u64,
+    pub tail: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchEvent {
+    pub seq: u64,
+    pub time: u64, // unix ms
+    pub target: String,
+    pub kind: &'static str, // "active", "idle", "command", "gone", "back", "trigger"
+    pub detail: String,
+}
+
+struct Watcher {
+    spec: WatchSpec,
+    source: &'static str,
+    content: Option<String>,
+    alive: bool,
+    active: bool,
+    command: String,
+    last_activity: u64,
+    changes: u64,
+}
+
+impl Watcher {
+    fn new(spec: WatchSpec, source: &'static str) -> Self {
+        Self {
+            spec, source, content: None, alive: true, active: false,
+            command: String::new(), last_activity: 0, changes: 0,
+        }
+    }
+
+    fn state(&self) -> WatchState {
+        let tail = self.content.as_deref().map(last_lines).unwrap_or_default();
+        WatchState {
+            spec: self.spec.clone(),
+            source: self.source,
+            alive: self.alive,
+            active: self.active,
+            command: self.command.clone(),
+            last_activity: self.last_activity,
+            changes: self.changes,
+            tail,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    watchers: Vec<Watcher>,
+    events: VecDeque<WatchEvent>,
+    next_seq: u64,
+}
+
+impl Registry {
+    fn push(&mut self, target: &str, kind: &'static str, detail: String) -> WatchEvent {
+        self.next_seq += 1;
+        let ev = WatchEvent { seq: self.next_seq, time: now_ms(), target: target.to_string(), kind, detail };
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(ev.clone());
+        ev
+    }
+}
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+fn registry() -> &'static Mutex<Registry> {
+    REGISTRY.get_or_init(|| Mutex::new(Registry::default()))
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn last_lines(content: &str) -> Vec<String> {
+    let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(TAIL_LINES)..].iter().map(|l| l.to_string()).collect()
+}
+
+/// Load watchers from config, replacing previously configured ones
+pub fn init(specs: Vec<WatchSpec>) {
+    let mut reg = registry().lock().unwrap();
+    reg.watchers.retain(|w| w.source != "config");
+    for spec in specs {
+        if spec.target.is_empty() || reg.watchers.iter().any(|w| w.spec.target == spec.target) {
+            eprintln!("⚠️  Skipping empty or duplicate watcher: {:?}", spec.target);
+            continue;
+        }
+        reg.watchers.push(Watcher::new(spec, "config"));
+    }
+}
+
+pub fn add(spec: WatchSpec) -> Result<WatchState, String> {
+    if spec.target.is_empty() {
+        return Err("watch target must not be empty".into());
+    }
+    let mut reg = registry().lock().unwrap();
+    if reg.watchers.iter().any(|w| w.spec.target == spec.target) {
+        return Err(format!("already watching: {}", spec.target));
+    }
+    let w = Watcher::new(spec, "runtime");
+    let state = w.state();
+    reg.watchers.push(w);
+    Ok(state)
+}
+
+pub fn remove(target: &str) -> Result<(), String> {
+    let mut reg = registry().lock().unwrap();
+    let before = reg.watchers.len();
+    reg.watchers.retain(|w| w.spec.target != target);
+    if reg.watchers.len() == before {
+        return Err(format!("not watching: {}", target));
+    }
+    Ok(())
+}
+
+pub fn list() -> Vec<WatchState> {
+    registry().lock().unwrap().watchers.iter().map(|w| w.state()).collect()
+}
+
+pub fn targets() -> Vec<String> {
+    registry().lock().unwrap().watchers.iter().map(|w| w.spec.target.clone()).collect()
+}
+
+/// Record an event that originates elsewhere (e.g. a trigger firing)
+pub fn record(target: &str, kind: &'static str, detail: String) -> WatchEvent {
+    registry().lock().unwrap().push(target, kind, detail)
+}
+
+/// Feed the result of one poll. `capture` is the ANSI-stripped pane content,
+/// or an error if the pane no longer exists. Returns newly generated events.
+pub fn observe(target: &str, capture: Result<&str, &str>, command: Option<&str>) -> Vec<WatchEvent> {
+    let mut reg = registry().lock().unwrap();
+    let now = now_ms();
+    let mut pending: Vec<(&'static str, String)> = Vec::new();
+    {
+        let Some(w) = reg.watchers.iter_mut().find(|w| w.spec.target == target) else {
+            return Vec::new();
+        };
+        match capture {
+            Err(e) => {
+                if w.alive {
+                    w.alive = false;
+                    w.active = false;
+                    pending.push(("gone", e.trim().to_string()));
+                }
+            }
+            Ok(content) => {
+                if !w.alive {
+                    w.alive = true;
+                    pending.push(("back", String::new()));
+                }
+                if let Some(cmd) = command.filter(|c| *c != w.command) {
+                    if !w.command.is_empty() {
+                        pending.push(("command", format!("{} → {}", w.command, cmd)));
+                    }
+                    w.command = cmd.to_string();
+                }
+                match w.content.as_deref() {
+                    // First poll only sets the baseline
+                    None => w.last_activity = now,
+                    Some(prev) if prev != content => {
+                        w.changes += 1;
+                        w.last_activity = now;
+                        if !w.active {
+                            w.active = true;
+                            let first = crate::tmux::new_lines(prev, content).into_iter().next().unwrap_or_default();
+                            pending.push(("active", first));
+                        }
+                    }
+                    Some(_) => {
+                        if w.active && now.saturating_sub(w.last_activity) >= w.spec.idle_secs * 1000 {
+                            w.active = false;
+                            pending.push(("idle", last_lines(content).pop().unwrap_or_default()));
+                        }
+                    }
+                }
+                w.content = Some(content.to_string());
+            }
+        }
+    }
+    pending.into_iter().map(|(kind, detail)| reg.push(target, kind, detail)).collect()
+}
+
+/// State of every watcher plus the events after `since` (unix ms).
+/// `truncated` is set when older events were dropped from the log.
+pub fn summary(since: u64) -> serde_json::Value {
+    let reg = registry().lock().unwrap();
+    let events: Vec<&WatchEvent> = reg.events.iter().filter(|e| e.time > since).collect();
+    let truncated = reg.events.front().is_some_and(|e| e.time > since && e.seq > 1);
+    serde_json::json!({
+        "now": now_ms(),
+        "since": since,
+        "watchers": reg.watchers.iter().map(|w| w.state()).collect::<Vec<_>>(),
+        "events": events,
+        "truncated": truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(idle_secs: u64) -> String {
+        let target = format!("test-{}:0.0", uuid::Uuid::new_v4());
+        add(WatchSpec { target: target.clone(), idle_secs }).unwrap();
+        target
+    }
+
+    fn kinds(events: &[WatchEvent]) -> Vec<(&'static str, &str)> {
+        events.iter().map(|e| (e.kind, e.detail.as_str())).collect()
+    }
+
+    #[test]
+    fn observe_walks_the_state_machine() {
+        // Idle as soon as a poll sees no change
+        let t = watch(0);
+        type Case = (Result<&'static str, &'static str>, Option<&'static str>, &'static [(&'static str, &'static str)]);
+        let cases: &[Case] = &[
+            (Ok("$ make"), Some("bash"), &[]),
+            (Ok("$ make\ncc main.c"), Some("make"), &[("command", "bash → make"), ("active", "cc main.c")]),
+            (Ok("$ make\ncc main.c\ncc util.c"), Some("make"), &[]),
+            (Ok("$ make\ncc main.c\ncc util.c"), Some("make"), &[("idle", "cc util.c")]),
+            (Ok("$ make\ncc main.c\ncc util.c"), None, &[]),
+            (Err("can't find pane\n"), None, &[("gone", "can't find pane")]),
+            (Err("can't find pane"), None, &[]),
+            (Ok("$ "), Some("bash"), &[("back", ""), ("command", "make → bash"), ("active", "$ ")]),
+        ];
+        for (i, (capture, command, expected)) in cases.iter().enumerate() {
+            let events = observe(&t, *capture, *command);
+            assert_eq!(kinds(&events), *expected, "poll {}", i);
+            assert!(events.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+        }
+        let state = list().into_iter().find(|w| w.spec.target == t).unwrap();
+        assert_eq!((state.alive, state.active, state.command.as_str(), state.changes), (true, true, "bash", 3));
+        assert_eq!(state.tail, vec!["$ "]);
+        assert!(observe("test-unwatched", Ok("x"), None).is_empty());
+        remove(&t).unwrap();
+    }
+
+    #[test]
+    fn stays_active_within_idle_secs() {
+        let t = watch(60);
+        observe(&t, Ok("a"), None);
+        assert_eq!(kinds(&observe(&t, Ok("a\nb"), None)), [("active", "b")]);
+        assert!(observe(&t, Ok("a\nb"), None).is_empty());
+        assert!(list().into_iter().find(|w| w.spec.target == t).unwrap().active);
+        remove(&t).unwrap();
+        assert!(remove(&t).is_err());
+    }
+
+    #[test]
+    fn summary_reports_events_since_and_truncation() {
+        let t = watch(10);
+        let before = now_ms() - 1;
+        let ev = record(&t, "trigger", "build: error".into());
+        let ours = |v: &serde_json::Value| -> Vec<u64> {
+            v["events"].as_array().unwrap().iter()
+                .filter(|e| e["target"] == t.as_str())
+                .map(|e| e["seq"].as_u64().unwrap())
+                .collect()
+        };
+        let s = summary(before);
+        assert_eq!(ours(&s), vec![ev.seq]);
+        assert!(s["watchers"].as_array().unwrap().iter().any(|w| w["target"] == t.as_str()));
+        assert!(ours(&summary(ev.time)).is_empty(), "strictly after since");
+
+        // Once the log overflows, a summary reaching back past it says so
+        for i in 0..MAX_EVENTS {
+            record(&t, "trigger", i.to_string());
+        }
+        assert!(summary(0)["truncated"].as_bool().unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(!summary(now_ms())["truncated"].as_bool().unwrap());
+        remove(&t).unwrap();
+    }
+}